@@ -1,5 +1,10 @@
-use libc::c_char;
+use libc::{c_char, c_int, c_uint};
 use std::ffi::c_void;
+use std::ptr::addr_of_mut;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(test)]
+pub mod stubs;
 
 #[repr(C)]
 pub struct Object {
@@ -18,14 +23,129 @@ pub struct ObjectClass {
 #[repr(C)]
 pub struct DeviceState {
     pub base: Object,
+    pub id: *mut c_char,
+    pub canonical_path: *mut c_char,
+    pub realized: bool,
+    pub pending_deleted_event: bool,
+    pub pending_deleted_expires_ms: i64,
+    pub opts: *mut QDict,
+    pub hotplugged: c_int,
+    pub allow_unplug_during_migration: bool,
+    pub parent_bus: *mut BusState,
+    // ...
+}
+
+#[repr(C)]
+pub struct BusState {
+    pub obj: Object,
+    // ...
+}
+
+#[repr(C)]
+pub struct ObjectProperty {
+    pub name: *mut c_char,
+    pub r#type: *mut c_char,
+    pub description: *mut c_char,
+    pub get: Option<ObjectPropertyAccessor>,
+    pub set: Option<ObjectPropertyAccessor>,
+    pub resolve: Option<ObjectPropertyResolve>,
+    pub release: Option<ObjectPropertyRelease>,
+    pub init: Option<ObjectPropertyInit>,
+    pub opaque: *mut c_void,
+    /// The default value, set by `set_default_value` for qdev properties
+    pub defval: *mut QObject,
 }
 
+#[repr(C)]
+pub struct Visitor {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct QmpCommandList {
+    _unused: c_char,
+}
+
+pub type QmpCommandFunc =
+    unsafe extern "C" fn(args: *mut QDict, ret: *mut *mut QObject, errp: *mut *mut Error);
+
+pub type QmpCommandOptions = c_uint;
+pub const QCO_NO_OPTIONS: QmpCommandOptions = 0;
+
+#[repr(C)]
+pub struct QmpCommand {
+    pub name: *const c_char,
+    pub fn_: QmpCommandFunc,
+    pub options: QmpCommandOptions,
+    pub special_features: c_uint,
+    pub node: QTailQLink,
+    pub enabled: bool,
+    pub disable_reason: *const c_char,
+}
+
+#[repr(C)]
+pub struct VersionTriple {
+    pub major: i64,
+    pub minor: i64,
+    pub micro: i64,
+}
+
+#[repr(C)]
+pub struct VersionInfo {
+    pub qemu: *mut VersionTriple,
+    pub package: *mut c_char,
+}
+
+#[repr(C)]
+pub struct Monitor {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct GenericList {
+    pub next: *mut GenericList,
+}
+
+pub type ObjectPropertyAccessor = unsafe extern "C" fn(
+    obj: *mut Object,
+    v: *mut Visitor,
+    name: *const c_char,
+    opaque: *mut c_void,
+    errp: *mut *mut Error,
+);
+pub type ObjectPropertyRelease =
+    unsafe extern "C" fn(obj: *mut Object, name: *const c_char, opaque: *mut c_void);
+pub type ObjectPropertyResolve =
+    unsafe extern "C" fn(obj: *mut Object, opaque: *mut c_void, part: *const c_char) -> *mut Object;
+pub type ObjectPropertyInit = unsafe extern "C" fn(obj: *mut Object, prop: *mut ObjectProperty);
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 pub struct PropertyInfo {
     pub name: *const c_char,
     pub description: *const c_char,
-    // ...
+    pub enum_table: *const c_void,
+    pub realized_set_allowed: bool,
+    pub print: Option<
+        unsafe extern "C" fn(
+            obj: *mut Object,
+            prop: *const Property,
+            dest: *mut c_char,
+            len: usize,
+        ) -> c_int,
+    >,
+    pub set_default_value:
+        Option<unsafe extern "C" fn(op: *mut ObjectProperty, prop: *const Property)>,
+    pub create: Option<
+        unsafe extern "C" fn(
+            oc: *mut ObjectClass,
+            name: *const c_char,
+            prop: *const Property,
+        ) -> *mut ObjectProperty,
+    >,
+    pub get: Option<ObjectPropertyAccessor>,
+    pub set: Option<ObjectPropertyAccessor>,
+    pub release: Option<ObjectPropertyRelease>,
 }
 #[repr(C)]
 pub struct Property {
@@ -33,15 +153,250 @@ pub struct Property {
     pub offset: usize,
     pub default: u64,
     pub info: *const PropertyInfo,
+    /// If true, `default` is applied by `info->set_default_value`
+    pub set_default: bool,
+    /// If not NULL, the help text for `-device help`.  C does not read
+    /// it; `DeviceClass::class_init` sets it as the description of the
+    /// class property.
+    pub description: *const c_char,
+    /// For link properties, the QOM type of the target
+    pub link_type: *const c_char,
+    /// For bit properties, the bit number within the field
+    pub bitnr: u8,
+    /// If true, the property cannot be set after the device is realized
+    pub frozen: bool,
+    /// For link properties, if true the device cannot be realized
+    /// until the link is set
+    pub required: bool,
+    /// If not `None`, called after the property is set successfully
+    pub notify: Option<unsafe extern "C" fn(*mut Object)>,
+    /// If not `None`, the property can only be set if it returns true
+    pub settable: Option<unsafe extern "C" fn(*mut Object) -> bool>,
 }
 
+#[repr(C)]
 pub struct DeviceClass {
     pub oc: ObjectClass,
 
     pub realize: Option<unsafe extern "C" fn(*mut DeviceState, *mut *mut Error)>,
     pub unrealize: Option<unsafe extern "C" fn(*mut DeviceState)>,
     pub cold_reset: Option<unsafe extern "C" fn(*mut DeviceState)>,
+    /// The `hold` phase of the `ResettableClass`
+    pub reset_hold: Option<unsafe extern "C" fn(*mut Object, ResetType)>,
+    /// If false, `device_add` refuses to create the device after startup
+    pub hotpluggable: bool,
     pub properties: *const Property,
+    pub vmsd: *const VMStateDescription,
+}
+
+#[repr(C)]
+pub struct VMStateInfo {
+    _unused: c_char,
+}
+
+pub type VMStateFlags = c_uint;
+pub const VMS_SINGLE: VMStateFlags = 0x001;
+pub const VMS_END: VMStateFlags = 0x10000;
+
+#[repr(C)]
+pub struct VMStateField {
+    pub name: *const c_char,
+    pub err_hint: *const c_char,
+    pub offset: usize,
+    pub size: usize,
+    pub start: usize,
+    pub num: c_int,
+    pub num_offset: usize,
+    pub size_offset: usize,
+    pub info: *const VMStateInfo,
+    pub flags: VMStateFlags,
+    pub vmsd: *const VMStateDescription,
+    pub version_id: c_int,
+    pub struct_version_id: c_int,
+    pub field_exists: Option<unsafe extern "C" fn(opaque: *mut c_void, version_id: c_int) -> bool>,
+}
+
+pub type MigrationPriority = c_uint;
+pub const MIG_PRI_DEFAULT: MigrationPriority = 0;
+
+#[repr(C)]
+pub struct VMStateDescription {
+    pub name: *const c_char,
+    pub unmigratable: bool,
+    pub early_setup: bool,
+    pub version_id: c_int,
+    pub minimum_version_id: c_int,
+    pub priority: MigrationPriority,
+    pub pre_load: Option<unsafe extern "C" fn(opaque: *mut c_void) -> c_int>,
+    pub post_load: Option<unsafe extern "C" fn(opaque: *mut c_void, version_id: c_int) -> c_int>,
+    pub pre_save: Option<unsafe extern "C" fn(opaque: *mut c_void) -> c_int>,
+    pub post_save: Option<unsafe extern "C" fn(opaque: *mut c_void) -> c_int>,
+    pub needed: Option<unsafe extern "C" fn(opaque: *mut c_void) -> bool>,
+    pub dev_unplug_pending: Option<unsafe extern "C" fn(opaque: *mut c_void) -> bool>,
+    pub fields: *const VMStateField,
+    pub subsections: *const *const VMStateDescription,
+}
+
+#[repr(C)]
+pub struct IRQState {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct MemoryRegion {
+    pub parent_obj: Object,
+    // ...
+}
+
+#[allow(non_camel_case_types)]
+pub type qemu_irq_handler =
+    Option<unsafe extern "C" fn(opaque: *mut c_void, n: c_int, level: c_int)>;
+
+pub type ResetType = c_uint;
+pub const RESET_TYPE_COLD: ResetType = 0;
+pub const RESET_TYPE_SNAPSHOT_LOAD: ResetType = 1;
+pub const RESET_TYPE_WAKEUP: ResetType = 2;
+
+pub type RunState = c_uint;
+pub const RUN_STATE_DEBUG: RunState = 0;
+pub const RUN_STATE_INMIGRATE: RunState = 1;
+pub const RUN_STATE_INTERNAL_ERROR: RunState = 2;
+pub const RUN_STATE_IO_ERROR: RunState = 3;
+pub const RUN_STATE_PAUSED: RunState = 4;
+pub const RUN_STATE_POSTMIGRATE: RunState = 5;
+pub const RUN_STATE_PRELAUNCH: RunState = 6;
+pub const RUN_STATE_FINISH_MIGRATE: RunState = 7;
+pub const RUN_STATE_RESTORE_VM: RunState = 8;
+pub const RUN_STATE_RUNNING: RunState = 9;
+pub const RUN_STATE_SAVE_VM: RunState = 10;
+pub const RUN_STATE_SHUTDOWN: RunState = 11;
+pub const RUN_STATE_SUSPENDED: RunState = 12;
+pub const RUN_STATE_WATCHDOG: RunState = 13;
+pub const RUN_STATE_GUEST_PANICKED: RunState = 14;
+pub const RUN_STATE_COLO: RunState = 15;
+
+pub type VMChangeStateHandler =
+    Option<unsafe extern "C" fn(opaque: *mut c_void, running: bool, state: RunState)>;
+
+#[repr(C)]
+pub struct VMChangeStateEntry {
+    _unused: c_char,
+}
+
+#[allow(non_camel_case_types)]
+pub type device_endian = c_int;
+pub const DEVICE_NATIVE_ENDIAN: device_endian = 0;
+pub const DEVICE_BIG_ENDIAN: device_endian = 1;
+pub const DEVICE_LITTLE_ENDIAN: device_endian = 2;
+
+pub type MemTxResult = u32;
+pub const MEMTX_OK: MemTxResult = 0;
+
+/// The C struct is made of bitfields that fit in 32 bits
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MemTxAttrs {
+    _bits: u32,
+}
+
+// The "unspecified" bitfield comes first
+#[cfg(target_endian = "little")]
+pub const MEMTXATTRS_UNSPECIFIED: MemTxAttrs = MemTxAttrs { _bits: 1 };
+#[cfg(target_endian = "big")]
+pub const MEMTXATTRS_UNSPECIFIED: MemTxAttrs = MemTxAttrs { _bits: 1 << 31 };
+
+#[repr(C)]
+pub struct AddressSpace {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct MemoryRegionOpsValid {
+    pub min_access_size: c_uint,
+    pub max_access_size: c_uint,
+    pub unaligned: bool,
+    pub accepts: Option<
+        unsafe extern "C" fn(
+            opaque: *mut c_void,
+            addr: u64,
+            size: c_uint,
+            is_write: bool,
+            attrs: MemTxAttrs,
+        ) -> bool,
+    >,
+}
+
+#[repr(C)]
+pub struct MemoryRegionOpsImpl {
+    pub min_access_size: c_uint,
+    pub max_access_size: c_uint,
+    pub unaligned: bool,
+}
+
+#[repr(C)]
+pub struct MemoryRegionOps {
+    pub read: Option<unsafe extern "C" fn(opaque: *mut c_void, addr: u64, size: c_uint) -> u64>,
+    pub write:
+        Option<unsafe extern "C" fn(opaque: *mut c_void, addr: u64, data: u64, size: c_uint)>,
+    pub read_with_attrs: Option<
+        unsafe extern "C" fn(
+            opaque: *mut c_void,
+            addr: u64,
+            data: *mut u64,
+            size: c_uint,
+            attrs: MemTxAttrs,
+        ) -> MemTxResult,
+    >,
+    pub write_with_attrs: Option<
+        unsafe extern "C" fn(
+            opaque: *mut c_void,
+            addr: u64,
+            data: u64,
+            size: c_uint,
+            attrs: MemTxAttrs,
+        ) -> MemTxResult,
+    >,
+    pub endianness: device_endian,
+    pub valid: MemoryRegionOpsValid,
+    pub r#impl: MemoryRegionOpsImpl,
+}
+
+#[repr(C)]
+pub struct SysBusDevice {
+    pub parent_obj: DeviceState,
+    // ...
+}
+
+#[repr(C)]
+pub struct SysBusDeviceClass {
+    pub parent_class: DeviceClass,
+    // ...
+}
+
+crate::with_offsets! {
+    #[repr(C)]
+    pub struct VirtIODevice {
+        pub parent_obj: DeviceState,
+        pub host_features: u64,
+        pub guest_features: u64,
+        pub config_len: usize,
+        // ...
+    }
+}
+
+#[repr(C)]
+pub struct VirtioDeviceClass {
+    pub parent: DeviceClass,
+
+    pub realize: Option<unsafe extern "C" fn(*mut DeviceState, *mut *mut Error)>,
+    pub unrealize: Option<unsafe extern "C" fn(*mut DeviceState)>,
+    pub get_features: Option<
+        unsafe extern "C" fn(vdev: *mut VirtIODevice, features: u64, errp: *mut *mut Error) -> u64,
+    >,
+    pub set_features: Option<unsafe extern "C" fn(vdev: *mut VirtIODevice, val: u64)>,
+    pub get_config: Option<unsafe extern "C" fn(vdev: *mut VirtIODevice, config: *mut u8)>,
+    pub set_config: Option<unsafe extern "C" fn(vdev: *mut VirtIODevice, config: *const u8)>,
+    // ...
 }
 
 #[repr(C)]
@@ -50,9 +405,109 @@ pub struct TypeInfo {
     pub parent: *const c_char,
     pub instance_mem_init: Option<unsafe extern "C" fn(*mut c_void)>,
     pub instance_init: Option<unsafe extern "C" fn(*mut c_void)>,
+    pub instance_post_init: Option<unsafe extern "C" fn(*mut c_void)>,
     pub instance_finalize: Option<unsafe extern "C" fn(*mut c_void)>,
     pub class_init: Option<unsafe extern "C" fn(*mut c_void, *mut c_void)>,
     pub instance_size: usize,
+    pub instance_align: usize,
+    /// Interfaces implemented by the type, terminated by an entry whose
+    /// `type_` is NULL
+    pub interfaces: *const InterfaceInfo,
+}
+
+#[repr(C)]
+pub struct InterfaceInfo {
+    pub type_: *const c_char,
+}
+
+pub type QType = c_int;
+pub const QTYPE_QNULL: QType = 1;
+pub const QTYPE_QNUM: QType = 2;
+pub const QTYPE_QSTRING: QType = 3;
+pub const QTYPE_QDICT: QType = 4;
+pub const QTYPE_QLIST: QType = 5;
+pub const QTYPE_QBOOL: QType = 6;
+
+#[repr(C)]
+pub struct QObjectBase_ {
+    pub r#type: QType,
+    pub refcnt: usize,
+}
+
+#[repr(C)]
+pub struct QObject {
+    pub base: QObjectBase_,
+}
+
+pub type QNumKind = c_int;
+pub const QNUM_I64: QNumKind = 0;
+pub const QNUM_U64: QNumKind = 1;
+pub const QNUM_DOUBLE: QNumKind = 2;
+
+#[repr(C)]
+pub union QNumValue {
+    pub i64: i64,
+    pub u64: u64,
+    pub dbl: f64,
+}
+
+#[repr(C)]
+pub struct QNum {
+    pub base: QObjectBase_,
+    pub kind: QNumKind,
+    pub u: QNumValue,
+}
+
+#[repr(C)]
+pub struct QBool {
+    pub base: QObjectBase_,
+    pub value: bool,
+}
+
+#[repr(C)]
+pub struct QString {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct QDict {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct QDictEntry {
+    _unused: c_char,
+}
+
+#[repr(C)]
+pub struct QNull {
+    pub base: QObjectBase_,
+}
+
+/// The head and the link fields of a `QTAILQ`; the `tqh_circ` and
+/// `tqe_circ` members of the C unions are not needed to walk the list
+/// forward.
+#[repr(C)]
+pub struct QTailQLink {
+    pub tql_next: *mut c_void,
+    pub tql_prev: *mut QTailQLink,
+}
+
+#[repr(C)]
+pub struct QList {
+    pub base: QObjectBase_,
+    pub head: QTailQLink,
+}
+
+#[repr(C)]
+pub struct QListEntry {
+    pub value: *mut QObject,
+    pub next: QTailQLink,
+}
+
+#[repr(C)]
+pub struct QemuUUID {
+    pub data: [u8; 16],
 }
 
 #[repr(C)]
@@ -69,19 +524,339 @@ extern "C" {
         fmt: *const c_char,
         ...
     );
+    pub fn error_set_internal(
+        errp: *mut *mut Error,
+        src: *mut c_char,
+        line: u32,
+        func: *mut c_char,
+        err_class: c_int,
+        fmt: *const c_char,
+        ...
+    );
     pub fn error_get_pretty(errp: *const Error) -> *mut c_char;
+    pub fn error_get_class(err: *const Error) -> c_int;
     pub fn error_free(errp: *mut Error);
+    pub fn error_propagate(dst_errp: *mut *mut Error, local_err: *mut Error);
 
     pub fn object_dynamic_cast(obj: *mut Object, typ: *const c_char) -> *mut c_void;
+    pub fn object_class_dynamic_cast(
+        klass: *mut ObjectClass,
+        typ: *const c_char,
+    ) -> *mut ObjectClass;
     pub fn object_get_typename(obj: *const Object) -> *const c_char;
+    pub fn object_class_get_parent(klass: *mut ObjectClass) -> *mut ObjectClass;
+    pub fn object_class_by_name(typename: *const c_char) -> *mut ObjectClass;
+    pub fn object_class_is_abstract(klass: *mut ObjectClass) -> bool;
+    pub fn object_type_get_instance_size(typename: *const c_char) -> usize;
     pub fn object_ref(obj: *mut Object);
     pub fn object_new(typ: *const c_char) -> *const Object;
+    pub fn object_initialize(data: *mut c_void, size: usize, typ: *const c_char);
     pub fn object_unref(obj: *mut Object);
     pub fn object_unparent(obj: *mut Object);
+    pub fn object_resolve_path_type(
+        path: *const c_char,
+        typename: *const c_char,
+        ambiguous: *mut bool,
+    ) -> *mut Object;
+    pub fn object_get_canonical_path(obj: *const Object) -> *mut c_char;
+    pub fn object_get_canonical_path_component(obj: *const Object) -> *const c_char;
+    pub fn object_property_add_child(
+        obj: *mut Object,
+        name: *const c_char,
+        child: *mut Object,
+    ) -> *mut ObjectProperty;
+    pub fn object_property_add(
+        obj: *mut Object,
+        name: *const c_char,
+        r#type: *const c_char,
+        get: Option<ObjectPropertyAccessor>,
+        set: Option<ObjectPropertyAccessor>,
+        release: Option<ObjectPropertyRelease>,
+        opaque: *mut c_void,
+    ) -> *mut ObjectProperty;
+    pub fn object_class_property_add(
+        klass: *mut ObjectClass,
+        name: *const c_char,
+        r#type: *const c_char,
+        get: Option<ObjectPropertyAccessor>,
+        set: Option<ObjectPropertyAccessor>,
+        release: Option<ObjectPropertyRelease>,
+        opaque: *mut c_void,
+    ) -> *mut ObjectProperty;
+    pub fn object_class_property_find(
+        klass: *mut ObjectClass,
+        name: *const c_char,
+    ) -> *mut ObjectProperty;
+    pub fn object_class_property_set_description(
+        klass: *mut ObjectClass,
+        name: *const c_char,
+        description: *const c_char,
+    );
+    pub fn object_property_find(obj: *mut Object, name: *const c_char) -> *mut ObjectProperty;
+    pub fn object_property_get_bool(
+        obj: *mut Object,
+        name: *const c_char,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn object_property_get_qobject(
+        obj: *mut Object,
+        name: *const c_char,
+        errp: *mut *mut Error,
+    ) -> *mut QObject;
+    pub fn object_property_set_qobject(
+        obj: *mut Object,
+        name: *const c_char,
+        value: *mut QObject,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn object_property_parse(
+        obj: *mut Object,
+        name: *const c_char,
+        string: *const c_char,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn object_property_del(obj: *mut Object, name: *const c_char);
+    pub fn object_child_foreach(
+        obj: *mut Object,
+        func: Option<unsafe extern "C" fn(child: *mut Object, opaque: *mut c_void) -> c_int>,
+        opaque: *mut c_void,
+    ) -> c_int;
+
+    pub fn visit_start_struct(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut *mut c_void,
+        size: usize,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn visit_check_struct(v: *mut Visitor, errp: *mut *mut Error) -> bool;
+    pub fn visit_end_struct(v: *mut Visitor, obj: *mut *mut c_void);
+    pub fn visit_start_list(
+        v: *mut Visitor,
+        name: *const c_char,
+        list: *mut *mut GenericList,
+        size: usize,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn visit_end_list(v: *mut Visitor, list: *mut *mut c_void);
+    pub fn visit_type_int64(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut i64,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn visit_type_uint64(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut u64,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn visit_type_bool(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut bool,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn visit_type_str(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut *mut c_char,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn visit_type_any(
+        v: *mut Visitor,
+        name: *const c_char,
+        obj: *mut *mut QObject,
+        errp: *mut *mut Error,
+    ) -> bool;
+
+    pub fn g_free(mem: *mut c_void);
+    pub fn qemu_memalign(alignment: usize, size: usize) -> *mut c_void;
+    pub fn qemu_vfree(ptr: *mut c_void);
+
+    pub fn qmp_register_command(
+        cmds: *mut QmpCommandList,
+        name: *const c_char,
+        func: QmpCommandFunc,
+        options: QmpCommandOptions,
+        special_features: c_uint,
+    );
+    pub fn qmp_find_command(cmds: *const QmpCommandList, name: *const c_char) -> *const QmpCommand;
+    pub fn qmp_query_version(errp: *mut *mut Error) -> *mut VersionInfo;
+    pub fn qapi_free_VersionInfo(obj: *mut VersionInfo);
 
+    pub fn monitor_cur() -> *mut Monitor;
+    pub fn monitor_fd_param(
+        mon: *mut Monitor,
+        fdname: *const c_char,
+        errp: *mut *mut Error,
+    ) -> c_int;
+
+    pub fn qobject_destroy(obj: *mut QObject);
+    pub fn qnum_from_int(value: i64) -> *mut QNum;
+    pub fn qnum_from_uint(value: u64) -> *mut QNum;
+    pub fn qnum_from_double(value: f64) -> *mut QNum;
+    pub fn qbool_from_bool(value: bool) -> *mut QBool;
+    pub fn qstring_from_str(str: *const c_char) -> *mut QString;
+    pub fn qstring_get_str(qstring: *const QString) -> *const c_char;
+    pub fn qdict_new() -> *mut QDict;
+    pub fn qdict_put_obj(qdict: *mut QDict, key: *const c_char, value: *mut QObject);
+    pub fn qdict_first(qdict: *const QDict) -> *const QDictEntry;
+    pub fn qdict_next(qdict: *const QDict, entry: *const QDictEntry) -> *const QDictEntry;
+    pub fn qdict_entry_key(entry: *const QDictEntry) -> *const c_char;
+    pub fn qdict_entry_value(entry: *const QDictEntry) -> *mut QObject;
+    pub fn qlist_new() -> *mut QList;
+    pub fn qlist_append_obj(qlist: *mut QList, obj: *mut QObject);
+
+    pub fn device_class_set_props(dc: *mut DeviceClass, props: *const Property);
+    pub fn vmstate_section_needed(vmsd: *const VMStateDescription, opaque: *mut c_void) -> bool;
     pub fn device_cold_reset(obj: *mut DeviceState);
+    pub fn resettable_reset(obj: *mut Object, typ: ResetType);
+    pub fn resettable_assert_reset(obj: *mut Object, typ: ResetType);
+    pub fn resettable_release_reset(obj: *mut Object, typ: ResetType);
     pub fn device_realize(obj: *mut DeviceState, err: *mut *mut Error) -> bool;
+    pub fn qdev_init_gpio_out(dev: *mut DeviceState, pins: *mut *mut IRQState, n: c_int);
+    pub fn qdev_init_gpio_in(dev: *mut DeviceState, handler: qemu_irq_handler, n: c_int);
+    pub fn qdev_get_gpio_in(dev: *mut DeviceState, n: c_int) -> *mut IRQState;
+    pub fn qdev_connect_gpio_out(dev: *mut DeviceState, n: c_int, input_pin: *mut IRQState);
     pub fn type_register(obj: *const TypeInfo);
 
+    pub fn bql_locked() -> bool;
+
+    pub fn qemu_log(fmt: *const c_char, ...);
+    pub fn error_report(fmt: *const c_char, ...);
+
+    pub fn memory_region_init(
+        mr: *mut MemoryRegion,
+        owner: *mut Object,
+        name: *const c_char,
+        size: u64,
+    );
+    pub fn memory_region_init_alias(
+        mr: *mut MemoryRegion,
+        owner: *mut Object,
+        name: *const c_char,
+        orig: *mut MemoryRegion,
+        offset: u64,
+        size: u64,
+    );
+    pub fn memory_region_init_ram(
+        mr: *mut MemoryRegion,
+        owner: *mut Object,
+        name: *const c_char,
+        size: u64,
+        errp: *mut *mut Error,
+    ) -> bool;
+    pub fn memory_region_init_io(
+        mr: *mut MemoryRegion,
+        owner: *mut Object,
+        ops: *const MemoryRegionOps,
+        opaque: *mut c_void,
+        name: *const c_char,
+        size: u64,
+    );
+    pub fn memory_region_get_ram_ptr(mr: *mut MemoryRegion) -> *mut c_void;
+    pub fn memory_region_size(mr: *mut MemoryRegion) -> u64;
+    pub fn memory_region_add_subregion(
+        mr: *mut MemoryRegion,
+        offset: u64,
+        subregion: *mut MemoryRegion,
+    );
+    pub fn memory_region_del_subregion(mr: *mut MemoryRegion, subregion: *mut MemoryRegion);
+    pub fn get_system_memory() -> *mut MemoryRegion;
+    pub fn address_space_rw(
+        as_: *mut AddressSpace,
+        addr: u64,
+        attrs: MemTxAttrs,
+        buf: *mut c_void,
+        len: u64,
+        is_write: bool,
+    ) -> MemTxResult;
+
+    pub fn sysbus_init_mmio(dev: *mut SysBusDevice, memory: *mut MemoryRegion);
+    pub fn sysbus_init_irq(dev: *mut SysBusDevice, p: *mut *mut IRQState);
+    pub fn qemu_set_irq(irq: *mut IRQState, level: c_int);
+
+    pub fn qemu_add_vm_change_state_handler(
+        cb: VMChangeStateHandler,
+        opaque: *mut c_void,
+    ) -> *mut VMChangeStateEntry;
+    pub fn qemu_del_vm_change_state_handler(e: *mut VMChangeStateEntry);
+
+    pub fn virtio_init(vdev: *mut VirtIODevice, device_id: u16, config_size: usize);
+    pub fn virtio_cleanup(vdev: *mut VirtIODevice);
+
+    pub static qemu_loglevel: c_int;
+    pub static mut qnull_: QNull;
+    pub static mut qmp_commands: QmpCommandList;
+    pub static mut address_space_memory: AddressSpace;
+
+    pub static qdev_prop_bit: PropertyInfo;
     pub static qdev_prop_bool: PropertyInfo;
+    pub static qdev_prop_int32: PropertyInfo;
+    pub static qdev_prop_int64: PropertyInfo;
+    pub static qdev_prop_link: PropertyInfo;
+    pub static qdev_prop_uint32: PropertyInfo;
+    pub static qdev_prop_uint64: PropertyInfo;
+    pub static qdev_prop_size: PropertyInfo;
+    pub static qdev_prop_size32: PropertyInfo;
+    pub static qdev_prop_uuid: PropertyInfo;
+
+    pub static vmstate_info_bool: VMStateInfo;
+    pub static vmstate_info_int32: VMStateInfo;
+    pub static vmstate_info_int64: VMStateInfo;
+    pub static vmstate_info_uint8: VMStateInfo;
+    pub static vmstate_info_uint16: VMStateInfo;
+    pub static vmstate_info_uint32: VMStateInfo;
+    pub static vmstate_info_uint64: VMStateInfo;
+}
+
+// size_t properties accept the same suffixes as "size" properties, but
+// are stored in a field as wide as a pointer
+#[cfg(target_pointer_width = "64")]
+pub use self::qdev_prop_size as qdev_prop_usize;
+#[cfg(target_pointer_width = "32")]
+pub use self::qdev_prop_size32 as qdev_prop_usize;
+
+// The following functions are static inline in C, so they are
+// reimplemented here on top of the structs above.
+
+unsafe fn qobject_refcnt<'a>(obj: *mut QObject) -> &'a AtomicUsize {
+    // SAFETY: AtomicUsize has the same layout as the size_t refcnt
+    &*addr_of_mut!((*obj).base.refcnt).cast::<AtomicUsize>()
+}
+
+pub unsafe fn qobject_ref_impl(obj: *mut QObject) {
+    if !obj.is_null() {
+        qobject_refcnt(obj).fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+pub unsafe fn qobject_unref_impl(obj: *mut QObject) {
+    if obj.is_null() {
+        return;
+    }
+    let refcnt = qobject_refcnt(obj).fetch_sub(1, Ordering::SeqCst);
+    assert!(refcnt != 0);
+    if refcnt == 1 {
+        qobject_destroy(obj);
+    }
+}
+
+pub unsafe fn qnull() -> *mut QObject {
+    let obj = addr_of_mut!(qnull_).cast::<QObject>();
+    qobject_ref_impl(obj);
+    obj
+}
+
+pub unsafe fn qlist_first(qlist: *const QList) -> *const QListEntry {
+    (*qlist).head.tql_next.cast()
+}
+
+pub unsafe fn qlist_next(entry: *const QListEntry) -> *const QListEntry {
+    (*entry).next.tql_next.cast()
+}
+
+pub unsafe fn qlist_entry_obj(entry: *const QListEntry) -> *mut QObject {
+    (*entry).value
 }
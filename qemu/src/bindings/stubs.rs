@@ -0,0 +1,136 @@
+//! Minimal implementations of the QEMU functions that are needed by the
+//! unit tests, which are not linked with QEMU.  Objects are `Object`
+//! structs whose `klass` points to a [`StubClass`].
+
+use libc::c_char;
+use std::cell::Cell;
+use std::ffi::{c_void, CStr};
+use std::ptr;
+
+use super::{
+    Error, MemoryRegion, MemoryRegionOps, Object, ObjectClass, SysBusDevice, VersionInfo,
+    VersionTriple,
+};
+
+/// The name of a type and of its ancestors, most derived first.
+pub struct StubClass(pub &'static [&'static CStr]);
+
+/// A `free` function for objects that are not heap allocated.
+pub extern "C" fn stub_free(_obj: *mut c_void) {}
+
+/// Return an object of the given class.  It is never freed.
+pub fn stub_object(class: &'static StubClass) -> Object {
+    Object {
+        klass: (class as *const StubClass).cast_mut().cast(),
+        free: stub_free,
+        properties: ptr::null_mut(),
+        r#ref: 1,
+        parent: ptr::null_mut(),
+    }
+}
+
+unsafe fn class_of<'a>(obj: *const Object) -> &'a StubClass {
+    &*(*obj).klass.cast::<StubClass>()
+}
+
+#[no_mangle]
+unsafe extern "C" fn object_dynamic_cast(obj: *mut Object, typ: *const c_char) -> *mut c_void {
+    let typ = CStr::from_ptr(typ);
+    if class_of(obj).0.contains(&typ) {
+        obj.cast()
+    } else {
+        ptr::null_mut()
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn object_get_typename(obj: *const Object) -> *const c_char {
+    class_of(obj).0[0].as_ptr()
+}
+
+#[no_mangle]
+unsafe extern "C" fn object_ref(obj: *mut Object) {
+    (*obj).r#ref += 1;
+}
+
+#[no_mangle]
+unsafe extern "C" fn object_unref(obj: *mut Object) {
+    (*obj).r#ref -= 1;
+}
+
+thread_local! {
+    static BQL_LOCKED: Cell<bool> = Cell::new(false);
+}
+
+/// Pretend that the current thread took or released the BQL.
+pub fn set_bql_locked(locked: bool) {
+    BQL_LOCKED.with(|bql| bql.set(locked));
+}
+
+#[no_mangle]
+extern "C" fn bql_locked() -> bool {
+    BQL_LOCKED.with(Cell::get)
+}
+
+/// Objects have no properties; in particular, devices are never realized.
+#[no_mangle]
+extern "C" fn object_property_get_bool(
+    _obj: *mut Object,
+    _name: *const c_char,
+    _errp: *mut *mut Error,
+) -> bool {
+    false
+}
+
+/// Report version 9.1.50 for `query-version`.
+#[no_mangle]
+extern "C" fn qmp_query_version(_errp: *mut *mut Error) -> *mut VersionInfo {
+    let qemu = Box::new(VersionTriple {
+        major: 9,
+        minor: 1,
+        micro: 50,
+    });
+    Box::into_raw(Box::new(VersionInfo {
+        qemu: Box::into_raw(qemu),
+        package: ptr::null_mut(),
+    }))
+}
+
+#[no_mangle]
+unsafe extern "C" fn qapi_free_VersionInfo(obj: *mut VersionInfo) {
+    let info = Box::from_raw(obj);
+    drop(Box::from_raw(info.qemu));
+}
+
+// The following functions are only linked in, but never called, by the
+// unit tests.  Unwinding out of them aborts.
+
+#[no_mangle]
+extern "C" fn object_class_dynamic_cast(
+    _klass: *mut ObjectClass,
+    _typ: *const c_char,
+) -> *mut ObjectClass {
+    unreachable!("object_class_dynamic_cast")
+}
+
+#[no_mangle]
+extern "C" fn object_type_get_instance_size(_typename: *const c_char) -> usize {
+    unreachable!("object_type_get_instance_size")
+}
+
+#[no_mangle]
+extern "C" fn memory_region_init_io(
+    _mr: *mut MemoryRegion,
+    _owner: *mut Object,
+    _ops: *const MemoryRegionOps,
+    _opaque: *mut c_void,
+    _name: *const c_char,
+    _size: u64,
+) {
+    unreachable!("memory_region_init_io")
+}
+
+#[no_mangle]
+extern "C" fn sysbus_init_mmio(_dev: *mut SysBusDevice, _memory: *mut MemoryRegion) {
+    unreachable!("sysbus_init_mmio")
+}
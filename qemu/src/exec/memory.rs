@@ -0,0 +1,325 @@
+//! Bindings for memory regions
+
+use std::ffi::{c_void, CStr};
+use std::marker::PhantomData;
+use std::ptr;
+use std::slice;
+
+use libc::c_uint;
+
+use cstr::cstr;
+
+use crate::bindings;
+use crate::bindings::memory_region_add_subregion;
+use crate::bindings::memory_region_del_subregion;
+use crate::bindings::memory_region_get_ram_ptr;
+use crate::bindings::memory_region_init;
+use crate::bindings::memory_region_init_alias;
+use crate::bindings::memory_region_init_io;
+use crate::bindings::memory_region_init_ram;
+use crate::bindings::memory_region_size;
+use crate::bindings::object_type_get_instance_size;
+use crate::bindings::MemoryRegion;
+use crate::bindings::MemoryRegionOps;
+use crate::bindings::MemoryRegionOpsImpl;
+use crate::bindings::MemoryRegionOpsValid;
+use crate::bindings::Object;
+use crate::bindings::DEVICE_NATIVE_ENDIAN;
+
+use crate::qom::object::ObjectType;
+
+use crate::qom::refs::IsA;
+use crate::qom::refs::ObjectCast;
+use crate::qom::refs::Owned;
+
+use crate::qom_isa;
+
+use crate::util::callback;
+
+use crate::Error;
+use crate::Result;
+
+unsafe impl ObjectType for MemoryRegion {
+    const TYPE: &'static CStr = cstr!("memory-region");
+}
+
+qom_isa!(MemoryRegion, Object);
+
+extern "C" fn free_memory_region(p: *mut c_void) {
+    // SAFETY: only used as the free function of memory regions
+    // created by MemoryRegion::alloc, which uses calloc
+    unsafe { libc::free(p) }
+}
+
+/// Callbacks for an MMIO region whose accesses are dispatched to a
+/// device of type `Device`.  Usually implemented by `mmio_region!`.
+pub trait MmioOps {
+    type Device;
+
+    /// Read `size` bytes at offset `addr` of the region.
+    const READ: fn(dev: &Self::Device, addr: u64, size: u32) -> u64;
+
+    /// Write the low `size` bytes of `data` at offset `addr` of the region.
+    const WRITE: fn(dev: &Self::Device, addr: u64, data: u64, size: u32);
+}
+
+unsafe extern "C" fn rust_mmio_read<T: MmioOps>(
+    opaque: *mut c_void,
+    addr: u64,
+    size: c_uint,
+) -> u64 {
+    callback::call_opaque(opaque, |dev| T::READ(dev, addr, size))
+}
+
+unsafe extern "C" fn rust_mmio_write<T: MmioOps>(
+    opaque: *mut c_void,
+    addr: u64,
+    data: u64,
+    size: c_uint,
+) {
+    callback::call_opaque(opaque, |dev| T::WRITE(dev, addr, data, size))
+}
+
+/// Description of an MMIO region of a device of type `T`.  See
+/// `DeviceImpl::MMIO_REGIONS` and `mmio_region!`.
+pub struct MmioRegion<T> {
+    name: &'static CStr,
+    size: u64,
+    ops: MemoryRegionOps,
+    _phantom: PhantomData<fn(&T)>,
+}
+
+impl<T> MmioRegion<T> {
+    pub const fn new<O: MmioOps<Device = T>>(name: &'static CStr, size: u64) -> Self {
+        MmioRegion {
+            name,
+            size,
+            ops: MemoryRegionOps {
+                read: Some(rust_mmio_read::<O>),
+                write: Some(rust_mmio_write::<O>),
+                read_with_attrs: None,
+                write_with_attrs: None,
+                endianness: DEVICE_NATIVE_ENDIAN,
+                // zero access sizes select QEMU's defaults
+                valid: MemoryRegionOpsValid {
+                    min_access_size: 0,
+                    max_access_size: 0,
+                    unaligned: false,
+                    accepts: None,
+                },
+                r#impl: MemoryRegionOpsImpl {
+                    min_access_size: 0,
+                    max_access_size: 0,
+                    unaligned: false,
+                },
+            },
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Describe an MMIO region of `size` bytes, whose accesses are handled by
+/// the `read` and `write` methods of `Type`:
+///
+/// ```ignore
+/// const MMIO_REGIONS: &'static [MmioRegion<MyDevice>] = &[
+///     mmio_region!(cstr!("regs"), 0x1000, MyDevice, MyDevice::read, MyDevice::write),
+/// ];
+/// ```
+#[macro_export]
+macro_rules! mmio_region {
+    ($name:expr, $size:expr, $type:ty, $read:expr, $write:expr) => {{
+        struct Ops;
+        impl $crate::exec::memory::MmioOps for Ops {
+            type Device = $type;
+            const READ: fn(&$type, u64, u32) -> u64 = $read;
+            const WRITE: fn(&$type, u64, u64, u32) = $write;
+        }
+        $crate::exec::memory::MmioRegion::new::<Ops>($name, $size)
+    }};
+}
+
+impl MemoryRegion {
+    /// Allocate memory for a memory region.  When the last reference
+    /// goes away, QOM frees it with `obj->free`.
+    unsafe fn alloc() -> *mut MemoryRegion {
+        let size_of = object_type_get_instance_size(Self::TYPE.as_ptr());
+        let mr: *mut MemoryRegion = libc::calloc(1, size_of).cast();
+        assert!(!mr.is_null(), "out of memory");
+        mr
+    }
+
+    /// Create an empty memory region of `size` bytes, as a child of
+    /// `owner`.  The region only serves as a container for subregions
+    /// added with [`add_subregion`](MemoryRegion::add_subregion).
+    pub fn container<O: IsA<Object>>(owner: &O, name: &CStr, size: u64) -> Owned<MemoryRegion> {
+        // SAFETY: same as for `ram`
+        unsafe {
+            let mr = Self::alloc();
+            memory_region_init(
+                mr,
+                owner.upcast::<Object>().as_mut_ptr(),
+                name.as_ptr(),
+                size,
+            );
+            (*mr).parent_obj.free = free_memory_region;
+            Owned::from(&*mr)
+        }
+    }
+
+    /// Create a memory region of `size` bytes, as a child of `owner`,
+    /// that gives access to `orig` starting at `offset`.  The alias keeps
+    /// `orig` alive.
+    pub fn alias<O: IsA<Object>>(
+        owner: &O,
+        name: &CStr,
+        orig: &MemoryRegion,
+        offset: u64,
+        size: u64,
+    ) -> Owned<MemoryRegion> {
+        let end = offset.checked_add(size);
+        assert!(
+            end.map_or(false, |end| end <= orig.size()),
+            "alias out of bounds of memory region"
+        );
+        // SAFETY: same as for `ram`
+        unsafe {
+            let mr = Self::alloc();
+            memory_region_init_alias(
+                mr,
+                owner.upcast::<Object>().as_mut_ptr(),
+                name.as_ptr(),
+                orig.as_mut_ptr(),
+                offset,
+                size,
+            );
+            (*mr).parent_obj.free = free_memory_region;
+            Owned::from(&*mr)
+        }
+    }
+
+    /// Create a RAM memory region of `size` bytes, as a child of `owner`.
+    pub fn ram<O: IsA<Object>>(owner: &O, name: &CStr, size: u64) -> Result<Owned<MemoryRegion>> {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the memory is allocated with the size that QOM expects
+        // for the type, and memory_region_init_ram initializes it.  The
+        // reference taken by memory_region_init_ram belongs to `owner`,
+        // so take a new one for the caller.
+        unsafe {
+            let mr = Self::alloc();
+            memory_region_init_ram(
+                mr,
+                owner.upcast::<Object>().as_mut_ptr(),
+                name.as_ptr(),
+                size,
+                &mut err,
+            );
+            (*mr).parent_obj.free = free_memory_region;
+            let mr = Owned::from(&*mr);
+            Error::err_or_else(err, || mr)
+        }
+    }
+
+    /// Create the MMIO region described by `region`, as a child of `owner`.
+    /// Accesses to the region are dispatched to `owner`.
+    pub fn io<O: ObjectType + 'static>(
+        owner: &O,
+        region: &'static MmioRegion<O>,
+    ) -> Owned<MemoryRegion> {
+        // SAFETY: same as for `ram`; all QOM types are subclasses of
+        // Object.  The callbacks in region.ops expect an opaque pointer
+        // to O, which the type of `region` guarantees
+        unsafe {
+            let mr = Self::alloc();
+            let opaque: *const O = owner;
+            memory_region_init_io(
+                mr,
+                owner.unsafe_cast::<Object>().as_mut_ptr(),
+                &region.ops,
+                opaque as *mut c_void,
+                region.name.as_ptr(),
+                region.size,
+            );
+            (*mr).parent_obj.free = free_memory_region;
+            Owned::from(&*mr)
+        }
+    }
+
+    /// Return the size of the memory region in bytes
+    pub fn size(&self) -> u64 {
+        // SAFETY: the memory region is valid
+        unsafe { memory_region_size(self.as_mut_ptr()) }
+    }
+
+    /// Map `child` into `self` at `offset`, so that accesses to that
+    /// part of `self` are dispatched to `child`.  `self` is usually a
+    /// region created with [`container`](MemoryRegion::container), and
+    /// it keeps `child` alive until the subregion is removed.
+    pub fn add_subregion(&self, offset: u64, child: &MemoryRegion) {
+        // SAFETY: both memory regions are valid
+        unsafe { memory_region_add_subregion(self.as_mut_ptr(), offset, child.as_mut_ptr()) }
+    }
+
+    /// Undo the effect of [`add_subregion`](MemoryRegion::add_subregion).
+    pub fn del_subregion(&self, child: &MemoryRegion) {
+        // SAFETY: both memory regions are valid
+        unsafe { memory_region_del_subregion(self.as_mut_ptr(), child.as_mut_ptr()) }
+    }
+
+    fn ram_ptr(&self, offset: u64, len: usize) -> *mut u8 {
+        let end = offset.checked_add(len as u64);
+        assert!(
+            end.map_or(false, |end| end <= self.size()),
+            "access out of bounds of memory region"
+        );
+        // SAFETY: the memory region is a RAM region, and the access was
+        // checked to be within its bounds
+        unsafe {
+            memory_region_get_ram_ptr(self.as_mut_ptr())
+                .cast::<u8>()
+                .add(offset as usize)
+        }
+    }
+
+    /// Copy `buf.len()` bytes starting at `offset` out of a RAM region.
+    /// The guest can access the memory at the same time, so the data is
+    /// copied instead of creating a Rust reference to it.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) {
+        let src = self.ram_ptr(offset, buf.len());
+        // SAFETY: ram_ptr checked the bounds
+        unsafe { ptr::copy(src, buf.as_mut_ptr(), buf.len()) }
+    }
+
+    /// Copy `data` into a RAM region, starting at `offset`.
+    pub fn write(&self, offset: u64, data: &[u8]) {
+        let dest = self.ram_ptr(offset, data.len());
+        // SAFETY: ram_ptr checked the bounds
+        unsafe { ptr::copy(data.as_ptr(), dest, data.len()) }
+    }
+
+    /// Return the contents of a RAM region as a slice.
+    ///
+    /// # Safety
+    ///
+    /// The caller must hold the BQL, and the guest must not write to the
+    /// memory region for as long as the slice is alive (for example
+    /// because the device owns the memory and does not map it for the
+    /// guest yet, or because the VM is stopped).
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        let len = self.size() as usize;
+        slice::from_raw_parts(self.ram_ptr(0, len), len)
+    }
+
+    /// Return the contents of a RAM region as a mutable slice.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`as_slice`](MemoryRegion::as_slice); in addition, the guest
+    /// must not read the memory region and no other slice for the same
+    /// memory region may be alive while the result is in use.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn as_mut_slice(&self) -> &mut [u8] {
+        let len = self.size() as usize;
+        slice::from_raw_parts_mut(self.ram_ptr(0, len), len)
+    }
+}
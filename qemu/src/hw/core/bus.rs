@@ -0,0 +1,18 @@
+//! Bindings for the QOM bus class
+
+use std::ffi::CStr;
+
+use cstr::cstr;
+
+use crate::bindings::BusState;
+use crate::bindings::Object;
+
+use crate::qom::object::ObjectType;
+
+use crate::qom_isa;
+
+unsafe impl ObjectType for BusState {
+    const TYPE: &'static CStr = cstr!("bus");
+}
+
+qom_isa!(BusState, Object);
@@ -2,31 +2,55 @@
 //!
 //! @author Paolo Bonzini
 
+use crate::qom::object::ClassType;
+use crate::qom::object::ObjectMethods;
 use crate::qom::object::ObjectType;
 
 use crate::qom::refs::IsA;
 use crate::qom::refs::ObjectCast;
+use crate::qom::refs::Owned;
 
 use crate::bindings;
 use crate::bindings::device_cold_reset;
 use crate::bindings::device_realize;
+use crate::bindings::object_child_foreach;
+use crate::bindings::object_property_find;
+use crate::bindings::object_property_get_bool;
+use crate::bindings::qdev_connect_gpio_out;
+use crate::bindings::qdev_get_gpio_in;
+use crate::bindings::resettable_reset;
+use crate::bindings::BusState;
+use crate::bindings::DeviceClass;
 use crate::bindings::DeviceState;
 use crate::bindings::Object;
 
 use crate::qom_isa;
 
+use crate::qapi::qobject::QObject;
+
+use crate::hw::core::device_impl::ResetType;
+use crate::hw::core::irq::IrqHandle;
+
+use crate::Error;
 use crate::Result;
 
 use cstr::cstr;
 
-use std::ffi::CStr;
+use libc::c_int;
+
+use std::ffi::{c_void, CStr, CString};
 use std::ops::Deref;
+use std::ptr::addr_of_mut;
 use std::ptr::null_mut;
 
 unsafe impl ObjectType for DeviceState {
     const TYPE: &'static CStr = cstr!("device");
 }
 
+unsafe impl ClassType for DeviceClass {
+    const TYPE: &'static CStr = cstr!("device");
+}
+
 qom_isa!(DeviceState, Object);
 
 /// Trait for methods exposed by the Object class.  The methods can be
@@ -48,11 +72,331 @@ where
         }
     }
 
+    /// Realize all the children of `self` that are devices.  Stop at the
+    /// first failure, and return its error prefixed with the QOM path
+    /// of the child that failed.
+    fn realize_children(&self) -> Result<()> {
+        unsafe extern "C" fn realize_child(child: *mut Object, opaque: *mut c_void) -> c_int {
+            let result = &mut *opaque.cast::<Result<()>>();
+            let child = &*child;
+            if let Some(device) = child.dynamic_cast::<DeviceState>() {
+                if let Err(err) = device.realize() {
+                    let path = child.canonical_path().unwrap_or_default();
+                    *result = Err(Error::with_error(&path, err));
+                    return 1;
+                }
+            }
+            0
+        }
+
+        let device = self.upcast::<DeviceState>();
+        let mut result: Result<()> = Ok(());
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // `result` outlives the call to object_child_foreach
+        unsafe {
+            object_child_foreach(
+                device.unsafe_cast::<Object>().as_mut_ptr(),
+                Some(realize_child),
+                addr_of_mut!(result).cast(),
+            );
+        }
+        result
+    }
+
+    /// Return a pointer to the device for C functions that take a
+    /// `DeviceState *`, such as `sysbus_mmio_map()` in board code.  The
+    /// pointer does not own a reference, so `self` must stay alive for
+    /// as long as C uses it; see [`Owned::into_device_ptr`] to hand
+    /// the reference over to C.
+    fn as_device_ptr(&self) -> *mut DeviceState {
+        let device = self.upcast::<DeviceState>();
+        // SAFETY: creating the pointer is safe, using it is not
+        unsafe { device.as_mut_ptr() }
+    }
+
+    /// Return the bus that the device is plugged into, or `None` if the
+    /// device is not on a bus, for example because it was not added to
+    /// one yet.  Devices can use it to reach the other devices on the
+    /// same bus.
+    fn parent_bus(&self) -> Option<Owned<BusState>> {
+        let device = self.upcast::<DeviceState>();
+        let bus = device.parent_bus;
+        if bus.is_null() {
+            None
+        } else {
+            // SAFETY: the device holds a reference to its bus, which
+            // is therefore valid
+            Some(unsafe { Owned::from(&*bus) })
+        }
+    }
+
+    /// Return whether the device has been realized.
+    fn is_realized(&self) -> bool {
+        let obj = self.upcast::<DeviceState>().upcast::<Object>();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // all devices have a "realized" property
+        unsafe {
+            object_property_get_bool(obj.as_mut_ptr(), cstr!("realized").as_ptr(), null_mut())
+        }
+    }
+
+    /// Return the `DeviceClass` of `self`, which gives access to the
+    /// virtual functions of the device
+    fn device_class(&self) -> &'static DeviceClass {
+        let device = self.upcast::<DeviceState>();
+        device
+            .class_of::<DeviceClass>()
+            .expect("class of a device is not a DeviceClass")
+    }
+
+    /// Return the number of unnamed GPIO outputs of the device, for
+    /// example those created with [`IrqBank::init_gpio_out`](crate::IrqBank::init_gpio_out).
+    /// GPIO inputs are not counted.
+    fn gpio_line_count(&self) -> usize {
+        let obj = self.upcast::<DeviceState>().upcast::<Object>();
+        (0..)
+            .take_while(|i| {
+                let name = CString::new(format!("unnamed-gpio-out[{}]", i)).unwrap();
+                // SAFETY: safety of this is the requirement for implementing IsA
+                unsafe { !object_property_find(obj.as_mut_ptr(), name.as_ptr()).is_null() }
+            })
+            .count()
+    }
+
+    /// Return input line `n` of the device, for example to connect it
+    /// to another device with [`connect_gpio_out`](DeviceMethods::connect_gpio_out).
+    /// Boards use it to obtain the endpoint that a source device drives.
+    ///
+    /// Panics if the device does not have an unnamed input `n`, for
+    /// example one created with [`DeviceImplExt::init_gpio_in`](crate::DeviceImplExt::init_gpio_in).
+    fn gpio_in(&self, n: usize) -> IrqHandle {
+        let device = self.upcast::<DeviceState>();
+        let name = CString::new(format!("unnamed-gpio-in[{}]", n)).unwrap();
+        // SAFETY: safety of this is the requirement for implementing IsA
+        let found = unsafe {
+            !object_property_find(device.upcast::<Object>().as_mut_ptr(), name.as_ptr()).is_null()
+        };
+        assert!(
+            found,
+            "device {} has no GPIO input {}",
+            device.typename(),
+            n
+        );
+        let n = c_int::try_from(n).expect("GPIO line out of range");
+        // SAFETY: safety of this is the requirement for implementing IsA
+        IrqHandle::from_raw(unsafe { qdev_get_gpio_in(device.as_mut_ptr(), n) })
+    }
+
+    /// Connect output line `n` of the device to `irq`, usually the input
+    /// of another device.  Like in C, this replaces an earlier connection
+    /// of the same line.
+    fn connect_gpio_out(&self, n: usize, irq: &IrqHandle) {
+        let device = self.upcast::<DeviceState>();
+        let n = c_int::try_from(n).expect("GPIO line out of range");
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // the IRQState is owned by the device that created the input
+        unsafe { qdev_connect_gpio_out(device.as_mut_ptr(), n, irq.as_raw()) }
+    }
+
     fn cold_reset(&self) {
         let device = self.upcast::<DeviceState>();
         // SAFETY: safety of this is the requirement for implementing IsA
         unsafe { device_cold_reset(device.as_mut_ptr()) }
     }
+
+    /// Reset the device and its children, passing `reset_type` to their
+    /// `RESET_HOLD` hooks.
+    fn reset(&self, reset_type: ResetType) {
+        let obj = self.upcast::<DeviceState>().upcast::<Object>();
+        // SAFETY: safety of this is the requirement for implementing IsA
+        unsafe { resettable_reset(obj.as_mut_ptr(), (&reset_type).into()) }
+    }
 }
 
 impl<R: Deref> DeviceMethods for R where R::Target: IsA<DeviceState> {}
+
+impl<T: IsA<DeviceState>> Owned<T> {
+    /// Consume the `Owned` and return a pointer to the device, for C
+    /// functions that take over the reference, such as the ones that
+    /// end in `_and_unref`.  The reference is not reported by
+    /// [`LeakGuard`](crate::LeakGuard) anymore.
+    pub fn into_device_ptr(src: Owned<T>) -> *mut DeviceState {
+        let device = Owned::leak(Owned::upcast::<DeviceState>(src));
+        // SAFETY: creating the pointer is safe, using it is not
+        unsafe { device.as_mut_ptr() }
+    }
+}
+
+/// Builder for a tree of objects, such as the devices of a board.
+/// [`add`](ObjectTree::add) adds each object to the QOM tree as soon
+/// as it is created, so that it can be the parent of other objects;
+/// [`realize`](ObjectTree::realize) then realizes the devices in the
+/// order in which they were added, which places parents before their
+/// children.
+///
+/// If realizing a device fails, or if the builder is dropped without
+/// calling `realize`, all the objects that were added are unparented,
+/// in reverse order:
+///
+/// ```ignore
+/// let mut tree = ObjectTree::new();
+/// let bus = tree.add(&*board, cstr!("bus"), MyBus::new());
+/// tree.add(&*bus, cstr!("uart0"), MyUart::new());
+/// tree.realize()?;
+/// ```
+#[derive(Debug, Default)]
+pub struct ObjectTree {
+    objects: Vec<Owned<Object>>,
+}
+
+impl ObjectTree {
+    pub fn new() -> Self {
+        ObjectTree::default()
+    }
+
+    /// Add `child` to the QOM tree as a child of `parent`, with the
+    /// given property name, and return it.
+    pub fn add<P: IsA<Object>, T: IsA<Object>>(
+        &mut self,
+        parent: &P,
+        name: &CStr,
+        child: Owned<T>,
+    ) -> Owned<T> {
+        parent.add_child(name, &*child);
+        self.objects.push(Owned::upcast(child.clone()));
+        child
+    }
+
+    /// Realize the devices that were added to the tree.  On failure,
+    /// unparent all the objects and return the error, prefixed with the
+    /// QOM path of the device that failed.
+    pub fn realize(mut self) -> Result<()> {
+        for obj in &self.objects {
+            if let Some(device) = obj.dynamic_cast::<DeviceState>() {
+                if let Err(err) = device.realize() {
+                    let path = obj.canonical_path().unwrap_or_default();
+                    return Err(Error::with_error(&path, err));
+                }
+            }
+        }
+        self.objects.clear();
+        Ok(())
+    }
+}
+
+impl Drop for ObjectTree {
+    fn drop(&mut self) {
+        while let Some(obj) = self.objects.pop() {
+            obj.unparent();
+        }
+    }
+}
+
+/// Conversion of the fields of a `device_config!` struct to property
+/// values.  `None` means that the property is left alone.
+pub trait ConfigValue {
+    fn to_property(&self) -> Option<QObject>;
+}
+
+macro_rules! impl_config_value {
+    ($($type:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl ConfigValue for $type {
+                fn to_property(&self) -> Option<QObject> {
+                    Some(QObject::$variant(self.clone().into()))
+                }
+            }
+        )*
+    };
+}
+
+impl_config_value! {
+    bool => Bool,
+    u8 => Uint, u16 => Uint, u32 => Uint, u64 => Uint,
+    i8 => Int, i16 => Int, i32 => Int, i64 => Int,
+    String => Str,
+}
+
+impl ConfigValue for usize {
+    fn to_property(&self) -> Option<QObject> {
+        Some(QObject::Uint(*self as u64))
+    }
+}
+
+impl ConfigValue for &str {
+    fn to_property(&self) -> Option<QObject> {
+        Some(QObject::Str((*self).to_string()))
+    }
+}
+
+impl<T: ConfigValue> ConfigValue for Option<T> {
+    fn to_property(&self) -> Option<QObject> {
+        self.as_ref().and_then(ConfigValue::to_property)
+    }
+}
+
+/// A wrapper for struct declarations, that adds an `apply_to` method
+/// to set the properties of a device from the fields of the struct.
+/// The name of each property is the name of the field, with underscores
+/// replaced by dashes; fields can have any type that implements
+/// [`ConfigValue`], and fields of type `Option` are only applied if
+/// they are `Some`:
+///
+/// ```ignore
+/// device_config! {
+///     #[derive(Default)]
+///     struct MyDeviceConfig {
+///         queue_size: Option<u32>,
+///         readonly: Option<bool>,
+///     }
+/// }
+///
+/// let config = MyDeviceConfig { readonly: Some(true), ..Default::default() };
+/// config.apply_to(&*dev)?;
+/// dev.realize()?;
+/// ```
+#[macro_export]
+macro_rules! device_config {
+    (
+        $(#[$struct_meta:meta])*
+        $struct_vis:vis
+        struct $StructName:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis
+                $field_name:ident : $field_ty:ty
+            ),*
+            $(,)?
+        }
+    ) => {
+        $(#[$struct_meta])*
+        $struct_vis
+        struct $StructName {
+            $(
+                $(#[$field_meta])*
+                $field_vis
+                $field_name : $field_ty ,
+            )*
+        }
+
+        impl $StructName {
+            /// Set the properties of `dev` from the fields of `self`,
+            /// stopping at the first error.
+            #[allow(dead_code)]
+            $struct_vis fn apply_to<D: $crate::qom::refs::IsA<$crate::DeviceState>>(
+                &self,
+                dev: &D,
+            ) -> $crate::Result<()> {
+                let dev = $crate::ObjectCast::upcast::<$crate::DeviceState>(dev);
+                $(
+                    if let Some(value) = $crate::hw::core::device::ConfigValue::to_property(&self.$field_name) {
+                        let name = stringify!($field_name).replace('_', "-");
+                        let name = std::ffi::CString::new(name).unwrap();
+                        $crate::ObjectMethods::set_property_qobject(&dev, &name, value)?;
+                    }
+                )*
+                Ok(())
+            }
+        }
+    };
+}
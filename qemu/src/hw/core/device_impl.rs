@@ -4,23 +4,67 @@
 
 #![allow(clippy::missing_safety_doc)]
 
-use std::ffi::c_void;
+use const_default::ConstDefault;
+
+use std::cell::{Cell, UnsafeCell};
+use std::ffi::{c_void, CStr};
+use std::fmt;
+use std::ops::Deref;
+use std::os::unix::io::RawFd;
+use std::ptr;
 
 use crate::bindings;
+use crate::bindings::device_class_set_props;
+use crate::bindings::g_free;
+use crate::bindings::monitor_cur;
+use crate::bindings::monitor_fd_param;
+use crate::bindings::object_class_property_set_description;
+use crate::bindings::qdev_init_gpio_in;
+use crate::bindings::visit_type_str;
 use crate::bindings::DeviceClass;
 use crate::bindings::DeviceState;
+use crate::bindings::MemoryRegion;
+use crate::bindings::Object;
+use crate::bindings::ObjectClass;
 use crate::bindings::Property;
+use crate::bindings::PropertyInfo;
+use crate::bindings::SysBusDevice;
+use crate::bindings::VMStateDescription;
+use crate::bindings::Visitor;
+
+use crate::exec::memory::MmioRegion;
+
+use crate::qapi::visitor::OutputVisitor;
 
+use crate::qom::object_impl::instance_init;
+use crate::qom::object_impl::register_type;
+use crate::qom::object_impl::register_type_with_init;
 use crate::qom::object_impl::ObjectImpl;
+use crate::qom::object_impl::SuperclassImpl;
 use crate::qom::object_impl::TypeImpl;
 
+use crate::qom::refs::assert_bql_locked;
+use crate::qom::refs::IsA;
 use crate::qom::refs::ObjectCast;
+use crate::qom::refs::Owned;
 
+use crate::hw::core::device::DeviceMethods;
+
+use crate::util::callback;
 use crate::util::error::Error;
+use crate::util::zeroed::Zeroed;
+
+use crate::foreign_enum;
+
+use libc::{c_char, c_int};
 
 /// Information on which superclass methods are overridden
 /// by a Rust-implemented subclass of Device.
-pub trait DeviceImpl: ObjectImpl + DeviceTypeImpl {
+///
+/// When the device is realized, `APPLY_DEFAULT_PROPS`, `TRY_INIT` and
+/// `REALIZE` happen in this order; see [`ObjectImpl`] for how they fit
+/// in the life of the object.
+pub trait DeviceImpl: ObjectImpl + DeviceTypeImpl + IsA<DeviceState> + 'static {
     /// If not `None`, a function that implements the `realize` member
     /// of the QOM `DeviceClass`.
     const REALIZE: Option<fn(obj: &Self) -> crate::Result<()>> = None;
@@ -32,36 +76,268 @@ pub trait DeviceImpl: ObjectImpl + DeviceTypeImpl {
     /// If not `None`, a function that implements the `cold_reset` member
     /// of the QOM `DeviceClass`.
     const COLD_RESET: Option<fn(obj: &Self)> = None;
+
+    /// If not `None`, a function that implements the `hold` phase of
+    /// the device's reset.  It receives the reason for the reset, so that
+    /// for example state that is restored by migration can be left alone
+    /// on `ResetType::SnapshotLoad`.
+    const RESET_HOLD: Option<fn(obj: &Self, reset_type: ResetType)> = None;
+
+    /// If not `None`, a function that is called when one of the GPIO
+    /// inputs created by [`DeviceImplExt::init_gpio_in`] changes level.
+    /// It receives the number of the line and the new level.
+    const GPIO_IN: Option<fn(obj: &Self, line: u32, level: bool)> = None;
+
+    /// If not `None`, a function that is called after properties have
+    /// been set but before `REALIZE`.  It can compute the value of
+    /// properties that the user did not set (see [`Tracked`]) from
+    /// other properties.  If it fails, the device is not realized.
+    const APPLY_DEFAULT_PROPS: Option<fn(obj: &Self) -> crate::Result<()>> = None;
+
+    /// MMIO regions of the device, usually built with `mmio_region!`.
+    /// Like in C devices, they are created by `instance_init`, after
+    /// `INSTANCE_INIT`, and, for subclasses of `SysBusDevice`, exposed
+    /// with `sysbus_init_mmio()` in the order in which they are listed.
+    /// This needs the type to be registered with [`register_device_type`].
+    const MMIO_REGIONS: &'static [MmioRegion<Self>] = &[];
+
+    /// If not `None`, a function that returns properties that are only
+    /// known at runtime, for example one per sub-unit of the device.  They
+    /// are added after the static properties of `qdev_define_type!`
+    /// when the class is initialized.  Their names must live forever,
+    /// for example because they are leaked with `Box::leak`.
+    const DYNAMIC_PROPERTIES: Option<fn() -> Vec<Property>> = None;
+
+    /// If not `None`, a function that returns the description of the
+    /// state that is migrated, usually defined with
+    /// `vmstate_description!`.  Without it, the device has no state
+    /// that needs to be migrated.
+    const VMSTATE: Option<fn() -> *const VMStateDescription> = None;
+
+    /// If not `None`, a function that performs fallible initialization
+    /// of the device state, for example allocating a buffer whose size
+    /// depends on a property.  QOM cannot report errors from
+    /// `instance_init`, so `INSTANCE_INIT` and the `Default`
+    /// implementation of the state must not fail; setup that can fail
+    /// is deferred to this hook instead, and stored in a [`Deferred`].
+    ///
+    /// The function runs during realize, after `APPLY_DEFAULT_PROPS`.
+    /// If it fails, the error is returned by realize.  It may run again
+    /// if realize is retried.
+    const TRY_INIT: Option<fn(obj: &Self) -> crate::Result<()>> = None;
+
+    /// Whether the device can be added with `device_add` while the
+    /// guest is running.  If false, the device can only be created on
+    /// the command line.  Setting it to true does not make hot-pluggable
+    /// a device whose superclass is not.
+    const HOTPLUGGABLE: bool = true;
+
+    /// Whether the device must be realized with the BQL held.  If true,
+    /// debug builds with the `check-bql` feature check that the lock is
+    /// taken before running the realize hooks.
+    const REALIZE_REQUIRES_BQL: bool = true;
+
+    /// Whether `COLD_RESET` assumes that the device has been realized.
+    /// QEMU can reset a device before realizing it, for example if it
+    /// is created while the machine is being reset, and realize itself
+    /// may reset the device before it is marked as realized.  If true,
+    /// debug builds check that `COLD_RESET` only runs on realized devices.
+    const COLD_RESET_REQUIRES_REALIZE: bool = false;
+}
+
+impl<T: DeviceImpl> DeviceImplExt for T {}
+
+/// Helpers built on the hooks in `DeviceImpl`, for use by the device
+/// itself and by subclasses of Device.
+pub trait DeviceImplExt: DeviceImpl {
+    /// Check `REALIZE_REQUIRES_BQL`, run `APPLY_DEFAULT_PROPS` and
+    /// `TRY_INIT`, and then run `REALIZE`.
+    fn apply_default_props_and_realize(&self) -> crate::Result<()> {
+        if Self::REALIZE_REQUIRES_BQL {
+            assert_bql_locked(Self::TYPE, "realized");
+        }
+        if let Some(f) = Self::APPLY_DEFAULT_PROPS {
+            f(self)?;
+        }
+        self.check_required_links()?;
+        if let Some(f) = Self::TRY_INIT {
+            f(self)?;
+        }
+        match Self::REALIZE {
+            Some(f) => f(self),
+            None => Ok(()),
+        }
+    }
+
+    /// Check `COLD_RESET_REQUIRES_REALIZE` and run `COLD_RESET`.
+    fn run_cold_reset(&self) {
+        debug_assert!(
+            !Self::COLD_RESET_REQUIRES_REALIZE || self.is_realized(),
+            "device {} reset before it was realized",
+            Self::TYPE.to_string_lossy()
+        );
+        if let Some(f) = Self::COLD_RESET {
+            f(self);
+        }
+    }
+
+    /// Fail if a link property declared with `@required` was not set.
+    fn check_required_links(&self) -> crate::Result<()> {
+        let device = self.upcast::<DeviceState>();
+        let mut prop = device.device_class().properties;
+        // SAFETY: the table is terminated by END_OF_LIST, and the offset
+        // of a link property points to an Option<Owned<_>> within self
+        unsafe {
+            while !(*prop).name.is_null() {
+                if (*prop).required {
+                    let this: *const Self = self;
+                    let link: &Option<Owned<Object>> =
+                        &*this.cast::<u8>().add((*prop).offset).cast();
+                    if link.is_none() {
+                        let name = CStr::from_ptr((*prop).name).to_string_lossy();
+                        return Err(Error::from(
+                            format!("property '{}' is required", name).as_str(),
+                        ));
+                    }
+                }
+                prop = prop.add(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Create `n` unnamed GPIO inputs, which call `GPIO_IN` when their
+    /// level changes.  Other devices can then be connected to them with
+    /// [`DeviceMethods::connect_gpio_out`](crate::DeviceMethods::connect_gpio_out).
+    /// Usually called from `INSTANCE_INIT`.
+    fn init_gpio_in(&self, n: usize) {
+        unsafe extern "C" fn rust_gpio_in<T: DeviceImpl>(
+            opaque: *mut c_void,
+            line: c_int,
+            level: c_int,
+        ) {
+            let f = T::GPIO_IN.unwrap();
+            callback::call_opaque(opaque, |dev| f(dev, line as u32, level != 0))
+        }
+
+        assert!(Self::GPIO_IN.is_some(), "GPIO inputs require GPIO_IN");
+        let n = c_int::try_from(n).expect("too many GPIO lines");
+        // SAFETY: QEMU passes the device as the opaque pointer of the
+        // handler, and rust_gpio_in::<Self> expects a Self
+        unsafe {
+            let dev = self.unsafe_cast::<DeviceState>();
+            qdev_init_gpio_in(dev.as_mut_ptr(), Some(rust_gpio_in::<Self>), n);
+        }
+    }
 }
 
 impl DeviceClass {
     pub fn class_init<T: DeviceImpl>(&mut self) {
+        self.init_callbacks::<T>();
+
+        // SAFETY: the property table is static and terminated by
+        // Property::END_OF_LIST.  Registering the properties with QOM
+        // also makes them subject to -global.
+        unsafe {
+            let props = match T::DYNAMIC_PROPERTIES {
+                None => <T as DeviceTypeImpl>::properties(),
+                Some(f) => Property::concat(<T as DeviceTypeImpl>::properties(), f()),
+            };
+            let props = Property::hook_setters(props);
+            device_class_set_props(self, props);
+            Property::describe(&mut self.oc, props);
+        }
+        if let Some(f) = T::VMSTATE {
+            self.vmsd = f();
+        }
+
+        // Now initialize the ObjectClass from the ObjectImpl.
+        self.oc.class_init::<T>();
+    }
+
+    /// Build the class of `T` as `class_init` would, starting from a
+    /// zeroed `DeviceClass` instead of the superclass, but only fill in
+    /// the callbacks.  Like the class of `TYPE_DEVICE`, the starting point
+    /// is hot-pluggable.  This needs neither `type_register` nor the QOM
+    /// class hierarchy, so that tests can check which callbacks a device
+    /// provides.
+    pub fn test_class_init<T: DeviceImpl>() -> DeviceClass {
+        let mut dc = DeviceClass::zeroed();
+        dc.hotpluggable = true;
+        dc.init_callbacks::<T>();
+        dc.oc.init_callbacks::<T>();
+        dc
+    }
+
+    /// Fill in the function pointers of the class from the `DeviceImpl`.
+    fn init_callbacks<T: DeviceImpl>(&mut self) {
         unsafe extern "C" fn rust_cold_reset<T: DeviceImpl>(obj: *mut DeviceState) {
-            let f = T::COLD_RESET.unwrap();
-            f((&*obj).unsafe_cast::<T>())
+            callback::call(obj, T::run_cold_reset)
         }
         self.cold_reset = T::COLD_RESET.map(|_| rust_cold_reset::<T> as _);
 
+        unsafe extern "C" fn rust_reset_hold<T: DeviceImpl>(
+            obj: *mut Object,
+            reset_type: bindings::ResetType,
+        ) {
+            let f = T::RESET_HOLD.unwrap();
+            // Other reset types only apply to CPUs
+            let reset_type = ResetType::try_from(reset_type).unwrap_or(ResetType::Cold);
+            callback::call(obj, |dev| f(dev, reset_type))
+        }
+        self.reset_hold = T::RESET_HOLD.map(|_| rust_reset_hold::<T> as _);
+
         unsafe extern "C" fn rust_realize<T: DeviceImpl>(
             obj: *mut DeviceState,
             errp: *mut *mut bindings::Error,
         ) {
-            let f = T::REALIZE.unwrap();
-            let result = f((&*obj).unsafe_cast::<T>());
+            let result = callback::call(obj, T::apply_default_props_and_realize);
             Error::ok_or_propagate(result, errp);
         }
-        self.realize = T::REALIZE.map(|_| rust_realize::<T> as _);
+        if T::REALIZE.is_some()
+            || T::APPLY_DEFAULT_PROPS.is_some()
+            || T::TRY_INIT.is_some()
+            || T::DYNAMIC_PROPERTIES.is_some()
+            // SAFETY: the property table is static and terminated by
+            // Property::END_OF_LIST
+            || unsafe { Property::any_required(<T as DeviceTypeImpl>::properties()) }
+        {
+            self.realize = Some(rust_realize::<T>);
+        }
 
         unsafe extern "C" fn rust_unrealize<T: DeviceImpl>(obj: *mut DeviceState) {
-            let f = T::UNREALIZE.unwrap();
-            f((&*obj).unsafe_cast::<T>())
+            callback::call(obj, T::UNREALIZE.unwrap())
         }
         self.unrealize = T::UNREALIZE.map(|_| rust_unrealize::<T> as _);
 
-        self.properties = <T as DeviceTypeImpl>::properties();
+        // The default comes from the superclass
+        if !T::HOTPLUGGABLE {
+            self.hotpluggable = false;
+        }
+    }
+}
 
-        // Now initialize the ObjectClass from the ObjectImpl.
-        self.oc.class_init::<T>();
+/// Register the device type `T`.  This is the same as
+/// [`register_type`](crate::qom::object_impl::register_type), but also
+/// checks that `T` is a device and creates its `MMIO_REGIONS` when an
+/// instance is initialized.
+pub fn register_device_type<T: DeviceImpl>() -> crate::Result<()> {
+    unsafe extern "C" fn rust_device_instance_init<T: DeviceImpl>(obj: *mut c_void) {
+        instance_init::<T>(obj);
+        let dev: &T = &*obj.cast();
+        for region in T::MMIO_REGIONS {
+            // The owner keeps the region alive
+            let mr = MemoryRegion::io(dev, region);
+            if let Some(sbd) = dev.dynamic_cast::<SysBusDevice>() {
+                sbd.init_mmio(&mr);
+            }
+        }
+    }
+
+    if T::MMIO_REGIONS.is_empty() {
+        register_type::<T>()
+    } else {
+        register_type_with_init::<T>(Some(rust_device_instance_init::<T>))
     }
 }
 
@@ -75,6 +351,184 @@ impl DeviceState {
     }
 }
 
+unsafe impl<T: DeviceImpl> SuperclassImpl<T> for DeviceState {
+    const CLASS_INIT: unsafe extern "C" fn(klass: *mut c_void, data: *mut c_void) =
+        DeviceState::rust_class_init::<T>;
+}
+
+impl Property {
+    /// Terminator for a property table, like `DEFINE_PROP_END_OF_LIST()`
+    /// in C.  `qdev_define_type!` adds it automatically.
+    pub const END_OF_LIST: Property = Property {
+        name: ptr::null(),
+        offset: 0,
+        default: 0,
+        info: ptr::null(),
+        set_default: false,
+        description: ptr::null(),
+        link_type: ptr::null(),
+        bitnr: 0,
+        frozen: false,
+        required: false,
+        notify: None,
+        settable: None,
+    };
+
+    /// Return whether `props`, a table terminated by `END_OF_LIST`,
+    /// includes a property declared with `@required`.
+    unsafe fn any_required(mut props: *const Property) -> bool {
+        while !(*props).name.is_null() {
+            if (*props).required {
+                return true;
+            }
+            props = props.add(1);
+        }
+        false
+    }
+
+    /// Build a property table with the contents of `props`, a table
+    /// terminated by `END_OF_LIST`, followed by `more`.  Classes are never
+    /// freed, and neither is the table.
+    unsafe fn concat(mut props: *const Property, more: Vec<Property>) -> *const Property {
+        let mut all = Vec::new();
+        while !(*props).name.is_null() {
+            all.push(ptr::read(props));
+            props = props.add(1);
+        }
+        all.extend(more);
+        all.push(Property::END_OF_LIST);
+        Box::leak(all.into_boxed_slice()).as_ptr()
+    }
+
+    /// Set the description of the class properties that `oc` created for
+    /// `props`, a table terminated by `END_OF_LIST`, if the `Property`
+    /// has one.  `-device help` shows the description of the class
+    /// property, which otherwise comes from the `PropertyInfo`.
+    unsafe fn describe(oc: &mut ObjectClass, mut props: *const Property) {
+        while !(*props).name.is_null() {
+            if !(*props).description.is_null() {
+                object_class_property_set_description(oc, (*props).name, (*props).description);
+            }
+            props = props.add(1);
+        }
+    }
+
+    /// Return a copy of `props`, a table terminated by `END_OF_LIST`,
+    /// where the `info` of properties declared with `@frozen`, `@notify`
+    /// or `@settable` is replaced by a [`HookedPropertyInfo`].  If there are no such
+    /// properties, return `props` itself.  Like for `concat`, the table
+    /// is never freed.
+    unsafe fn hook_setters(props: *const Property) -> *const Property {
+        let mut all = Vec::new();
+        let mut p = props;
+        while !(*p).name.is_null() {
+            all.push(ptr::read(p));
+            p = p.add(1);
+        }
+        assert!(
+            all.iter()
+                .all(|prop| !prop.required || !prop.link_type.is_null()),
+            "only link properties can be @required"
+        );
+        if !all.iter().any(HookedPropertyInfo::needed) {
+            return props;
+        }
+        for prop in all
+            .iter_mut()
+            .filter(|prop| HookedPropertyInfo::needed(prop))
+        {
+            prop.info = HookedPropertyInfo::wrap(prop);
+        }
+        all.push(Property::END_OF_LIST);
+        Box::leak(all.into_boxed_slice()).as_ptr()
+    }
+}
+
+/// The `PropertyInfo` of a property declared with `@frozen`, `@notify`
+/// or `@settable`, which forwards everything to the original
+/// `PropertyInfo` except `set`.  The setter refuses to set a frozen
+/// property on a realized device or a property whose guard returns
+/// false, and calls the notifier after the original setter succeeds.
+/// Because the wrapper belongs to the class, the hooks also run for
+/// subclasses.
+#[repr(C)]
+struct HookedPropertyInfo {
+    info: PropertyInfo,
+    orig: &'static PropertyInfo,
+    frozen: bool,
+    notify: Option<unsafe extern "C" fn(*mut Object)>,
+    settable: Option<unsafe extern "C" fn(*mut Object) -> bool>,
+}
+
+impl HookedPropertyInfo {
+    fn needed(prop: &Property) -> bool {
+        prop.frozen || prop.notify.is_some() || prop.settable.is_some()
+    }
+
+    /// # Safety
+    ///
+    /// `prop.info` must be valid for the rest of the program
+    unsafe fn wrap(prop: &Property) -> *const PropertyInfo {
+        let orig: &'static PropertyInfo = &*prop.info;
+        let hooked = Box::leak(Box::new(HookedPropertyInfo {
+            info: PropertyInfo {
+                set: Some(Self::set),
+                ..*orig
+            },
+            orig,
+            frozen: prop.frozen,
+            notify: prop.notify,
+            settable: prop.settable,
+        }));
+        &hooked.info
+    }
+
+    unsafe extern "C" fn set(
+        obj: *mut Object,
+        v: *mut Visitor,
+        name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let prop: &Property = &*opaque.cast();
+        let hooked: &HookedPropertyInfo = &*prop.info.cast();
+        let dev: &DeviceState = (&*obj).unsafe_cast();
+        if hooked.frozen && dev.is_realized() {
+            let err = Error::from(
+                format!(
+                    "Attempt to set property '{}' after the device was realized",
+                    CStr::from_ptr(name).to_string_lossy()
+                )
+                .as_str(),
+            );
+            err.propagate(errp);
+            return;
+        }
+        if let Some(settable) = hooked.settable {
+            if !settable(obj) {
+                let err = Error::from(
+                    format!(
+                        "Property {} cannot be set in the current state",
+                        CStr::from_ptr(name).to_string_lossy()
+                    )
+                    .as_str(),
+                );
+                err.propagate(errp);
+                return;
+            }
+        }
+        if let Some(set) = hooked.orig.set {
+            let mut local_err: *mut bindings::Error = ptr::null_mut();
+            set(obj, v, name, opaque, &mut local_err);
+            if !local_err.is_null() {
+                bindings::error_propagate(errp, local_err);
+            } else if let Some(notify) = hooked.notify {
+                notify(obj);
+            }
+        }
+    }
+}
+
 /// Internal information on a Rust-implemented subclass of Device.
 /// Only public because it is used by macros.
 pub unsafe trait DeviceTypeImpl: TypeImpl {
@@ -84,7 +538,23 @@ pub unsafe trait DeviceTypeImpl: TypeImpl {
     // reference statics (for globals defined in C, e.g. qdev_prop_bool)
     // which is unstable (see https://github.com/rust-lang/rust/issues/119618,
     // feature const_refs_to_static)
+    /// Return the properties of the device, terminated by
+    /// `Property::END_OF_LIST`.
     fn properties() -> *const Property;
+
+    /// Return the configuration of the device, i.e. the values of its
+    /// properties.  Unlike the `conf` field, this is also available to
+    /// generic code, such as callbacks shared by several device types.
+    fn conf(&self) -> &Self::Conf {
+        // SAFETY: the trait guarantees that the configuration is at
+        // offset CONF_OFFSET of Self
+        unsafe {
+            &*ptr::addr_of!(*self)
+                .cast::<u8>()
+                .add(Self::CONF_OFFSET)
+                .cast::<Self::Conf>()
+        }
+    }
 }
 
 pub struct QdevPropBool;
@@ -94,25 +564,873 @@ impl QdevPropBool {
     }
 }
 
+/// Signed defaults are stored sign-extended, because the C code reads
+/// them from the `int64_t` member of the union that `default` stands for.
+pub struct QdevPropInt32;
+impl QdevPropInt32 {
+    pub const fn convert(value: &i32) -> u64 {
+        *value as i64 as u64
+    }
+}
+
+pub struct QdevPropInt64;
+impl QdevPropInt64 {
+    pub const fn convert(value: &i64) -> u64 {
+        *value as u64
+    }
+}
+
+pub struct QdevPropUsize;
+impl QdevPropUsize {
+    pub const fn convert(value: &usize) -> u64 {
+        *value as u64
+    }
+}
+
+/// A property value that remembers whether it was set by the user, for
+/// example on the command line or with `-global`.  This lets the
+/// `APPLY_DEFAULT_PROPS` hook of `DeviceImpl` compute a default from
+/// other properties.
+///
+/// The initial value comes from the `ConstDefault` implementation of the
+/// configuration struct; `qdev_prop!(tracked, ...)` does not apply a
+/// default through QOM, because that would count as setting the property.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Tracked<T: Copy> {
+    value: Cell<T>,
+    is_set: Cell<bool>,
+}
+
+impl<T: Copy + ConstDefault> ConstDefault for Tracked<T> {
+    const DEFAULT: Self = Tracked::new(T::DEFAULT);
+}
+
+impl<T: Copy> Tracked<T> {
+    pub const fn new(value: T) -> Self {
+        Tracked {
+            value: Cell::new(value),
+            is_set: Cell::new(false),
+        }
+    }
+
+    /// Return the current value of the property.
+    pub fn get(&self) -> T {
+        self.value.get()
+    }
+
+    /// Return true if the property was set by the user.
+    pub fn is_set(&self) -> bool {
+        self.is_set.get()
+    }
+
+    /// Change the value of the property, unless it was set by the user.
+    pub fn set_default(&self, value: T) {
+        if !self.is_set.get() {
+            self.value.set(value);
+        }
+    }
+}
+
+foreign_enum! {
+    #[repr(u32)]
+    /// The reason for a reset, as passed to the `RESET_HOLD` hook of
+    /// `DeviceImpl`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum ResetType {
+        /// Power-on or system reset
+        Cold = bindings::RESET_TYPE_COLD,
+        /// Reset before loading a snapshot or an incoming migration stream
+        SnapshotLoad = bindings::RESET_TYPE_SNAPSHOT_LOAD,
+        /// Wakeup from a suspended state
+        Wakeup = bindings::RESET_TYPE_WAKEUP,
+    }
+    fallback Cold;
+}
+
+/// Part of the device state that is only available after realize,
+/// because creating it can fail; see `DeviceImpl::TRY_INIT`.
+///
+/// ```ignore
+/// fn try_init(&self) -> Result<()> {
+///     self.state.buffer.get_or_try_init(|| alloc_buffer(self.conf.size))?;
+///     Ok(())
+/// }
+/// ```
+pub struct Deferred<T>(UnsafeCell<Option<T>>);
+
+impl<T> Deferred<T> {
+    pub const fn new() -> Self {
+        Deferred(UnsafeCell::new(None))
+    }
+
+    /// Return the value, or `None` if it was not initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        // SAFETY: the value is only written while it is None, and
+        // therefore while there are no references to it
+        unsafe { (*self.0.get()).as_ref() }
+    }
+
+    /// Return the value, initializing it with `f` if needed.  If `f`
+    /// fails, the value stays uninitialized and the error is returned.
+    pub fn get_or_try_init<F: FnOnce() -> crate::Result<T>>(&self, f: F) -> crate::Result<&T> {
+        if self.get().is_none() {
+            let value = f()?;
+            // SAFETY: the value is None, so there are no references to it;
+            // if f() initialized it reentrantly, the original value is
+            // kept and the new one is dropped
+            unsafe {
+                let slot = &mut *self.0.get();
+                if slot.is_none() {
+                    *slot = Some(value);
+                }
+            }
+        }
+        Ok(self.get().unwrap())
+    }
+}
+
+impl<T> Default for Deferred<T> {
+    fn default() -> Self {
+        Deferred::new()
+    }
+}
+
+impl<T> Deref for Deferred<T> {
+    type Target = T;
+
+    /// Return the value; panics if `TRY_INIT` did not initialize it,
+    /// for example because the device is not realized.
+    fn deref(&self) -> &T {
+        self.get()
+            .expect("Deferred value used before initialization")
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Deferred<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Deferred").field(&self.get()).finish()
+    }
+}
+
+/// Implemented by marker types that `qdev_prop!` generates for
+/// `Tracked` properties.  Only public because it is used by macros.
+pub trait QdevPropTrackedInfo {
+    type Value: Copy;
+
+    /// The `PropertyInfo` that is used to access the value
+    fn info() -> &'static PropertyInfo;
+}
+
+/// Implemented by marker types that `qdev_prop!` generates for the
+/// `@notify` modifier.  Only public because it is used by macros.
+pub trait QdevPropNotify {
+    type Device: IsA<Object>;
+
+    const NOTIFY: fn(&Self::Device);
+
+    /// Call `NOTIFY`; `obj` is a `Device` or an instance of a subclass.
+    unsafe extern "C" fn notify(obj: *mut Object) {
+        callback::call(obj, Self::NOTIFY);
+    }
+}
+
+/// Implemented by marker types that `qdev_prop!` generates for the
+/// `@settable` modifier.  Only public because it is used by macros.
+pub trait QdevPropSettable {
+    type Device: IsA<Object>;
+
+    const SETTABLE: fn(&Self::Device) -> bool;
+
+    /// Call `SETTABLE`; `obj` is a `Device` or an instance of a subclass.
+    unsafe extern "C" fn settable(obj: *mut Object) -> bool {
+        callback::call(obj, Self::SETTABLE)
+    }
+}
+
+pub struct QdevPropTracked;
+impl QdevPropTracked {
+    /// `get` callback for tracked properties
+    pub unsafe extern "C" fn get<I: QdevPropTrackedInfo>(
+        obj: *mut Object,
+        v: *mut Visitor,
+        name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let get = I::info().get.unwrap();
+        get(obj, v, name, opaque, errp);
+    }
+
+    /// `set` callback for tracked properties; after the value is stored,
+    /// it is marked as set by the user.
+    pub unsafe extern "C" fn set<I: QdevPropTrackedInfo>(
+        obj: *mut Object,
+        v: *mut Visitor,
+        name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        let set = I::info().set.unwrap();
+        set(obj, v, name, opaque, &mut err);
+        if err.is_null() {
+            let prop: &Property = &*opaque.cast();
+            let tracked: &Tracked<I::Value> = &*obj.cast::<u8>().add(prop.offset).cast();
+            tracked.is_set.set(true);
+        } else {
+            bindings::error_propagate(errp, err);
+        }
+    }
+}
+
+// The getters of read-only properties are shared with class properties
+pub use crate::qom::object_impl::PropertyGetter as QdevPropGetter;
+pub use crate::qom::object_impl::PropertyRo as QdevPropRo;
+
+pub struct QdevPropFd;
+impl QdevPropFd {
+    /// # Safety
+    ///
+    /// `obj` and `opaque` must be the arguments of a property callback
+    unsafe fn field<'a>(obj: *mut Object, opaque: *mut c_void) -> &'a mut Option<RawFd> {
+        let prop: &Property = &*opaque.cast();
+        &mut *obj.cast::<u8>().add(prop.offset).cast()
+    }
+
+    /// `get` callback for file descriptor properties; returns -1 if
+    /// the property was not set.
+    pub unsafe extern "C" fn get(
+        obj: *mut Object,
+        v: *mut Visitor,
+        name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let fd = Self::field(obj, opaque).unwrap_or(-1);
+        let mut v = OutputVisitor::from_raw(v);
+        let result = v.visit_i64(Some(CStr::from_ptr(name)), fd.into());
+        Error::ok_or_propagate(result, errp);
+    }
+
+    /// `set` callback for file descriptor properties.  The value is
+    /// either a number or the name of a file descriptor that was passed
+    /// to the monitor with `getfd`; the device becomes the owner of the
+    /// file descriptor.
+    pub unsafe extern "C" fn set(
+        obj: *mut Object,
+        v: *mut Visitor,
+        name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let mut fdname: *mut c_char = ptr::null_mut();
+        if !visit_type_str(v, name, &mut fdname, errp) {
+            return;
+        }
+        let fd = monitor_fd_param(monitor_cur(), fdname, errp);
+        g_free(fdname.cast());
+        if fd < 0 {
+            return;
+        }
+        if let Some(old) = Self::field(obj, opaque).replace(fd) {
+            libc::close(old);
+        }
+    }
+
+    /// `release` callback for file descriptor properties, which closes
+    /// the file descriptor when the device is finalized.
+    pub unsafe extern "C" fn release(obj: *mut Object, _name: *const c_char, opaque: *mut c_void) {
+        if let Some(fd) = Self::field(obj, opaque).take() {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Build a `Property` for a field of a device's configuration struct.
+///
+/// The basic form is `qdev_prop!(kind, name, Type, field)`.
+/// `field` can also be a path such as `dma.window`, to refer to a field
+/// of a nested `#[repr(C)]` struct within the configuration.
+/// The value of the property defaults to that of `field` in the
+/// `ConstDefault` implementation of the configuration struct.  The
+/// default can also be written out, as in `qdev_prop!(kind, name, Type,
+/// default, field)`; compilation then fails if it differs from the one
+/// in `ConstDefault`.
+/// It can be followed by a semicolon and a list of modifiers:
+///
+/// - `@description desc` sets the help text shown by `-device help`
+///   (`desc` is a `&CStr`)
+/// - `@frozen` makes attempts to set the property fail once the device
+///   is realized, for configuration that cannot change afterwards
+/// - `@notify f` calls `f`, a `fn(&Type)`, whenever the property is set
+///   successfully, for example with `qom-set`; unlike
+///   [`on_property_change`](crate::ObjectMethods::on_property_change),
+///   it works on the properties of the class
+/// - `@settable f` only allows setting the property if `f`, a
+///   `fn(&Type) -> bool`, returns true; it is the class counterpart of
+///   [`set_property_guard`](crate::ObjectMethods::set_property_guard)
+/// - `@required`, for link properties, makes realize fail if the link
+///   is not set
+///
+/// `kind` is `bool` for a `bool` field, `int32` or `int64` for an `i32`
+/// or `i64` field, or `usize` for a `usize` field (a `size_t` in C).
+/// `usize` properties accept suffixes such as `k` or `M` on the command
+/// line; on 32-bit hosts, values that do not fit in 32 bits are rejected.
+///
+/// Read-only properties use `qdev_prop!(ro, name, Type, ValueType, getter)`,
+/// where `getter` is a `fn(&Type) -> ValueType` that is called whenever
+/// the property is read, and `ValueType` implements `VisitOutput`.
+/// Attempts to set the property fail.  `qdev_prop!(ro_uint64, name, Type,
+/// getter)` is a shortcut for `u64` properties.
+///
+/// Link properties use `qdev_prop!(link, name, Type, LinkType, field)`
+/// instead, where `field` is an `Option<Owned<LinkType>>`.  The command
+/// line accepts a QOM path, which is resolved when the property is set;
+/// the reference is owned by the field and released when the device is
+/// finalized.  A path that does not resolve to an object of type
+/// `LinkType` is rejected when the property is set.  If the device
+/// cannot work without the link, add the `@required` modifier: realizing
+/// the device then fails, instead of running the realize hooks, if the
+/// link was not set.
+///
+/// `qdev_prop!(bool_bit, name, Type, field, bit, default)` declares a
+/// boolean property that is stored in bit number `bit` of a `u32` field,
+/// for example a control register.  Several properties can refer to
+/// different bits of the same field.
+///
+/// `qdev_prop!(uuid, name, Type, field)` declares a property for a
+/// [`Uuid`](crate::Uuid) field.  The command line accepts the canonical
+/// hyphenated format; the default is the nil UUID.
+///
+/// `qdev_prop!(fd, name, Type, field)` declares a property for an
+/// `Option<RawFd>` field.  The command line accepts a file descriptor
+/// number or the name of a file descriptor passed with the `getfd`
+/// monitor command; an unknown name is rejected when the property is
+/// set.  The device owns the file descriptor, which is closed when the
+/// property is set again or the device is finalized; use `dup` to keep
+/// it longer.
+///
+/// `qdev_prop!(tracked, kind, name, Type, ValueType, field)` declares a
+/// property whose field is a `Tracked<ValueType>`; `kind` is `bool` or
+/// `uint32` and selects how the value is parsed.
+///
+/// Subclasses of `VirtIODevice` use `qdev_prop!(virtio_features, name,
+/// Type)` for the bitmask of features that the device offers to the guest.
+/// The default value is `VirtioDeviceImpl::HOST_FEATURES`.
 #[macro_export]
 macro_rules! qdev_prop {
+    // The C PropertyInfo for each kind.  These are the only references to
+    // the qdev_prop_* statics; adding a kind only needs a rule here.
+    (@info bit) => { unsafe { &$crate::bindings::qdev_prop_bit } };
+    (@info bool) => { unsafe { &$crate::bindings::qdev_prop_bool } };
+    (@info int32) => { unsafe { &$crate::bindings::qdev_prop_int32 } };
+    (@info int64) => { unsafe { &$crate::bindings::qdev_prop_int64 } };
+    (@info link) => { unsafe { &$crate::bindings::qdev_prop_link } };
+    (@info uint32) => { unsafe { &$crate::bindings::qdev_prop_uint32 } };
+    (@info uint64) => { unsafe { &$crate::bindings::qdev_prop_uint64 } };
+    (@info usize) => { unsafe { &$crate::bindings::qdev_prop_usize } };
+    (@info uuid) => { unsafe { &$crate::bindings::qdev_prop_uuid } };
+
+    // The value stored in Property::default for each kind
+    (@convert bool, $value:expr) => {
+        $crate::hw::core::device_impl::QdevPropBool::convert(&($value))
+    };
+    (@convert int32, $value:expr) => {
+        $crate::hw::core::device_impl::QdevPropInt32::convert(&($value))
+    };
+    (@convert int64, $value:expr) => {
+        $crate::hw::core::device_impl::QdevPropInt64::convert(&($value))
+    };
+    (@convert usize, $value:expr) => {
+        $crate::hw::core::device_impl::QdevPropUsize::convert(&($value))
+    };
+
+    // Read the value of a field in the ConstDefault of the configuration
+    (@const_default $type:ty, $($field:ident).+) => {{
+        // Use ManuallyDrop and a raw pointer, because the configuration
+        // might include fields that cannot be dropped at compile time (such
+        // as links) or that have interior mutability (such as Tracked)
+        let default = std::mem::ManuallyDrop::new(
+            <$crate::conf_type!($type) as ConstDefault>::DEFAULT
+        );
+        // SAFETY: ManuallyDrop<T> has the same layout as T
+        unsafe {
+            (*std::ptr::addr_of!(default).cast::<$crate::conf_type!($type)>()).$($field).+
+        }
+    }};
+
     (@internal bool, $name:expr, $default:expr, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: qdev_prop!(@convert bool, $default),
+            info: qdev_prop!(@info bool),
+            set_default: true,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal int32, $name:expr, $default:expr, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: qdev_prop!(@convert int32, $default),
+            info: qdev_prop!(@info int32),
+            set_default: true,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal int64, $name:expr, $default:expr, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: qdev_prop!(@convert int64, $default),
+            info: qdev_prop!(@info int64),
+            set_default: true,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal usize, $name:expr, $default:expr, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: qdev_prop!(@convert usize, $default),
+            info: qdev_prop!(@info usize),
+            set_default: true,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal link, $name:expr, $link:ty, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: 0,
+            info: qdev_prop!(@info link),
+            set_default: false,
+            description: std::ptr::null(),
+            link_type: <$link as $crate::ObjectType>::TYPE.as_ptr(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal ro, $name:expr, $value:ty, $getter:ty) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: 0,
+            default: 0,
+            info: &$crate::PropertyInfo {
+                name: <$value as $crate::VisitOutput>::TYPE.as_ptr(),
+                description: std::ptr::null(),
+                enum_table: std::ptr::null(),
+                realized_set_allowed: false,
+                print: None,
+                set_default_value: None,
+                create: None,
+                get: Some($crate::hw::core::device_impl::QdevPropRo::get::<$getter>),
+                set: None,
+                release: None,
+            },
+            set_default: false,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal virtio_features, $name:expr, $default:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $crate::offset_of!($crate::bindings::VirtIODevice, host_features),
+            default: $default,
+            info: qdev_prop!(@info uint64),
+            set_default: true,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal tracked, $name:expr, $inner:ident, $info:ty, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: 0,
+            info: &$crate::PropertyInfo {
+                name: concat!(stringify!($inner), "\0").as_ptr().cast(),
+                description: std::ptr::null(),
+                enum_table: std::ptr::null(),
+                realized_set_allowed: false,
+                print: None,
+                set_default_value: None,
+                create: None,
+                get: Some($crate::hw::core::device_impl::QdevPropTracked::get::<$info>),
+                set: Some($crate::hw::core::device_impl::QdevPropTracked::set::<$info>),
+                release: None,
+            },
+            set_default: false,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal bool_bit, $name:expr, $bit:expr, $default:expr, $offset:expr) => {
         $crate::Property {
             name: $name.as_ptr(),
             offset: $offset,
             default: $crate::hw::core::device_impl::QdevPropBool::convert(&($default)),
-            info: unsafe { &$crate::bindings::qdev_prop_bool },
+            info: qdev_prop!(@info bit),
+            set_default: true,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: $bit,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal uuid, $name:expr, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: 0,
+            info: qdev_prop!(@info uuid),
+            set_default: false,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@internal fd, $name:expr, $offset:expr) => {
+        $crate::Property {
+            name: $name.as_ptr(),
+            offset: $offset,
+            default: 0,
+            info: &$crate::PropertyInfo {
+                name: "fd\0".as_ptr().cast(),
+                description: std::ptr::null(),
+                enum_table: std::ptr::null(),
+                realized_set_allowed: false,
+                print: None,
+                set_default_value: None,
+                create: None,
+                get: Some($crate::hw::core::device_impl::QdevPropFd::get),
+                set: Some($crate::hw::core::device_impl::QdevPropFd::set),
+                release: Some($crate::hw::core::device_impl::QdevPropFd::release),
+            },
+            set_default: false,
+            description: std::ptr::null(),
+            link_type: std::ptr::null(),
+            bitnr: 0,
+            frozen: false,
+            required: false,
+            notify: None,
+            settable: None,
+        }
+    };
+
+    (@modifier $type:ty, $prop:expr, description, $desc:expr) => {
+        $crate::Property {
+            description: $desc.as_ptr(),
+            ..$prop
+        }
+    };
+
+    (@modifier $type:ty, $prop:expr, frozen) => {
+        $crate::Property {
+            frozen: true,
+            ..$prop
+        }
+    };
+
+    (@modifier $type:ty, $prop:expr, required) => {
+        $crate::Property {
+            required: true,
+            ..$prop
+        }
+    };
+
+    (@modifier $type:ty, $prop:expr, notify, $notify:expr) => {{
+        struct Notify;
+        impl $crate::hw::core::device_impl::QdevPropNotify for Notify {
+            type Device = $type;
+
+            const NOTIFY: fn(&$type) = $notify;
+        }
+        $crate::Property {
+            notify: Some(<Notify as $crate::hw::core::device_impl::QdevPropNotify>::notify),
+            ..$prop
+        }
+    }};
+
+    (@modifier $type:ty, $prop:expr, settable, $settable:expr) => {{
+        struct Settable;
+        impl $crate::hw::core::device_impl::QdevPropSettable for Settable {
+            type Device = $type;
+
+            const SETTABLE: fn(&$type) -> bool = $settable;
+        }
+        $crate::Property {
+            settable: Some(
+                <Settable as $crate::hw::core::device_impl::QdevPropSettable>::settable,
+            ),
+            ..$prop
         }
+    }};
+
+    (ro, $name:expr, $type:ty, $value:ty, $getter:expr
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        struct Getter;
+        impl $crate::hw::core::device_impl::QdevPropGetter for Getter {
+            type Device = $type;
+            type Value = $value;
+
+            const GET: fn(&$type) -> $value = $getter;
+        }
+        let prop = qdev_prop!(@internal ro, $name, $value, Getter);
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    (ro_uint64, $name:expr, $type:ty, $getter:expr
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {
+        qdev_prop!(ro, $name, $type, u64, $getter $(; $(@$modifier $($arg)?),+)?)
     };
 
+    (virtio_features, $name:expr, $type:ty
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        let prop = qdev_prop!(@internal
+            virtio_features,
+            $name,
+            <$type as $crate::hw::virtio::device_impl::VirtioDeviceImpl>::HOST_FEATURES
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    (tracked, $inner:ident, $name:expr, $type:ty, $value:ty, $($field:ident).+
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        // Typecheck the field, since there is no default value to do it
+        #[allow(dead_code)]
+        fn check(conf: &$crate::conf_type!($type)) -> &$crate::hw::core::device_impl::Tracked<$value> {
+            &conf.$($field).+
+        }
+        struct Info;
+        impl $crate::hw::core::device_impl::QdevPropTrackedInfo for Info {
+            type Value = $value;
+
+            fn info() -> &'static $crate::PropertyInfo {
+                qdev_prop!(@info $inner)
+            }
+        }
+        let prop = qdev_prop!(@internal
+            tracked,
+            $name,
+            $inner,
+            Info,
+            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of_path!($crate::conf_type!($type), $($field).+)
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    (bool_bit, $name:expr, $type:ty, $($field:ident).+, $bit:expr, $default:expr
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        // Typecheck the field, since the default value refers to a single bit
+        #[allow(dead_code)]
+        fn check(conf: &$crate::conf_type!($type)) -> &u32 {
+            &conf.$($field).+
+        }
+        const _: () = assert!($bit < 32, "bit number out of range");
+        let prop = qdev_prop!(@internal
+            bool_bit,
+            $name,
+            $bit,
+            $default,
+            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of_path!($crate::conf_type!($type), $($field).+)
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    (uuid, $name:expr, $type:ty, $($field:ident).+
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        // Typecheck the field, since there is no default value to do it
+        #[allow(dead_code)]
+        fn check(conf: &$crate::conf_type!($type)) -> &$crate::Uuid {
+            &conf.$($field).+
+        }
+        let prop = qdev_prop!(@internal
+            uuid,
+            $name,
+            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of_path!($crate::conf_type!($type), $($field).+)
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    (fd, $name:expr, $type:ty, $($field:ident).+
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        // Typecheck the field, since there is no default value to do it
+        #[allow(dead_code)]
+        fn check(conf: &$crate::conf_type!($type)) -> &Option<std::os::unix::io::RawFd> {
+            &conf.$($field).+
+        }
+        let prop = qdev_prop!(@internal
+            fd,
+            $name,
+            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of_path!($crate::conf_type!($type), $($field).+)
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    (link, $name:expr, $type:ty, $link:ty, $($field:ident).+
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        // Typecheck the field, since there is no default value to do it
+        #[allow(dead_code)]
+        fn check(conf: &$crate::conf_type!($type)) -> &Option<$crate::Owned<$link>> {
+            &conf.$($field).+
+        }
+        let prop = qdev_prop!(@internal
+            link,
+            $name,
+            $link,
+            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of_path!($crate::conf_type!($type), $($field).+)
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+
+    ($kind:tt, $name:expr, $type:ty, $default:expr, $($field:ident).+
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        const _: () = assert!(
+            qdev_prop!(@convert $kind, $default)
+                == qdev_prop!(@convert $kind, qdev_prop!(@const_default $type, $($field).+)),
+            "the default of the property differs from ConstDefault"
+        );
+        qdev_prop!($kind, $name, $type, $($field).+ $(; $(@$modifier $($arg)?),+)?)
+    }};
+
     // Replace field with typechecking expression and offset
-    ($kind:tt, $name:expr, $type:ty, $default:expr, $field:ident) => {
-        qdev_prop!(@internal
+    ($kind:tt, $name:expr, $type:ty, $($field:ident).+
+     $(; $(@$modifier:ident $($arg:expr)?),+)?) => {{
+        let prop = qdev_prop!(@internal
             $kind,
             $name,
-            (<$crate::conf_type!($type) as ConstDefault>::DEFAULT).$field,
-            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of!($crate::conf_type!($type), $field)
-        )
+            qdev_prop!(@const_default $type, $($field).+),
+            <$type as $crate::DeviceTypeImpl>::CONF_OFFSET + $crate::offset_of_path!($crate::conf_type!($type), $($field).+)
+        );
+        $($(let prop = qdev_prop!(@modifier $type, prop, $modifier $(,$arg)?);)+)?
+        prop
+    }};
+}
+
+/// Implement `DeviceImpl` for a type, pointing each listed hook to the
+/// inherent method of the same name:
+///
+/// ```ignore
+/// device_impl!(MyDevice: realize, cold_reset);
+/// ```
+///
+/// is the same as
+///
+/// ```ignore
+/// impl DeviceImpl for MyDevice {
+///     const REALIZE: Option<fn(&MyDevice) -> Result<()>> = Some(MyDevice::realize);
+///     const COLD_RESET: Option<fn(&MyDevice)> = Some(MyDevice::cold_reset);
+/// }
+/// ```
+///
+/// Hooks that are not listed keep their default value of `None`.
+#[macro_export]
+macro_rules! device_impl {
+    (@hook $type:ty, realize) => {
+        const REALIZE: Option<fn(&$type) -> $crate::Result<()>> = Some(<$type>::realize);
+    };
+    (@hook $type:ty, unrealize) => {
+        const UNREALIZE: Option<fn(&$type)> = Some(<$type>::unrealize);
+    };
+    (@hook $type:ty, cold_reset) => {
+        const COLD_RESET: Option<fn(&$type)> = Some(<$type>::cold_reset);
+    };
+    (@hook $type:ty, gpio_in) => {
+        const GPIO_IN: Option<fn(&$type, u32, bool)> = Some(<$type>::gpio_in);
+    };
+    (@hook $type:ty, try_init) => {
+        const TRY_INIT: Option<fn(&$type) -> $crate::Result<()>> = Some(<$type>::try_init);
+    };
+    (@hook $type:ty, reset_hold) => {
+        const RESET_HOLD: Option<fn(&$type, $crate::ResetType)> = Some(<$type>::reset_hold);
+    };
+    (@hook $type:ty, apply_default_props) => {
+        const APPLY_DEFAULT_PROPS: Option<fn(&$type) -> $crate::Result<()>> =
+            Some(<$type>::apply_default_props);
+    };
+    (@hook $type:ty, dynamic_properties) => {
+        const DYNAMIC_PROPERTIES: Option<fn() -> Vec<$crate::Property>> =
+            Some(<$type>::dynamic_properties);
+    };
+    (@hook $type:ty, vmstate) => {
+        const VMSTATE: Option<fn() -> *const $crate::VMStateDescription> =
+            Some(<$type>::vmstate);
+    };
+
+    ($type:ty $(: $($hook:ident),* $(,)?)?) => {
+        impl $crate::DeviceImpl for $type {
+            $($($crate::device_impl!(@hook $type, $hook);)*)?
+        }
     };
 }
 
@@ -120,21 +1438,120 @@ macro_rules! qdev_prop {
 macro_rules! qdev_define_type {
     ($name:expr, $struct:ident, $conf_ty:ty, $state_ty:ty;
      @extends $super:ty $(,$supers:ty)*;
-     @properties [$($props: expr),+]) => {
+     @properties [$($props: expr),* $(,)?]) => {
         $crate::qom_define_type!(
             $name, $struct, $conf_ty, $state_ty;
-            @extends $super $(,$supers)*, $crate::Object);
+            @extends $super $(,$supers)*);
 
         unsafe impl $crate::DeviceTypeImpl for $struct {
             const CONF_OFFSET: usize = $crate::offset_of!($struct, conf);
 
             fn properties() -> *const $crate::Property {
-                static mut PROPERTIES: &'static [$crate::Property] = &[$($props),+];
+                static mut PROPERTIES: &'static [$crate::Property] =
+                    &[$($props,)* $crate::Property::END_OF_LIST];
 
                 // SAFETY: The only reference is created here; mut is needed to refer to
                 // &qdev_prop_xxx.
-                unsafe { PROPERTIES.as_ptr() }
+                unsafe { (*std::ptr::addr_of!(PROPERTIES)).as_ptr() }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_default() {
+        assert_eq!(QdevPropInt32::convert(&-1), u64::MAX);
+        assert_eq!(QdevPropInt32::convert(&-5) as i64, -5);
+        assert_eq!(QdevPropInt32::convert(&i32::MAX), 0x7fff_ffff);
+        assert_eq!(QdevPropInt64::convert(&i64::MIN) as i64, i64::MIN);
+    }
+
+    #[cfg(all(debug_assertions, feature = "check-bql"))]
+    mod bql {
+        use crate::bindings::stubs::stub_free;
+        use crate::{DeviceImpl, DeviceImplExt, ObjectImpl};
+        use cstr::cstr;
+        use std::mem::MaybeUninit;
+        use std::ptr::addr_of_mut;
+
+        qdev_define_type!(
+            cstr!("test-bql-device"),
+            TestBqlDevice,
+            (),
+            ();
+            @extends crate::DeviceState;
+            @properties []
+        );
+
+        impl ObjectImpl for TestBqlDevice {}
+        impl DeviceImpl for TestBqlDevice {}
+
+        #[test]
+        #[should_panic(
+            expected = "object of type test-bql-device realized by a thread that does not hold the BQL"
+        )]
+        fn test_realize_without_bql() {
+            let mut dev = MaybeUninit::<TestBqlDevice>::zeroed();
+            // SAFETY: all other fields of the device are valid when zeroed
+            let dev = unsafe {
+                let obj = dev.as_mut_ptr().cast::<crate::Object>();
+                addr_of_mut!((*obj).free).write(stub_free);
+                dev.assume_init()
+            };
+            let _ = dev.apply_default_props_and_realize();
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    mod reset {
+        use crate::bindings::stubs::stub_free;
+        use crate::{DeviceImpl, DeviceImplExt, ObjectImpl};
+        use cstr::cstr;
+        use std::mem::MaybeUninit;
+        use std::ptr::addr_of_mut;
+
+        qdev_define_type!(
+            cstr!("test-reset-device"),
+            TestResetDevice,
+            (),
+            ();
+            @extends crate::DeviceState;
+            @properties []
+        );
+
+        impl ObjectImpl for TestResetDevice {}
+        impl DeviceImpl for TestResetDevice {
+            const COLD_RESET: Option<fn(&TestResetDevice)> = Some(|_| {});
+            const COLD_RESET_REQUIRES_REALIZE: bool = true;
+        }
+
+        #[test]
+        #[should_panic(expected = "device test-reset-device reset before it was realized")]
+        fn test_reset_before_realize() {
+            let mut dev = MaybeUninit::<TestResetDevice>::zeroed();
+            // SAFETY: all other fields of the device are valid when zeroed
+            let dev = unsafe {
+                let obj = dev.as_mut_ptr().cast::<crate::Object>();
+                addr_of_mut!((*obj).free).write(stub_free);
+                dev.assume_init()
+            };
+            dev.run_cold_reset();
+        }
+    }
+
+    #[test]
+    fn test_deferred() {
+        let d = Deferred::<Vec<u8>>::new();
+        assert!(d.get().is_none());
+        assert!(d.get_or_try_init(|| Err(Error::from("no memory"))).is_err());
+        assert!(d.get().is_none());
+        assert_eq!(d.get_or_try_init(|| Ok(vec![1, 2])).unwrap().len(), 2);
+        // Already initialized, f is not called
+        assert_eq!(d.get_or_try_init(|| Ok(vec![])).unwrap().len(), 2);
+        assert_eq!(d[1], 2);
+    }
+}
@@ -0,0 +1,190 @@
+//! Bindings for interrupt lines
+
+use std::cell::Cell;
+use std::ptr;
+
+use libc::c_int;
+
+use crate::bindings::qdev_init_gpio_out;
+use crate::bindings::qemu_set_irq;
+use crate::bindings::sysbus_init_irq;
+use crate::bindings::DeviceState;
+use crate::bindings::IRQState;
+use crate::bindings::SysBusDevice;
+
+use crate::qom::refs::IsA;
+use crate::qom::refs::ObjectCast;
+
+/// An output interrupt line of a device, corresponding to a C `qemu_irq`.
+///
+/// The line is connected by board code, which writes to it from C;
+/// until then it is NULL and changing its level does nothing.  Therefore
+/// an `IrqHandle` must stay at the same address after it has been
+/// exposed to C, for example with [`IrqBank::init_sysbus`].
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct IrqHandle(Cell<*mut IRQState>);
+
+impl IrqHandle {
+    pub const fn new() -> Self {
+        IrqHandle(Cell::new(ptr::null_mut()))
+    }
+
+    /// Wrap a `qemu_irq` obtained from C, for example the input line
+    /// of a device.
+    pub(crate) fn from_raw(irq: *mut IRQState) -> Self {
+        IrqHandle(Cell::new(irq))
+    }
+
+    /// Return the `qemu_irq` that the line is connected to.
+    pub(crate) fn as_raw(&self) -> *mut IRQState {
+        self.0.get()
+    }
+
+    /// Set the level of the interrupt line.
+    pub fn set(&self, level: bool) {
+        // SAFETY: the pointer is either NULL, which qemu_set_irq
+        // ignores, or it was stored by C code when connecting the line
+        unsafe { qemu_set_irq(self.0.get(), level.into()) }
+    }
+
+    pub fn raise(&self) {
+        self.set(true);
+    }
+
+    pub fn lower(&self) {
+        self.set(false);
+    }
+
+    /// Raise and immediately lower the interrupt line, as in `qemu_irq_pulse`.
+    pub fn pulse(&self) {
+        self.set(true);
+        self.set(false);
+    }
+
+    fn as_mut_ptr(&self) -> *mut *mut IRQState {
+        self.0.as_ptr()
+    }
+}
+
+impl Default for IrqHandle {
+    fn default() -> Self {
+        IrqHandle::new()
+    }
+}
+
+/// A fixed number of output interrupt lines, for example the outputs of
+/// an interrupt controller.
+///
+/// Like [`RegisterFile`](crate::RegisterFile), accesses to lines that do
+/// not exist are ignored; in debug builds they panic instead, because
+/// they are a bug in the device model rather than something that the
+/// guest can trigger.
+///
+/// The bank remembers the level that the device last set on each line,
+/// so that [`IrqBank::reset`] can bring them back to deasserted.
+#[derive(Debug, Default)]
+pub struct IrqBank {
+    // Never resized, so that the lines do not move after init_sysbus
+    irqs: Vec<IrqHandle>,
+    levels: Vec<Cell<bool>>,
+}
+
+impl IrqBank {
+    /// Return a bank of `n` unconnected interrupt lines.
+    pub fn new(n: usize) -> Self {
+        IrqBank {
+            irqs: (0..n).map(|_| IrqHandle::new()).collect(),
+            levels: (0..n).map(|_| Cell::new(false)).collect(),
+        }
+    }
+
+    /// Return the number of interrupt lines in the bank.
+    pub fn len(&self) -> usize {
+        self.irqs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.irqs.is_empty()
+    }
+
+    fn check_index(&self, index: usize) -> bool {
+        debug_assert!(
+            index < self.irqs.len(),
+            "IRQ index {} out of range (bank has {} lines)",
+            index,
+            self.irqs.len()
+        );
+        index < self.irqs.len()
+    }
+
+    /// Set the level of line `index`.
+    pub fn set(&self, index: usize, level: bool) {
+        if self.check_index(index) {
+            self.irqs[index].set(level);
+            self.levels[index].set(level);
+        }
+    }
+
+    /// Raise and immediately lower line `index`.
+    pub fn pulse(&self, index: usize) {
+        if self.check_index(index) {
+            self.irqs[index].pulse();
+            self.levels[index].set(false);
+        }
+    }
+
+    /// Return the level that was last set on line `index`, or false
+    /// if the line does not exist.
+    pub fn level(&self, index: usize) -> bool {
+        self.check_index(index) && self.levels[index].get()
+    }
+
+    /// Lower all lines that are currently raised.  Call this from the
+    /// device's `COLD_RESET` hook, so that no interrupt remains asserted
+    /// across a reset.
+    pub fn reset(&self) {
+        for (irq, level) in self.irqs.iter().zip(&self.levels) {
+            if level.replace(false) {
+                irq.lower();
+            }
+        }
+    }
+
+    /// Expose the lines of the bank as the next interrupt outputs of
+    /// `dev`, so that board code can connect them with
+    /// `sysbus_connect_irq()`.
+    pub fn init_sysbus<D: IsA<SysBusDevice>>(&self, dev: &D) {
+        let dev = dev.upcast::<SysBusDevice>();
+        for irq in &self.irqs {
+            // SAFETY: the bank lives as long as the device, and the
+            // vector is never resized, so the pointer remains valid
+            unsafe { sysbus_init_irq(dev.as_mut_ptr(), irq.as_mut_ptr()) }
+        }
+    }
+
+    /// Expose the lines of the bank as the unnamed GPIO outputs of `dev`,
+    /// so that board code can connect them with `qdev_connect_gpio_out()`.
+    pub fn init_gpio_out<D: IsA<DeviceState>>(&self, dev: &D) {
+        if self.irqs.is_empty() {
+            return;
+        }
+        let dev = dev.upcast::<DeviceState>();
+        let n = c_int::try_from(self.irqs.len()).expect("too many GPIO lines");
+        // SAFETY: IrqHandle is transparent, so the vector is an array of
+        // qemu_irq; it lives as long as the device and is never resized
+        unsafe { qdev_init_gpio_out(dev.as_mut_ptr(), self.irqs[0].as_mut_ptr(), n) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_len() {
+        let bank = IrqBank::new(8);
+        assert_eq!(bank.len(), 8);
+        assert!(IrqBank::default().is_empty());
+    }
+}
@@ -1,2 +1,6 @@
+pub mod bus;
 pub mod device;
 pub mod device_impl;
+pub mod irq;
+pub mod register;
+pub mod sysbus;
@@ -0,0 +1,76 @@
+//! Register files for device models
+
+use std::cell::Cell;
+use std::fmt::{self, Debug};
+
+/// A bank of `N` 32-bit registers, that can be read and written through
+/// a shared reference.  This makes it possible to access the registers
+/// from MMIO callbacks, which only receive `&self`, without wrapping the
+/// device state in a `RefCell`.
+///
+/// Like typical hardware, accesses outside the register file read as
+/// zero and ignore writes.
+pub struct RegisterFile<const N: usize> {
+    regs: [Cell<u32>; N],
+}
+
+impl<const N: usize> RegisterFile<N> {
+    /// Return a register file with all registers set to zero.
+    pub fn new() -> Self {
+        RegisterFile {
+            regs: [(); N].map(|()| Cell::new(0)),
+        }
+    }
+
+    /// Return the value of register `idx`, or zero if `idx` is out of range.
+    pub fn read(&self, idx: usize) -> u32 {
+        self.regs.get(idx).map_or(0, Cell::get)
+    }
+
+    /// Set register `idx` to `val`.  Do nothing if `idx` is out of range.
+    pub fn write(&self, idx: usize, val: u32) {
+        if let Some(reg) = self.regs.get(idx) {
+            reg.set(val);
+        }
+    }
+
+    /// Set all registers to zero, for example on reset.
+    pub fn reset(&self) {
+        for reg in &self.regs {
+            reg.set(0);
+        }
+    }
+}
+
+impl<const N: usize> Default for RegisterFile<N> {
+    fn default() -> Self {
+        RegisterFile::new()
+    }
+}
+
+impl<const N: usize> Debug for RegisterFile<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.regs.iter().map(Cell::get))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_file() {
+        let regs = RegisterFile::<16>::default();
+        regs.write(3, 0x1234);
+        assert_eq!(regs.read(3), 0x1234);
+        assert_eq!(regs.read(4), 0);
+
+        regs.write(16, 42);
+        assert_eq!(regs.read(16), 0);
+
+        regs.reset();
+        assert_eq!(regs.read(3), 0);
+    }
+}
@@ -0,0 +1,57 @@
+//! Bindings for the QOM sys-bus-device class
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{c_void, CStr};
+
+use cstr::cstr;
+
+use crate::bindings::sysbus_init_mmio;
+use crate::bindings::DeviceState;
+use crate::bindings::MemoryRegion;
+use crate::bindings::Object;
+use crate::bindings::SysBusDevice;
+use crate::bindings::SysBusDeviceClass;
+
+use crate::hw::core::device_impl::DeviceImpl;
+
+use crate::qom::object::ClassType;
+use crate::qom::object::ObjectType;
+use crate::qom::object_impl::SuperclassImpl;
+
+use crate::qom::refs::ObjectCast;
+
+use crate::qom_isa;
+
+unsafe impl ObjectType for SysBusDevice {
+    const TYPE: &'static CStr = cstr!("sys-bus-device");
+}
+
+unsafe impl ClassType for SysBusDeviceClass {
+    const TYPE: &'static CStr = cstr!("sys-bus-device");
+}
+
+qom_isa!(SysBusDevice, DeviceState, Object);
+
+impl SysBusDevice {
+    /// Expose `mr` as the next MMIO region of the device, so that board
+    /// code can map it with `sysbus_mmio_map()`.
+    pub fn init_mmio(&self, mr: &MemoryRegion) {
+        // SAFETY: both pointers are valid; the memory region is a child
+        // of the device and lives as long as it
+        unsafe { sysbus_init_mmio(self.as_mut_ptr(), mr.as_mut_ptr()) }
+    }
+
+    pub unsafe extern "C" fn rust_class_init<T: DeviceImpl>(
+        klass: *mut c_void,
+        _data: *mut c_void,
+    ) {
+        let sbc: &mut SysBusDeviceClass = &mut *(klass.cast());
+        sbc.parent_class.class_init::<T>();
+    }
+}
+
+unsafe impl<T: DeviceImpl> SuperclassImpl<T> for SysBusDevice {
+    const CLASS_INIT: unsafe extern "C" fn(klass: *mut c_void, data: *mut c_void) =
+        SysBusDevice::rust_class_init::<T>;
+}
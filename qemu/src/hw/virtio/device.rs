@@ -0,0 +1,59 @@
+//! Bindings for the QOM virtio-device class
+
+use crate::qom::object::ClassType;
+use crate::qom::object::ObjectType;
+
+use crate::qom::refs::IsA;
+use crate::qom::refs::ObjectCast;
+
+use crate::bindings::DeviceState;
+use crate::bindings::Object;
+use crate::bindings::VirtIODevice;
+use crate::bindings::VirtioDeviceClass;
+
+use crate::qom_isa;
+
+use cstr::cstr;
+
+use std::ffi::CStr;
+use std::ops::Deref;
+
+unsafe impl ObjectType for VirtIODevice {
+    const TYPE: &'static CStr = cstr!("virtio-device");
+}
+
+unsafe impl ClassType for VirtioDeviceClass {
+    const TYPE: &'static CStr = cstr!("virtio-device");
+}
+
+qom_isa!(VirtIODevice, DeviceState, Object);
+
+/// Trait for methods exposed by the virtio-device class.  The methods can be
+/// called on all objects that have the trait `IsA<VirtIODevice>`.
+///
+/// The trait should only be used through the blanket implementation,
+/// which guarantees safety via `IsA`
+pub trait VirtioDeviceMethods: Deref
+where
+    Self::Target: IsA<VirtIODevice>,
+{
+    /// Return the features that the device offers to the guest.
+    fn host_features(&self) -> u64 {
+        self.upcast::<VirtIODevice>().host_features
+    }
+
+    /// Return the features that the guest has acknowledged.  The result
+    /// is only meaningful after feature negotiation has completed.
+    fn guest_features(&self) -> u64 {
+        self.upcast::<VirtIODevice>().guest_features
+    }
+
+    /// Return true if the guest has acknowledged feature bit `bit`.
+    /// Bits outside the 64-bit feature mask are never acknowledged.
+    fn has_guest_feature(&self, bit: u32) -> bool {
+        1u64.checked_shl(bit)
+            .map_or(false, |mask| self.guest_features() & mask != 0)
+    }
+}
+
+impl<R: Deref> VirtioDeviceMethods for R where R::Target: IsA<VirtIODevice> {}
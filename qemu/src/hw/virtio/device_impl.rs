@@ -0,0 +1,157 @@
+//! Macros and traits to implement subclasses of virtio-device in Rust
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::c_void;
+use std::slice;
+
+use crate::bindings;
+use crate::bindings::virtio_cleanup;
+use crate::bindings::virtio_init;
+use crate::bindings::DeviceState;
+use crate::bindings::VirtIODevice;
+use crate::bindings::VirtioDeviceClass;
+
+use crate::hw::core::device_impl::DeviceImpl;
+use crate::hw::core::device_impl::DeviceImplExt;
+
+use crate::qom::object_impl::SuperclassImpl;
+use crate::qom::refs::IsA;
+use crate::util::callback;
+use crate::util::error::Error;
+
+/// Information on which superclass methods are overridden
+/// by a Rust-implemented subclass of virtio-device.
+///
+/// The `APPLY_DEFAULT_PROPS`, `REALIZE` and `UNREALIZE` members of
+/// `DeviceImpl` are installed in the `VirtioDeviceClass`; they run after
+/// `virtio_init()` and before `virtio_cleanup()` respectively.
+pub trait VirtioDeviceImpl: DeviceImpl + IsA<VirtIODevice> {
+    /// The virtio device ID, as assigned by the virtio specification.
+    const DEVICE_ID: u16;
+
+    /// Size in bytes of the device-specific configuration space.
+    const CONFIG_SIZE: usize = 0;
+
+    /// Default value of the `features` property, i.e. the feature bits
+    /// that the device offers unless disabled on the command line.
+    const HOST_FEATURES: u64 = 0;
+
+    /// If not `None`, a function that implements the `get_features`
+    /// member of the QOM `VirtioDeviceClass`.  It receives the features
+    /// offered by the transport and the `features` property, and returns
+    /// the subset that the device supports.  If `None`, all of them are
+    /// offered to the guest.
+    const GET_FEATURES: Option<fn(obj: &Self, features: u64) -> crate::Result<u64>> = None;
+
+    /// If not `None`, a function that implements the `set_features`
+    /// member of the QOM `VirtioDeviceClass`.  It is called with the
+    /// features that were negotiated with the guest.
+    const SET_FEATURES: Option<fn(obj: &Self, features: u64)> = None;
+
+    /// If not `None`, a function that implements the `get_config`
+    /// member of the QOM `VirtioDeviceClass`.  It fills in the
+    /// `CONFIG_SIZE` bytes of the configuration space.
+    const GET_CONFIG: Option<fn(obj: &Self, config: &mut [u8])> = None;
+
+    /// If not `None`, a function that implements the `set_config`
+    /// member of the QOM `VirtioDeviceClass`.  It is called after the
+    /// guest writes to the configuration space.
+    const SET_CONFIG: Option<fn(obj: &Self, config: &[u8])> = None;
+}
+
+impl VirtioDeviceClass {
+    pub fn class_init<T: VirtioDeviceImpl>(&mut self) {
+        unsafe extern "C" fn rust_realize<T: VirtioDeviceImpl>(
+            obj: *mut DeviceState,
+            errp: *mut *mut bindings::Error,
+        ) {
+            let vdev: *mut VirtIODevice = obj.cast();
+            virtio_init(vdev, T::DEVICE_ID, T::CONFIG_SIZE);
+            let result = callback::call(obj, T::apply_default_props_and_realize);
+            if Error::ok_or_propagate(result, errp).is_none() {
+                virtio_cleanup(vdev);
+            }
+        }
+
+        unsafe extern "C" fn rust_unrealize<T: VirtioDeviceImpl>(obj: *mut DeviceState) {
+            if let Some(f) = T::UNREALIZE {
+                callback::call(obj, f);
+            }
+            virtio_cleanup(obj.cast());
+        }
+
+        unsafe extern "C" fn rust_get_features<T: VirtioDeviceImpl>(
+            obj: *mut VirtIODevice,
+            features: u64,
+            errp: *mut *mut bindings::Error,
+        ) -> u64 {
+            match T::GET_FEATURES {
+                None => features,
+                Some(f) => {
+                    let result = callback::call(obj, |dev| f(dev, features));
+                    Error::ok_or_propagate(result, errp).unwrap_or(0)
+                }
+            }
+        }
+
+        unsafe extern "C" fn rust_set_features<T: VirtioDeviceImpl>(
+            obj: *mut VirtIODevice,
+            features: u64,
+        ) {
+            let f = T::SET_FEATURES.unwrap();
+            callback::call(obj, |dev| f(dev, features))
+        }
+        self.set_features = T::SET_FEATURES.map(|_| rust_set_features::<T> as _);
+
+        unsafe extern "C" fn rust_get_config<T: VirtioDeviceImpl>(
+            obj: *mut VirtIODevice,
+            config: *mut u8,
+        ) {
+            let f = T::GET_CONFIG.unwrap();
+            let config = slice::from_raw_parts_mut(config, T::CONFIG_SIZE);
+            callback::call(obj, |dev| f(dev, config))
+        }
+        self.get_config = T::GET_CONFIG.map(|_| rust_get_config::<T> as _);
+
+        unsafe extern "C" fn rust_set_config<T: VirtioDeviceImpl>(
+            obj: *mut VirtIODevice,
+            config: *const u8,
+        ) {
+            let f = T::SET_CONFIG.unwrap();
+            let config = slice::from_raw_parts(config, T::CONFIG_SIZE);
+            callback::call(obj, |dev| f(dev, config))
+        }
+        self.set_config = T::SET_CONFIG.map(|_| rust_set_config::<T> as _);
+
+        // The C core always calls get_features and needs virtio_init to
+        // run before the device's own realize function.
+        self.realize = Some(rust_realize::<T>);
+        self.unrealize = Some(rust_unrealize::<T>);
+        self.get_features = Some(rust_get_features::<T>);
+
+        // Now initialize the DeviceClass from the DeviceImpl; the
+        // realize and unrealize members of the DeviceClass belong to
+        // virtio-device, so preserve them.
+        let realize = self.parent.realize;
+        let unrealize = self.parent.unrealize;
+        self.parent.class_init::<T>();
+        self.parent.realize = realize;
+        self.parent.unrealize = unrealize;
+    }
+}
+
+impl VirtIODevice {
+    pub unsafe extern "C" fn rust_class_init<T: VirtioDeviceImpl>(
+        klass: *mut c_void,
+        _data: *mut c_void,
+    ) {
+        let vdc: &mut VirtioDeviceClass = &mut *(klass.cast());
+        vdc.class_init::<T>();
+    }
+}
+
+unsafe impl<T: VirtioDeviceImpl> SuperclassImpl<T> for VirtIODevice {
+    const CLASS_INIT: unsafe extern "C" fn(klass: *mut c_void, data: *mut c_void) =
+        VirtIODevice::rust_class_init::<T>;
+}
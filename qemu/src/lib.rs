@@ -2,35 +2,78 @@
 #![allow(dead_code)]
 
 pub mod bindings;
+pub use bindings::BusState;
 pub use bindings::DeviceClass;
 pub use bindings::DeviceState;
+pub use bindings::MemoryRegion;
 pub use bindings::Object;
 pub use bindings::Property;
 pub use bindings::PropertyInfo;
+pub use bindings::SysBusDevice;
 pub use bindings::TypeInfo;
+pub use bindings::VMStateDescription;
+pub use bindings::VMStateField;
+pub use bindings::VirtIODevice;
+pub use bindings::VirtioDeviceClass;
+
+pub mod exec;
+pub use exec::memory::MmioRegion;
 
 pub mod hw;
+pub use hw::core::device::ConfigValue;
 pub use hw::core::device::DeviceMethods;
+pub use hw::core::device::ObjectTree;
+pub use hw::core::device_impl::register_device_type;
+pub use hw::core::device_impl::Deferred;
 pub use hw::core::device_impl::DeviceImpl;
+pub use hw::core::device_impl::DeviceImplExt;
 pub use hw::core::device_impl::DeviceTypeImpl;
+pub use hw::core::device_impl::ResetType;
+pub use hw::core::device_impl::Tracked;
+pub use hw::core::irq::IrqBank;
+pub use hw::core::irq::IrqHandle;
+pub use hw::core::register::RegisterFile;
+pub use hw::virtio::device::VirtioDeviceMethods;
+pub use hw::virtio::device_impl::VirtioDeviceImpl;
+
+pub mod migration;
+
+pub mod qapi;
+pub use qapi::qobject::QObject;
+pub use qapi::visitor::OutputVisitor;
+pub use qapi::visitor::VisitOutput;
 
 pub mod qom;
+pub use qom::object::ClassType;
 pub use qom::object::ObjectClassMethods;
 pub use qom::object::ObjectMethods;
 pub use qom::object::ObjectType;
+pub use qom::object_impl::register_type;
+pub use qom::object_impl::ClassProperty;
 pub use qom::object_impl::ObjectImpl;
 pub use qom::object_impl::TypeImpl;
+pub use qom::refs::AnyObject;
+pub use qom::refs::LeakGuard;
 pub use qom::refs::ObjectCast;
+pub use qom::refs::ObjectRef;
 pub use qom::refs::Owned;
 
 pub mod util;
+pub use util::dma::DmaBuf;
 pub use util::error::Error;
+pub use util::error::ErrorClass;
+pub use util::error::ResultExt;
 pub use util::foreign::CloneToForeign;
 pub use util::foreign::ForeignBorrow;
 pub use util::foreign::ForeignBorrowMut;
 pub use util::foreign::FromForeign;
 pub use util::foreign::IntoNative;
 pub use util::foreign::OwnedPointer;
+pub use util::foreign::Timespec;
+pub use util::foreign::Timeval;
+pub use util::runstate::RunState;
+pub use util::runstate::VmChangeStateHandler;
+pub use util::uuid::Uuid;
 pub use util::zeroed::Zeroed;
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -0,0 +1,205 @@
+//! Description of the migration state of devices
+
+use std::ffi::c_void;
+use std::ptr;
+
+use libc::c_char;
+
+use crate::bindings::{VMStateDescription, VMStateField, VMStateInfo};
+use crate::bindings::{MIG_PRI_DEFAULT, VMS_END, VMS_SINGLE};
+use crate::util::callback;
+
+impl VMStateField {
+    /// The terminator of the fields of a `VMStateDescription`.
+    pub const END_OF_LIST: VMStateField = VMStateField {
+        name: ptr::null(),
+        err_hint: ptr::null(),
+        offset: 0,
+        size: 0,
+        start: 0,
+        num: 0,
+        num_offset: 0,
+        size_offset: 0,
+        info: ptr::null(),
+        flags: VMS_END,
+        vmsd: ptr::null(),
+        version_id: 0,
+        struct_version_id: 0,
+        field_exists: None,
+    };
+
+    /// A field that holds a single value of `size` bytes, which is
+    /// migrated with `info`.  Used by `vmstate_field!`.
+    pub const fn single(
+        name: *const c_char,
+        offset: usize,
+        size: usize,
+        info: &'static VMStateInfo,
+    ) -> VMStateField {
+        VMStateField {
+            name,
+            offset,
+            size,
+            info,
+            flags: VMS_SINGLE,
+            ..VMStateField::END_OF_LIST
+        }
+    }
+}
+
+impl VMStateDescription {
+    /// A description without a name, fields or callbacks, for use with
+    /// struct update syntax.  Used by `vmstate_description!`.
+    pub const DEFAULT: VMStateDescription = VMStateDescription {
+        name: ptr::null(),
+        unmigratable: false,
+        early_setup: false,
+        version_id: 0,
+        minimum_version_id: 0,
+        priority: MIG_PRI_DEFAULT,
+        pre_load: None,
+        post_load: None,
+        pre_save: None,
+        post_save: None,
+        needed: None,
+        dev_unplug_pending: None,
+        fields: ptr::null(),
+        subsections: ptr::null(),
+    };
+}
+
+/// The `needed` predicate of a subsection, see `vmstate_description!`.
+pub trait VMStateNeeded {
+    type Device;
+
+    const NEEDED: fn(&Self::Device) -> bool;
+}
+
+pub struct VMStateCallbacks;
+impl VMStateCallbacks {
+    /// `needed` callback of a `VMStateDescription`
+    pub unsafe extern "C" fn needed<N: VMStateNeeded>(opaque: *mut c_void) -> bool {
+        callback::call_opaque(opaque, N::NEEDED)
+    }
+}
+
+/// Describe a field of the device state that is migrated:
+///
+/// ```ignore
+/// vmstate_field!(uint32, MyDevice, ctrl)
+/// ```
+///
+/// `kind` is one of `bool`, `int32`, `int64`, `uint8`, `uint16`,
+/// `uint32` or `uint64`; the field is a `Cell` of the corresponding Rust
+/// type, so that migration can store into it while the device is shared.
+/// The field is named after its path in the state, and fields of
+/// nested structs are written as `sub.value`.
+#[macro_export]
+macro_rules! vmstate_field {
+    // The Rust type and the C VMStateInfo for each kind
+    (@type bool) => { bool };
+    (@type int32) => { i32 };
+    (@type int64) => { i64 };
+    (@type uint8) => { u8 };
+    (@type uint16) => { u16 };
+    (@type uint32) => { u32 };
+    (@type uint64) => { u64 };
+
+    (@info bool) => { unsafe { &$crate::bindings::vmstate_info_bool } };
+    (@info int32) => { unsafe { &$crate::bindings::vmstate_info_int32 } };
+    (@info int64) => { unsafe { &$crate::bindings::vmstate_info_int64 } };
+    (@info uint8) => { unsafe { &$crate::bindings::vmstate_info_uint8 } };
+    (@info uint16) => { unsafe { &$crate::bindings::vmstate_info_uint16 } };
+    (@info uint32) => { unsafe { &$crate::bindings::vmstate_info_uint32 } };
+    (@info uint64) => { unsafe { &$crate::bindings::vmstate_info_uint64 } };
+
+    ($kind:tt, $type:ty, $($field:ident).+) => {{
+        #[allow(dead_code)]
+        fn check(
+            state: &$crate::state_type!($type),
+        ) -> &std::cell::Cell<$crate::vmstate_field!(@type $kind)> {
+            &state.$($field).+
+        }
+        $crate::VMStateField::single(
+            concat!(stringify!($($field).+), "\0").as_ptr().cast(),
+            $crate::offset_of!($type, state)
+                + $crate::offset_of_path!($crate::state_type!($type), $($field).+),
+            std::mem::size_of::<$crate::vmstate_field!(@type $kind)>(),
+            $crate::vmstate_field!(@info $kind),
+        )
+    }};
+}
+
+/// Define a function `name` that returns the `VMStateDescription` of
+/// a device, for use in `DeviceImpl::VMSTATE`:
+///
+/// ```ignore
+/// impl MyDevice {
+///     vmstate_description!(
+///         fn vmstate, cstr!("my-device"), MyDevice, 2, 1;
+///         @fields [
+///             vmstate_field!(uint32, MyDevice, ctrl),
+///         ];
+///         @subsections [MyDevice::vmstate_dma]
+///     );
+/// }
+/// ```
+///
+/// The numbers are the version of the state and the oldest version
+/// that can still be loaded.  Fields refer to the `state` of the device,
+/// see [`vmstate_field!`].
+///
+/// Subsections are functions defined with the same macro.  They usually
+/// add `@needed predicate;` before `@fields`, where `predicate` is a
+/// `fn(&Type) -> bool`: the subsection is only sent if it returns true,
+/// so that a destination that does not know the subsection can still
+/// load the state as long as the new feature is not in use.
+#[macro_export]
+macro_rules! vmstate_description {
+    (@needed $type:ty) => { None };
+    (@needed $type:ty, $needed:expr) => {{
+        struct Needed;
+        impl $crate::migration::vmstate::VMStateNeeded for Needed {
+            type Device = $type;
+
+            const NEEDED: fn(&$type) -> bool = $needed;
+        }
+        Some($crate::migration::vmstate::VMStateCallbacks::needed::<Needed>)
+    }};
+
+    ($vis:vis fn $fn:ident, $name:expr, $type:ty, $version_id:expr, $minimum_version_id:expr;
+     $(@needed $needed:expr;)?
+     @fields [$($fields:expr),* $(,)?]
+     $(; @subsections [$($subsections:expr),* $(,)?])?) => {
+        $vis fn $fn() -> *const $crate::VMStateDescription {
+            const SUBSECTIONS: usize = <[&str]>::len(&[$($(stringify!($subsections)),*)?]);
+
+            // The list is terminated by a NULL pointer, and is filled in
+            // on the first call because it refers to other functions
+            static mut SUBSECTION_LIST: [*const $crate::VMStateDescription; SUBSECTIONS + 1] =
+                [std::ptr::null(); SUBSECTIONS + 1];
+            static INIT: std::sync::Once = std::sync::Once::new();
+
+            static mut VMSD: $crate::VMStateDescription = $crate::VMStateDescription {
+                name: $name.as_ptr(),
+                version_id: $version_id,
+                minimum_version_id: $minimum_version_id,
+                needed: $crate::vmstate_description!(@needed $type $(, $needed)?),
+                fields: &[$($fields,)* $crate::VMStateField::END_OF_LIST]
+                    as *const [$crate::VMStateField] as *const $crate::VMStateField,
+                // SAFETY: only the address is taken
+                subsections: unsafe { std::ptr::addr_of!(SUBSECTION_LIST).cast() },
+                ..$crate::VMStateDescription::DEFAULT
+            };
+
+            // SAFETY: the subsection list is only written here, before
+            // the description is returned for the first time
+            INIT.call_once(|| unsafe {
+                SUBSECTION_LIST = [$($($subsections(),)*)? std::ptr::null()];
+            });
+            // SAFETY: mut is needed because the description contains raw
+            // pointers, but it is never modified
+            unsafe { std::ptr::addr_of!(VMSD) }
+        }
+    };
+}
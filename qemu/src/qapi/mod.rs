@@ -0,0 +1,3 @@
+pub mod qmp;
+pub mod qobject;
+pub mod visitor;
@@ -0,0 +1,83 @@
+//! Registration of QMP commands implemented in Rust
+
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr::{self, NonNull};
+
+use crate::bindings;
+use crate::bindings::{qmp_commands, qmp_register_command, QCO_NO_OPTIONS};
+
+use crate::qapi::qobject::QObject;
+use crate::util::callback;
+use crate::util::error::Error;
+use crate::util::foreign::{CloneToForeign, FromForeign};
+use crate::Result;
+
+/// Register `handler` as the implementation of the QMP command `name`.
+/// The handler receives the arguments of the command, usually a
+/// `QObject::Dict`, and returns the value that is sent back to the
+/// client; commands that do not return anything should return an
+/// empty dictionary.
+///
+/// ```ignore
+/// fn query_mydev(_args: QObject) -> Result<QObject> {
+///     Ok(QObject::Dict(BTreeMap::from([("status".to_string(), QObject::Uint(42))])))
+/// }
+///
+/// qmp::register_command(cstr!("query-mydev"), query_mydev);
+/// ```
+///
+/// Because QEMU does not pass an opaque pointer to command handlers,
+/// `handler` must be the name of a function or a closure that does not
+/// capture anything; function pointers are rejected at compile time.
+pub fn register_command<F>(name: &'static CStr, _handler: F)
+where
+    F: Fn(QObject) -> Result<QObject> + Copy + 'static,
+{
+    let () = HandlerChecks::<F>::OK;
+    // SAFETY: name is a static string, and the command list is only
+    // modified with the BQL taken, during startup
+    unsafe {
+        qmp_register_command(
+            ptr::addr_of_mut!(qmp_commands),
+            name.as_ptr(),
+            rust_qmp_command::<F>,
+            QCO_NO_OPTIONS,
+            0,
+        );
+    }
+}
+
+/// Checks on a command handler that are evaluated at compile time by
+/// `register_command`.
+struct HandlerChecks<F>(PhantomData<F>);
+
+impl<F> HandlerChecks<F> {
+    const OK: () = assert!(
+        mem::size_of::<F>() == 0,
+        "QMP handler must be a function item, not a function pointer"
+    );
+}
+
+unsafe extern "C" fn rust_qmp_command<F>(
+    args: *mut bindings::QDict,
+    ret: *mut *mut bindings::QObject,
+    errp: *mut *mut bindings::Error,
+) where
+    F: Fn(QObject) -> Result<QObject> + Copy + 'static,
+{
+    // SAFETY: register_command checked that F is zero-sized, so any
+    // well-aligned pointer is a valid reference to it
+    let handler = NonNull::<F>::dangling().as_ptr();
+    callback::call_opaque(handler.cast(), |handler: &F| {
+        let args = if args.is_null() {
+            QObject::Null
+        } else {
+            QObject::cloned_from_foreign(args.cast())
+        };
+        if let Some(value) = Error::ok_or_propagate(handler(args), errp) {
+            *ret = value.clone_to_foreign_ptr();
+        }
+    })
+}
@@ -0,0 +1,119 @@
+//! Conversion between QEMU's `QObject` and a Rust enum
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+
+use crate::bindings;
+use crate::bindings::{qbool_from_bool, qnull, qobject_unref_impl, qstring_from_str};
+use crate::bindings::{qdict_entry_key, qdict_entry_value, qdict_first, qdict_new, qdict_next};
+use crate::bindings::{qdict_put_obj, qstring_get_str};
+use crate::bindings::{qlist_append_obj, qlist_entry_obj, qlist_first, qlist_new, qlist_next};
+use crate::bindings::{qnum_from_double, qnum_from_int, qnum_from_uint};
+use crate::bindings::{QBool, QDict, QList, QNum, QString};
+
+use crate::util::foreign::{CloneToForeign, FromForeign, OwnedPointer};
+
+/// A dynamically typed QAPI value, such as the arguments of a QMP command.
+///
+/// Numbers keep the representation that they had in C: a `QNum`
+/// can hold a signed or unsigned integer, or a floating-point value.
+/// C values whose type is not known to Rust are converted to `Null`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QObject {
+    Null,
+    Int(i64),
+    Uint(u64),
+    Double(f64),
+    Bool(bool),
+    Str(String),
+    Dict(BTreeMap<String, QObject>),
+    List(Vec<QObject>),
+}
+
+impl CloneToForeign for QObject {
+    type Foreign = bindings::QObject;
+
+    unsafe fn free_foreign(p: *mut bindings::QObject) {
+        if !p.is_null() {
+            qobject_unref_impl(p);
+        }
+    }
+
+    fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+        // SAFETY: all the constructors return a new reference, and
+        // qdict_put_obj/qlist_append_obj take ownership of the value
+        unsafe {
+            let p: *mut bindings::QObject = match self {
+                QObject::Null => qnull(),
+                QObject::Int(n) => qnum_from_int(*n).cast(),
+                QObject::Uint(n) => qnum_from_uint(*n).cast(),
+                QObject::Double(n) => qnum_from_double(*n).cast(),
+                QObject::Bool(b) => qbool_from_bool(*b).cast(),
+                QObject::Str(s) => {
+                    let s = CString::new(s.as_str()).unwrap();
+                    qstring_from_str(s.as_ptr()).cast()
+                }
+                QObject::Dict(dict) => {
+                    let qdict = qdict_new();
+                    for (key, value) in dict {
+                        let key = CString::new(key.as_str()).unwrap();
+                        qdict_put_obj(qdict, key.as_ptr(), value.clone_to_foreign_ptr());
+                    }
+                    qdict.cast()
+                }
+                QObject::List(list) => {
+                    let qlist = qlist_new();
+                    for value in list {
+                        qlist_append_obj(qlist, value.clone_to_foreign_ptr());
+                    }
+                    qlist.cast()
+                }
+            };
+            OwnedPointer::new(p)
+        }
+    }
+}
+
+impl FromForeign for QObject {
+    unsafe fn cloned_from_foreign(p: *const bindings::QObject) -> Self {
+        match (*p).base.r#type {
+            bindings::QTYPE_QNULL => QObject::Null,
+            bindings::QTYPE_QNUM => {
+                let qnum = &*p.cast::<QNum>();
+                match qnum.kind {
+                    bindings::QNUM_I64 => QObject::Int(qnum.u.i64),
+                    bindings::QNUM_U64 => QObject::Uint(qnum.u.u64),
+                    bindings::QNUM_DOUBLE => QObject::Double(qnum.u.dbl),
+                    _ => QObject::Null,
+                }
+            }
+            bindings::QTYPE_QBOOL => QObject::Bool((*p.cast::<QBool>()).value),
+            bindings::QTYPE_QSTRING => {
+                let s = CStr::from_ptr(qstring_get_str(p.cast::<QString>()));
+                QObject::Str(s.to_string_lossy().into_owned())
+            }
+            bindings::QTYPE_QDICT => {
+                let qdict = p.cast::<QDict>();
+                let mut dict = BTreeMap::new();
+                let mut entry = qdict_first(qdict);
+                while !entry.is_null() {
+                    let key = CStr::from_ptr(qdict_entry_key(entry));
+                    let value = QObject::cloned_from_foreign(qdict_entry_value(entry));
+                    dict.insert(key.to_string_lossy().into_owned(), value);
+                    entry = qdict_next(qdict, entry);
+                }
+                QObject::Dict(dict)
+            }
+            bindings::QTYPE_QLIST => {
+                let mut list = Vec::new();
+                let mut entry = qlist_first(p.cast::<QList>());
+                while !entry.is_null() {
+                    list.push(QObject::cloned_from_foreign(qlist_entry_obj(entry)));
+                    entry = qlist_next(entry);
+                }
+                QObject::List(list)
+            }
+            _ => QObject::Null,
+        }
+    }
+}
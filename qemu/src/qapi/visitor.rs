@@ -0,0 +1,183 @@
+//! Wrappers for QAPI visitors
+
+use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::ptr;
+
+use cstr::cstr;
+
+use libc::c_char;
+
+use crate::bindings;
+use crate::bindings::Visitor;
+use crate::bindings::{visit_check_struct, visit_end_list, visit_end_struct};
+use crate::bindings::{visit_start_list, visit_start_struct};
+use crate::bindings::{visit_type_bool, visit_type_int64, visit_type_str, visit_type_uint64};
+
+use crate::Error;
+use crate::Result;
+
+fn name_ptr(name: Option<&CStr>) -> *const c_char {
+    name.map_or(ptr::null(), CStr::as_ptr)
+}
+
+/// A QAPI visitor that produces output from Rust values, for example the
+/// visitor passed to the `get` callback of a property.
+///
+/// Structs and lists are visited with a "virtual walk", i.e. without a
+/// corresponding C data structure.  The fields of a struct must be visited
+/// in the order in which they are declared in the QAPI schema.
+#[derive(Debug)]
+pub struct OutputVisitor<'a> {
+    v: *mut Visitor,
+    _marker: PhantomData<&'a mut Visitor>,
+}
+
+impl<'a> OutputVisitor<'a> {
+    /// Wrap a C output visitor.
+    ///
+    /// # Safety
+    ///
+    /// `v` must be a valid output visitor, and must remain valid for
+    /// the lifetime `'a`.
+    pub unsafe fn from_raw(v: *mut Visitor) -> Self {
+        OutputVisitor {
+            v,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Visit a signed integer
+    pub fn visit_i64(&mut self, name: Option<&CStr>, mut value: i64) -> Result<()> {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the visitor is valid, and an output visitor does not
+        // modify `value`
+        unsafe {
+            visit_type_int64(self.v, name_ptr(name), &mut value, &mut err);
+            Error::err_or_default(err)
+        }
+    }
+
+    /// Visit an unsigned integer
+    pub fn visit_u64(&mut self, name: Option<&CStr>, mut value: u64) -> Result<()> {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the visitor is valid, and an output visitor does not
+        // modify `value`
+        unsafe {
+            visit_type_uint64(self.v, name_ptr(name), &mut value, &mut err);
+            Error::err_or_default(err)
+        }
+    }
+
+    /// Visit a boolean
+    pub fn visit_bool(&mut self, name: Option<&CStr>, mut value: bool) -> Result<()> {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the visitor is valid, and an output visitor does not
+        // modify `value`
+        unsafe {
+            visit_type_bool(self.v, name_ptr(name), &mut value, &mut err);
+            Error::err_or_default(err)
+        }
+    }
+
+    /// Visit a string
+    pub fn visit_str(&mut self, name: Option<&CStr>, value: &str) -> Result<()> {
+        let value = CString::new(value).map_err(|e| Error::with_error("invalid string", e))?;
+        let mut value_ptr = value.as_ptr().cast_mut();
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the visitor is valid, and an output visitor does not
+        // modify the string
+        unsafe {
+            visit_type_str(self.v, name_ptr(name), &mut value_ptr, &mut err);
+            Error::err_or_default(err)
+        }
+    }
+
+    /// Visit a struct, whose fields are visited by `f`.
+    pub fn visit_struct<F>(&mut self, name: Option<&CStr>, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the visitor is valid; a NULL object requests a virtual walk
+        unsafe {
+            if !visit_start_struct(self.v, name_ptr(name), ptr::null_mut(), 0, &mut err) {
+                return Error::err_or_default(err);
+            }
+        }
+        let result = f(self).and_then(|()| unsafe {
+            visit_check_struct(self.v, &mut err);
+            Error::err_or_default(err)
+        });
+        // SAFETY: the visitor is valid, and visit_start_struct succeeded
+        unsafe {
+            visit_end_struct(self.v, ptr::null_mut());
+        }
+        result
+    }
+
+    /// Visit a list, calling `f` to visit each of the `items`.  The
+    /// elements of a list have no name, so `f` should pass `None` as
+    /// the name.
+    pub fn visit_list<I, F>(&mut self, name: Option<&CStr>, items: I, mut f: F) -> Result<()>
+    where
+        I: IntoIterator,
+        F: FnMut(&mut Self, I::Item) -> Result<()>,
+    {
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: the visitor is valid; a NULL list requests a virtual walk
+        unsafe {
+            if !visit_start_list(self.v, name_ptr(name), ptr::null_mut(), 0, &mut err) {
+                return Error::err_or_default(err);
+            }
+        }
+        let result = items.into_iter().try_for_each(|item| f(self, item));
+        // SAFETY: the visitor is valid, and visit_start_list succeeded
+        unsafe {
+            visit_end_list(self.v, ptr::null_mut());
+        }
+        result
+    }
+}
+
+/// Trait for types that can be written to an `OutputVisitor`, for example
+/// in order to expose them as read-only properties.
+pub trait VisitOutput {
+    /// Name of the QAPI type corresponding to `Self`
+    const TYPE: &'static CStr;
+
+    /// Visit `self` with the given name.
+    fn visit_output(&self, v: &mut OutputVisitor, name: Option<&CStr>) -> Result<()>;
+}
+
+impl VisitOutput for i64 {
+    const TYPE: &'static CStr = cstr!("int64");
+
+    fn visit_output(&self, v: &mut OutputVisitor, name: Option<&CStr>) -> Result<()> {
+        v.visit_i64(name, *self)
+    }
+}
+
+impl VisitOutput for u64 {
+    const TYPE: &'static CStr = cstr!("uint64");
+
+    fn visit_output(&self, v: &mut OutputVisitor, name: Option<&CStr>) -> Result<()> {
+        v.visit_u64(name, *self)
+    }
+}
+
+impl VisitOutput for bool {
+    const TYPE: &'static CStr = cstr!("bool");
+
+    fn visit_output(&self, v: &mut OutputVisitor, name: Option<&CStr>) -> Result<()> {
+        v.visit_bool(name, *self)
+    }
+}
+
+impl VisitOutput for String {
+    const TYPE: &'static CStr = cstr!("str");
+
+    fn visit_output(&self, v: &mut OutputVisitor, name: Option<&CStr>) -> Result<()> {
+        v.visit_str(name, self)
+    }
+}
@@ -3,16 +3,50 @@
 //! @author Paolo Bonzini
 
 use std::borrow::Cow;
-use std::ffi::CStr;
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CStr, CString};
 use std::fmt;
-use std::ops::Deref;
+use std::mem::MaybeUninit;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::ptr;
+use std::vec;
 
 use cstr::cstr;
 
+use libc::{c_char, c_int};
+
+use crate::bindings;
+use crate::bindings::error_propagate;
+use crate::bindings::object_child_foreach;
+use crate::bindings::object_class_by_name;
+use crate::bindings::object_class_dynamic_cast;
+use crate::bindings::object_class_property_find;
+use crate::bindings::object_dynamic_cast;
+use crate::bindings::object_get_canonical_path;
+use crate::bindings::object_get_canonical_path_component;
 use crate::bindings::object_get_typename;
+use crate::bindings::object_initialize;
 use crate::bindings::object_new;
+use crate::bindings::object_property_add;
+use crate::bindings::object_property_add_child;
+use crate::bindings::object_property_del;
+use crate::bindings::object_property_find;
+use crate::bindings::object_property_get_qobject;
+use crate::bindings::object_property_parse;
+use crate::bindings::object_property_set_qobject;
+use crate::bindings::object_type_get_instance_size;
 use crate::bindings::object_unparent;
+use crate::bindings::resettable_assert_reset;
+use crate::bindings::resettable_release_reset;
+use crate::bindings::visit_type_any;
+use crate::bindings::visit_type_uint64;
 use crate::bindings::Object;
+use crate::bindings::ObjectClass;
+use crate::bindings::ObjectPropertyAccessor;
+use crate::bindings::ObjectPropertyRelease;
+
+use crate::qapi::qobject::QObject;
+use crate::qapi::visitor::OutputVisitor;
 
 use crate::qom_isa;
 
@@ -20,6 +54,14 @@ use crate::qom::refs::IsA;
 use crate::qom::refs::ObjectCast;
 use crate::qom::refs::Owned;
 
+use crate::util::callback;
+use crate::util::error::Error;
+use crate::util::error::ResultExt;
+use crate::util::foreign::CloneToForeign;
+use crate::util::foreign::FromForeign;
+
+use crate::Result;
+
 /// Trait exposed by all structs corresponding to QOM objects.
 /// Defines "class methods" for the class.  Usually these can be
 /// implemented on the class itself; here, using a trait allows
@@ -37,12 +79,37 @@ use crate::qom::refs::Owned;
 /// - the struct must be `#[repr(C)]`
 pub unsafe trait ObjectType: Sized {
     const TYPE: &'static CStr;
+
+    /// True if the object can be used without holding the BQL, for
+    /// example because it protects its state with its own locks.  With
+    /// the `check-bql` feature, debug builds assert that all other
+    /// objects are only accessed by the thread that holds the BQL.
+    const THREAD_SAFE: bool = false;
 }
 
 unsafe impl ObjectType for Object {
     const TYPE: &'static CStr = cstr!("object");
 }
 
+/// Trait exposed by all structs corresponding to QOM classes.
+///
+/// # Safety
+///
+/// - the first field of the struct must be of `ObjectClass` type,
+///   or derived from it
+///
+/// - `TYPE` must be the name of the type that introduced the struct
+///   as its class (for example `device` for `DeviceClass`)
+///
+/// - the struct must be `#[repr(C)]`
+pub unsafe trait ClassType: Sized {
+    const TYPE: &'static CStr;
+}
+
+unsafe impl ClassType for ObjectClass {
+    const TYPE: &'static CStr = cstr!("object");
+}
+
 // ------------------------------
 // Object class
 
@@ -64,6 +131,101 @@ pub trait ObjectClassMethods: IsA<Object> {
             Owned::from_raw(obj.unsafe_cast::<Self>())
         }
     }
+
+    /// Initialize an instance of this class in memory provided by the
+    /// caller, for example as part of a larger allocation.  The returned
+    /// reference is the only one; once it and any other references are
+    /// dropped, the object is finalized and the `FREE` function of its
+    /// `ObjectImpl`, if any, is called with `place`.
+    ///
+    /// # Safety
+    ///
+    /// `place` must be valid for the instance size of the type, which
+    /// can be larger than `Self` (for example because of
+    /// `EXTRA_INSTANCE_BYTES`), and must remain valid until the object
+    /// is finalized.  Unless the type has a
+    /// `FREE` function, the caller is responsible for releasing the memory
+    /// afterwards.
+    unsafe fn initialize(place: *mut MaybeUninit<Self>) -> Owned<Self> {
+        let size = object_type_get_instance_size(Self::TYPE.as_ptr());
+        object_initialize(place.cast(), size, Self::TYPE.as_ptr());
+        Owned::from_raw(place.cast::<Self>())
+    }
+
+    /// Return the type name of the machine that is being emulated, or
+    /// `None` if there is no machine, as is the case in unit tests.
+    fn current_machine_type() -> Option<String> {
+        let machine = Owned::<Object>::resolve_path("/machine")?;
+        Some(machine.typename().into_owned())
+    }
+
+    /// Return the class of this type, without creating an instance.
+    /// Panics if the type is not registered.
+    fn object_class() -> &'static ObjectClass {
+        // SAFETY: classes are never freed
+        unsafe {
+            let klass = object_class_by_name(Self::TYPE.as_ptr());
+            assert!(
+                !klass.is_null(),
+                "type {} not registered",
+                Self::TYPE.to_string_lossy()
+            );
+            &*klass
+        }
+    }
+}
+
+impl ObjectClass {
+    /// Return true if the class or one of its superclasses has a class
+    /// property called `name`.
+    pub fn has_property(&self, name: &CStr) -> bool {
+        let klass: *const ObjectClass = self;
+        // SAFETY: the class is valid; the result is only compared to NULL
+        unsafe { !object_class_property_find(klass.cast_mut(), name.as_ptr()).is_null() }
+    }
+}
+
+/// Iterator over the children of an object that are of type `T`.
+/// Returned by [`ObjectMethods::children_of_type`].
+///
+/// The iterator borrows the parent, so that the parent stays alive for as
+/// long as the iterator does; each child is returned with its own reference.
+pub struct ChildrenOfType<'a, T: ObjectType> {
+    _parent: &'a Object,
+    children: vec::IntoIter<Owned<T>>,
+}
+
+impl<T: ObjectType> Iterator for ChildrenOfType<'_, T> {
+    type Item = Owned<T>;
+
+    fn next(&mut self) -> Option<Owned<T>> {
+        self.children.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.children.size_hint()
+    }
+}
+
+/// Iterator returned by [`ObjectMethods::child_refs`].
+///
+/// Like [`ChildrenOfType`], it borrows the parent for as long as it is
+/// alive.
+pub struct ChildRefs<'a> {
+    _parent: &'a Object,
+    children: vec::IntoIter<(String, Owned<Object>)>,
+}
+
+impl Iterator for ChildRefs<'_> {
+    type Item = (String, Owned<Object>);
+
+    fn next(&mut self) -> Option<(String, Owned<Object>)> {
+        self.children.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.children.size_hint()
+    }
 }
 
 /// Trait for methods exposed by the Object class.  The methods can be
@@ -75,19 +237,459 @@ pub trait ObjectMethods: Deref
 where
     Self::Target: IsA<Object>,
 {
-    /// Return the name of the type of `self`
+    /// Return the name of the type of `self`, or `<unknown>` if the
+    /// object does not have a type yet (for example because it is only
+    /// partially constructed)
     fn typename(&self) -> Cow<'_, str> {
         let obj = self.upcast::<Object>();
         // SAFETY: safety of this is the requirement for implementing IsA
         // The result of the C API has static lifetime
         let type_cstr = unsafe {
             let type_cstr = object_get_typename(obj.as_mut_ptr());
+            if type_cstr.is_null() {
+                return Cow::Borrowed("<unknown>");
+            }
             CStr::from_ptr(type_cstr)
         };
 
         type_cstr.to_string_lossy()
     }
 
+    /// Return the class of `self`
+    fn class(&self) -> &'static ObjectClass {
+        let obj = self.upcast::<Object>();
+        // SAFETY: the class pointer of an object is always valid, and
+        // classes are never freed
+        unsafe { &*obj.klass.cast::<ObjectClass>() }
+    }
+
+    /// Return the class of `self` as a `C`, or `None` if the type of
+    /// `self` is not `C::TYPE` or one of its subclasses
+    fn class_of<C: ClassType>(&self) -> Option<&'static C> {
+        let klass: *const ObjectClass = self.class();
+        // SAFETY: the return value is either NULL or the class of `self`,
+        // which object_class_dynamic_cast has checked to be a `C`
+        unsafe {
+            let result = object_class_dynamic_cast(klass.cast_mut(), C::TYPE.as_ptr());
+            result.cast::<C>().as_ref()
+        }
+    }
+
+    /// Return the path of `self` in the QOM tree, or `None` if it is
+    /// not part of the tree
+    fn canonical_path(&self) -> Option<String> {
+        let obj = self.upcast::<Object>();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // the result is freshly allocated and owned by the caller
+        unsafe { Option::<String>::from_foreign(object_get_canonical_path(obj.as_ptr())) }
+    }
+
+    /// Add `child` to the QOM tree as a child of `self`, with the given
+    /// property name.  The parent takes a reference to the child.
+    fn add_child<C: IsA<Object>>(&self, name: &CStr, child: &C) {
+        let obj = self.upcast::<Object>();
+        let child = child.upcast::<Object>();
+        // SAFETY: safety of this is the requirement for implementing IsA
+        unsafe {
+            object_property_add_child(obj.as_mut_ptr(), name.as_ptr(), child.as_mut_ptr());
+        }
+    }
+
+    /// Return the value of the property `name` of `self` as a dynamically
+    /// typed value, the same way QMP's `qom-get` command does.  Properties
+    /// that do not exist or are write-only produce an error.
+    fn get_property_qobject(&self, name: &CStr) -> Result<QObject> {
+        let obj = self.upcast::<Object>();
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // the result is NULL on error, or a new reference that from_foreign
+        // takes ownership of
+        unsafe {
+            let value = object_property_get_qobject(obj.as_mut_ptr(), name.as_ptr(), &mut err);
+            Error::err_or_else(err, || QObject::from_foreign(value))
+        }
+    }
+
+    /// Set the property `name` of `self` from a dynamically typed value,
+    /// the same way QMP's `qom-set` command does.  If the value does not
+    /// match the type of the property, the error comes from the property's
+    /// setter.
+    fn set_property_qobject(&self, name: &CStr, value: QObject) -> Result<()> {
+        let obj = self.upcast::<Object>();
+        let value = value.clone_to_foreign();
+        let mut err: *mut bindings::Error = ptr::null_mut();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // object_property_set_qobject does not take ownership of the value
+        unsafe {
+            object_property_set_qobject(
+                obj.as_mut_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr(),
+                &mut err,
+            );
+            Error::err_or_default(err)
+        }
+    }
+
+    /// Set several properties of `self` like [`set_property_qobject`](ObjectMethods::set_property_qobject),
+    /// but try all of them instead of stopping at the first failure.
+    /// On failure, return the name of each property that could not be
+    /// set together with the corresponding error, for example to report
+    /// all the problems in a configuration at once.
+    fn set_properties(
+        &self,
+        props: &[(&CStr, QObject)],
+    ) -> std::result::Result<(), Vec<(String, Error)>> {
+        let errors: Vec<(String, Error)> = props
+            .iter()
+            .filter_map(|(name, value)| {
+                let result = self.set_property_qobject(name, value.clone());
+                result
+                    .err()
+                    .map(|err| (name.to_string_lossy().into_owned(), err))
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Add an unsigned integer property to `self`, for objects that are
+    /// not devices and therefore cannot use `qdev_prop!`, such as backends
+    /// created with `-object`.  Usually called from `INSTANCE_INIT`.
+    ///
+    /// `get` and `set` access the value; without `set`, the property is
+    /// read-only.  Values outside `range` are rejected before `set` is
+    /// called, with an error that is reported to the user:
+    ///
+    /// ```ignore
+    /// obj.add_uint_property(cstr!("size"), MyBackend::size, Some(MyBackend::set_size), 512..=65536);
+    /// ```
+    fn add_uint_property<B: RangeBounds<u64>>(
+        &self,
+        name: &CStr,
+        get: fn(&Self::Target) -> u64,
+        set: Option<fn(&Self::Target, u64) -> Result<()>>,
+        range: B,
+    ) {
+        struct UintProperty<T> {
+            get: fn(&T) -> u64,
+            set: Option<fn(&T, u64) -> Result<()>>,
+            min: u64,
+            max: u64,
+        }
+
+        unsafe extern "C" fn get_uint<T: IsA<Object>>(
+            obj: *mut Object,
+            v: *mut bindings::Visitor,
+            name: *const c_char,
+            opaque: *mut c_void,
+            errp: *mut *mut bindings::Error,
+        ) {
+            let prop = &*opaque.cast::<UintProperty<T>>();
+            let value = callback::call(obj, prop.get);
+            let result = OutputVisitor::from_raw(v).visit_u64(Some(CStr::from_ptr(name)), value);
+            Error::ok_or_propagate(result, errp);
+        }
+
+        unsafe extern "C" fn set_uint<T: IsA<Object>>(
+            obj: *mut Object,
+            v: *mut bindings::Visitor,
+            name: *const c_char,
+            opaque: *mut c_void,
+            errp: *mut *mut bindings::Error,
+        ) {
+            let prop = &*opaque.cast::<UintProperty<T>>();
+            let mut value: u64 = 0;
+            if !visit_type_uint64(v, name, &mut value, errp) {
+                return;
+            }
+            let result = if value < prop.min || value > prop.max {
+                Err(Error::from(
+                    format!(
+                        "Property {} doesn't take value {} (minimum: {}, maximum: {})",
+                        CStr::from_ptr(name).to_string_lossy(),
+                        value,
+                        prop.min,
+                        prop.max
+                    )
+                    .as_str(),
+                ))
+            } else {
+                callback::call(obj, |obj| (prop.set.unwrap())(obj, value))
+            };
+            Error::ok_or_propagate(result, errp);
+        }
+
+        unsafe extern "C" fn release_uint<T>(
+            _obj: *mut Object,
+            _name: *const c_char,
+            opaque: *mut c_void,
+        ) {
+            drop(Box::from_raw(opaque.cast::<UintProperty<T>>()));
+        }
+
+        let min = match range.start_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_add(1).expect("empty range"),
+            Bound::Unbounded => 0,
+        };
+        let max = match range.end_bound() {
+            Bound::Included(&n) => n,
+            Bound::Excluded(&n) => n.checked_sub(1).expect("empty range"),
+            Bound::Unbounded => u64::MAX,
+        };
+        assert!(min <= max, "empty range");
+
+        let obj = self.upcast::<Object>();
+        let prop = Box::new(UintProperty::<Self::Target> { get, set, min, max });
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // the opaque is freed by release_uint when the property is deleted
+        unsafe {
+            object_property_add(
+                obj.as_mut_ptr(),
+                name.as_ptr(),
+                cstr!("uint64").as_ptr(),
+                Some(get_uint::<Self::Target>),
+                set.map(|_| set_uint::<Self::Target> as _),
+                Some(release_uint::<Self::Target>),
+                Box::into_raw(prop).cast(),
+            );
+        }
+    }
+
+    /// Add a property `name` that creates a child of `self` with type `C`
+    /// from an inline definition, for example `"disk": {"size": 4096}` in
+    /// JSON or `disk.size=4096` on the command line.  Each key of the
+    /// definition sets the property of the same name in the new object,
+    /// so definitions can be nested if `C` has inline child properties
+    /// too.  Usually called from `INSTANCE_INIT`.
+    ///
+    /// Once the object is created, it replaces the property as the child
+    /// `name` of `self`; therefore, the property can only be set once.
+    fn add_inline_child_property<C: IsA<Object>>(&self, name: &CStr) {
+        unsafe extern "C" fn set_inline_child<C: IsA<Object>>(
+            obj: *mut Object,
+            v: *mut bindings::Visitor,
+            name: *const c_char,
+            _opaque: *mut c_void,
+            errp: *mut *mut bindings::Error,
+        ) {
+            let mut value: *mut bindings::QObject = ptr::null_mut();
+            if !visit_type_any(v, name, &mut value, errp) {
+                return;
+            }
+            let result = match QObject::from_foreign(value) {
+                QObject::Dict(props) => create_inline_child::<C>(props),
+                _ => Err(Error::from(
+                    format!(
+                        "Property {} expects an object definition",
+                        CStr::from_ptr(name).to_string_lossy()
+                    )
+                    .as_str(),
+                )),
+            };
+            if let Some(child) = Error::ok_or_propagate(result, errp) {
+                // object_property_set() does not look at the property
+                // after the setter returns, so it can be deleted here
+                object_property_del(obj, name);
+                object_property_add_child(obj, name, child.upcast::<Object>().as_mut_ptr());
+            }
+        }
+
+        fn create_inline_child<C: IsA<Object>>(
+            props: BTreeMap<String, QObject>,
+        ) -> Result<Owned<C>> {
+            let child = C::new();
+            for (key, value) in props {
+                let key = CString::new(key).map_err(|_| Error::from("invalid property name"))?;
+                let result = match value {
+                    // Values from the command line are all strings
+                    QObject::Str(string) => {
+                        let string = CString::new(string)
+                            .map_err(|_| Error::from("invalid property value"))?;
+                        let mut err: *mut bindings::Error = ptr::null_mut();
+                        // SAFETY: the child is valid, and all strings are
+                        // NUL-terminated
+                        unsafe {
+                            object_property_parse(
+                                child.upcast::<Object>().as_mut_ptr(),
+                                key.as_ptr(),
+                                string.as_ptr(),
+                                &mut err,
+                            );
+                            Error::err_or_default(err)
+                        }
+                    }
+                    value => child.set_property_qobject(&key, value),
+                };
+                result.with_qemu_context(|| key.to_string_lossy().into_owned())?;
+            }
+            Ok(child)
+        }
+
+        let obj = self.upcast::<Object>();
+        let typename = format!("child<{}>", C::TYPE.to_string_lossy());
+        let typename = CString::new(typename).unwrap();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // object_property_add copies the type name
+        unsafe {
+            object_property_add(
+                obj.as_mut_ptr(),
+                name.as_ptr(),
+                typename.as_ptr(),
+                None,
+                Some(set_inline_child::<C>),
+                None,
+                ptr::null_mut(),
+            );
+        }
+    }
+
+    /// Call `notify` whenever the property `name` of `self` is set
+    /// successfully, for example with `qom-set`, so that the object can
+    /// react to live updates.  Registering the same notifier twice has
+    /// no effect.  The notifier is removed together with the property
+    /// when `self` is finalized.
+    ///
+    /// Only properties of the instance, such as those added by
+    /// [`add_uint_property`](ObjectMethods::add_uint_property), can have
+    /// notifiers.  Panics if the property does not exist, is read-only or
+    /// is defined by the class: class properties are shared with objects
+    /// of other types.  For `qdev_prop!` properties, use the `@notify`
+    /// modifier instead.
+    fn on_property_change(&self, name: &CStr, notify: fn(&Self::Target)) {
+        hook_property(self.upcast::<Object>(), name, PropertyHook::Notify(notify));
+    }
+
+    /// Only allow setting the property `name` of `self` if `settable`
+    /// returns true, for example to refuse changes while the device is
+    /// running.  Otherwise the setter is not called and the user gets an
+    /// error.  Unlike the range of [`add_uint_property`](ObjectMethods::add_uint_property),
+    /// the check depends on the state of the object and not on the value.
+    ///
+    /// As for [`on_property_change`](ObjectMethods::on_property_change),
+    /// the property must belong to the instance; the guard is removed
+    /// together with the property when `self` is finalized.  For
+    /// `qdev_prop!` properties, use the `@settable` modifier instead.
+    fn set_property_guard(&self, name: &CStr, settable: fn(&Self::Target) -> bool) {
+        hook_property(self.upcast::<Object>(), name, PropertyHook::Guard(settable));
+    }
+
+    /// Return an iterator over the children of `self` whose type is `T`
+    /// or a subclass of `T`.  The children are those that exist when
+    /// the method is called.
+    ///
+    /// Note that children can be embedded in the parent; the references
+    /// that are returned must not outlive the parent in that case.
+    fn children_of_type<T: ObjectType>(&self) -> ChildrenOfType<'_, T> {
+        unsafe extern "C" fn add_child<T: ObjectType>(
+            child: *mut Object,
+            opaque: *mut c_void,
+        ) -> c_int {
+            let children = &mut *opaque.cast::<Vec<Owned<T>>>();
+            if let Some(child) = (&*child).dynamic_cast::<T>() {
+                children.push(Owned::from(child));
+            }
+            0
+        }
+
+        let obj = self.upcast::<Object>();
+        let mut children: Vec<Owned<T>> = Vec::new();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // `children` outlives the call to object_child_foreach
+        unsafe {
+            object_child_foreach(
+                obj.as_mut_ptr(),
+                Some(add_child::<T>),
+                ptr::addr_of_mut!(children).cast(),
+            );
+        }
+        ChildrenOfType {
+            _parent: obj,
+            children: children.into_iter(),
+        }
+    }
+
+    /// Return an iterator over all the children of `self`, together with
+    /// the name of the child property that points to each of them.  The
+    /// children are those that exist when the method is called; use
+    /// [`Owned::dynamic_cast`] to recover their type.
+    fn child_refs(&self) -> ChildRefs<'_> {
+        unsafe extern "C" fn add_child(child: *mut Object, opaque: *mut c_void) -> c_int {
+            let children = &mut *opaque.cast::<Vec<(String, Owned<Object>)>>();
+            // The name belongs to the child property and must not be freed
+            let name =
+                Option::<String>::cloned_from_foreign(object_get_canonical_path_component(child));
+            children.push((name.unwrap_or_default(), Owned::from(&*child)));
+            0
+        }
+
+        let obj = self.upcast::<Object>();
+        let mut children: Vec<(String, Owned<Object>)> = Vec::new();
+        // SAFETY: safety of this is the requirement for implementing IsA;
+        // `children` outlives the call to object_child_foreach
+        unsafe {
+            object_child_foreach(
+                obj.as_mut_ptr(),
+                Some(add_child),
+                ptr::addr_of_mut!(children).cast(),
+            );
+        }
+        ChildRefs {
+            _parent: obj,
+            children: children.into_iter(),
+        }
+    }
+
+    /// Perform a cold reset of `self` and of all its descendants in the
+    /// QOM tree that can be reset, for example all the devices of a board.
+    /// All the objects enter reset, starting from `self`, before any of
+    /// them leaves it; an object that is also reached through a bus is
+    /// still reset only once.
+    fn reset_subtree(&self) {
+        unsafe extern "C" fn add_child(child: *mut Object, opaque: *mut c_void) -> c_int {
+            let objects = &mut *opaque.cast::<Vec<Owned<Object>>>();
+            collect_resettable(&*child, objects);
+            0
+        }
+
+        fn collect_resettable(obj: &Object, objects: &mut Vec<Owned<Object>>) {
+            // SAFETY: the object is valid, and `objects` outlives the call
+            // to object_child_foreach
+            unsafe {
+                let resettable =
+                    object_dynamic_cast(obj.as_mut_ptr(), cstr!("resettable").as_ptr());
+                if !resettable.is_null() {
+                    objects.push(Owned::from(obj));
+                }
+                object_child_foreach(
+                    obj.as_mut_ptr(),
+                    Some(add_child),
+                    (objects as *mut Vec<Owned<Object>>).cast(),
+                );
+            }
+        }
+
+        let mut objects: Vec<Owned<Object>> = Vec::new();
+        collect_resettable(self.upcast::<Object>(), &mut objects);
+        // SAFETY: the objects were checked to implement the resettable
+        // interface; the reset count of each object makes resets that
+        // nest (for example through a bus) run the phases only once
+        unsafe {
+            for obj in &objects {
+                let obj: &Object = obj;
+                resettable_assert_reset(obj.as_mut_ptr(), bindings::RESET_TYPE_COLD);
+            }
+            for obj in objects.iter().rev() {
+                let obj: &Object = obj;
+                resettable_release_reset(obj.as_mut_ptr(), bindings::RESET_TYPE_COLD);
+            }
+        }
+    }
+
     /// Remove the object from the QOM tree
     fn unparent(&self) {
         let obj = self.upcast::<Object>();
@@ -105,5 +707,120 @@ where
     }
 }
 
+/// Code that runs around the setter of a property, see
+/// `ObjectMethods::on_property_change` and `ObjectMethods::set_property_guard`
+enum PropertyHook<T> {
+    Notify(fn(&T)),
+    Guard(fn(&T) -> bool),
+}
+
+impl<T> PropertyHook<T> {
+    fn same_as(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PropertyHook::Notify(a), PropertyHook::Notify(b)) => *a as usize == *b as usize,
+            (PropertyHook::Guard(a), PropertyHook::Guard(b)) => *a as usize == *b as usize,
+            _ => false,
+        }
+    }
+}
+
+struct HookedProperty<T> {
+    hook: PropertyHook<T>,
+    set: ObjectPropertyAccessor,
+    release: Option<ObjectPropertyRelease>,
+    opaque: *mut c_void,
+}
+
+/// Install `hook` around the setter of the property `name` of `obj`,
+/// unless the same hook is already there.  The previous setter, release
+/// function and opaque are kept in a `HookedProperty`, so that several
+/// hooks can be chained.
+fn hook_property<T: IsA<Object>>(obj: &Object, name: &CStr, hook: PropertyHook<T>) {
+    unsafe extern "C" fn set_hooked<T: IsA<Object>>(
+        obj: *mut Object,
+        v: *mut bindings::Visitor,
+        name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let hooked = &*opaque.cast::<HookedProperty<T>>();
+        if let PropertyHook::Guard(settable) = hooked.hook {
+            if !callback::call(obj, settable) {
+                let err = Error::from(
+                    format!(
+                        "Property {} cannot be set in the current state",
+                        CStr::from_ptr(name).to_string_lossy()
+                    )
+                    .as_str(),
+                );
+                err.propagate(errp);
+                return;
+            }
+        }
+        let mut local_err: *mut bindings::Error = ptr::null_mut();
+        (hooked.set)(obj, v, name, hooked.opaque, &mut local_err);
+        if !local_err.is_null() {
+            error_propagate(errp, local_err);
+        } else if let PropertyHook::Notify(notify) = hooked.hook {
+            callback::call(obj, notify);
+        }
+    }
+
+    unsafe extern "C" fn release_hooked<T: IsA<Object>>(
+        obj: *mut Object,
+        name: *const c_char,
+        opaque: *mut c_void,
+    ) {
+        let hooked = Box::from_raw(opaque.cast::<HookedProperty<T>>());
+        if let Some(release) = hooked.release {
+            release(obj, name, hooked.opaque);
+        }
+    }
+
+    // SAFETY: the object is valid; the property, if found, lives at
+    // least as long as the object
+    let prop = unsafe { object_property_find(obj.as_mut_ptr(), name.as_ptr()).as_mut() };
+    let prop = prop.unwrap_or_else(|| {
+        panic!(
+            "no property {} in {}",
+            name.to_string_lossy(),
+            obj.typename()
+        )
+    });
+    // The ObjectProperty of a class property is shared by all instances
+    // of the class and of its subclasses, and the hook would then be
+    // called with objects that are not a T
+    assert!(
+        !obj.class().has_property(name),
+        "property {} of {} is defined by the class and cannot be hooked",
+        name.to_string_lossy(),
+        obj.typename()
+    );
+    let set = prop
+        .set
+        .unwrap_or_else(|| panic!("property {} is read-only", name.to_string_lossy()));
+
+    let set_hooked_fn: ObjectPropertyAccessor = set_hooked::<T>;
+    let mut chained = (set, prop.opaque);
+    while chained.0 as usize == set_hooked_fn as usize {
+        // SAFETY: set_hooked is only installed together with its opaque
+        let hooked = unsafe { &*chained.1.cast::<HookedProperty<T>>() };
+        if hooked.hook.same_as(&hook) {
+            return;
+        }
+        chained = (hooked.set, hooked.opaque);
+    }
+
+    let hooked = Box::new(HookedProperty {
+        hook,
+        set,
+        release: prop.release,
+        opaque: prop.opaque,
+    });
+    prop.set = Some(set_hooked_fn);
+    prop.release = Some(release_hooked::<T>);
+    prop.opaque = Box::into_raw(hooked).cast();
+}
+
 impl<R> ObjectClassMethods for R where R: IsA<Object> {}
 impl<R: Deref> ObjectMethods for R where R::Target: IsA<Object> {}
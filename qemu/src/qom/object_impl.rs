@@ -6,41 +6,297 @@
 
 use const_default::ConstDefault;
 
-use std::ffi::c_void;
+use cstr::cstr;
+
+use libc::c_char;
+
+use std::cell::Cell;
+use std::ffi::{c_void, CStr, CString};
+use std::marker::PhantomData;
 use std::mem;
 use std::mem::MaybeUninit;
+use std::ptr;
 use std::ptr::drop_in_place;
+use std::slice;
 
-use crate::qom::object::ObjectType;
+use crate::qapi::visitor::OutputVisitor;
+use crate::qapi::visitor::VisitOutput;
 
-use crate::qom::refs::ObjectCast;
+use crate::qom::object::ObjectType;
+use crate::qom::refs::IsA;
 
+use crate::bindings;
+use crate::bindings::object_class_by_name;
+use crate::bindings::object_class_get_parent;
+use crate::bindings::object_class_property_add;
+use crate::bindings::object_property_add;
 use crate::bindings::type_register;
+use crate::bindings::InterfaceInfo;
 use crate::bindings::Object;
 use crate::bindings::ObjectClass;
+use crate::bindings::ObjectPropertyAccessor;
 use crate::bindings::TypeInfo;
+use crate::bindings::Visitor;
 
+use crate::util::callback;
+use crate::util::error::Error;
 use crate::util::zeroed::Zeroed;
 
+use crate::Result;
+
 /// Information on which superclass methods are overridden
 /// by a Rust-implemented subclass of Object.
-pub trait ObjectImpl: ObjectType {
-    /// If not `None`, a function that implements the `unparent` member
-    /// of the QOM `ObjectClass`.
+///
+/// Over the life of an object, the hooks run in this order:
+///
+/// 1. when memory for the object is initialized, `conf` is set to its
+///    `ConstDefault` value (unless `INIT_CONF_IN_INSTANCE_INIT` is true)
+///    and `state` to its `Default` value;
+///
+/// 2. `INSTANCE_INIT`, after the `instance_init` of the superclasses
+///    and before that of the subclasses;
+///
+/// 3. `INSTANCE_POST_INIT`, after the `instance_init` of all classes;
+///    since QEMU 9.2 it also runs after the `instance_post_init` of the
+///    superclasses, which for devices applies the `-global` options;
+///
+/// 4. for devices, the properties are set and then the realize hooks
+///    of `DeviceImpl` run;
+///
+/// 5. when the object is removed from the QOM tree, `PRE_UNPARENT`,
+///    the superclass's `unparent` (which unrealizes devices), and
+///    `UNPARENT`;
+///
+/// 6. when the last reference is dropped, `conf` and then `state` are
+///    dropped, after the subclasses and before the superclasses finalize
+///    their part of the object, and before `FREE` is called.
+pub trait ObjectImpl: ObjectType + IsA<Object> + 'static {
+    /// If not `None`, a function that is called when the object is
+    /// removed from the QOM tree, before the superclass's `unparent`
+    /// method.  At this point the superclass has not torn down anything
+    /// yet; for devices, the device is still realized and its buses are
+    /// still attached, so their state can be inspected.
+    const PRE_UNPARENT: Option<fn(obj: &Self)> = None;
+
+    /// If not `None`, a function that is called when the object is
+    /// removed from the QOM tree, after the superclass's `unparent`
+    /// method.  Together with `PRE_UNPARENT`, it implements the
+    /// `unparent` member of the QOM `ObjectClass`.
+    ///
+    /// Before `PRE_UNPARENT` was added, `UNPARENT` ran instead of the
+    /// superclass's method, which was not called at all.  Code that
+    /// needs to run while devices are still realized belongs in
+    /// `PRE_UNPARENT` now.
     const UNPARENT: Option<fn(obj: &Self)> = None;
+
+    /// Number of bytes that are allocated after the end of the struct,
+    /// for example to hold the flexible array member of a C struct.
+    /// Types that set this must not be subclassed.
+    const EXTRA_INSTANCE_BYTES: usize = 0;
+
+    /// Minimum alignment of the instance, for example to place a DMA
+    /// buffer or data that is accessed by several threads on a cache
+    /// line of its own.  Zero means that the natural alignment of the
+    /// struct is enough; QOM always respects the latter anyway.
+    ///
+    /// `#[repr(align)]` needs a literal, so the struct generated by
+    /// `qom_define_type!` does not follow `INSTANCE_ALIGN`: only the
+    /// start of the allocation does.  To align a field, give it a
+    /// type with `#[repr(align(N))]`; this also raises the natural
+    /// alignment of the struct.  `object_new()` honors the alignment,
+    /// while `ObjectClassMethods::initialize` leaves it to the caller.
+    const INSTANCE_ALIGN: usize = 0;
+
+    /// If not `None`, a function that implements the `instance_init`
+    /// member of the QOM `TypeInfo`.  It runs after the superclasses'
+    /// `instance_init`, and receives the object with its `State` and,
+    /// unless `INIT_CONF_IN_INSTANCE_INIT` is true, its `Conf` already
+    /// initialized.
+    const INSTANCE_INIT: Option<fn(obj: &mut MaybeUninit<Self>)> = None;
+
+    /// If not `None`, a function that implements the `instance_post_init`
+    /// member of the QOM `TypeInfo`.  Unlike `INSTANCE_INIT`, it runs
+    /// after the `instance_init` of the subclasses too.
+    ///
+    /// The order relative to the superclasses depends on the QEMU version.
+    /// Since QEMU 9.2, QOM calls `instance_post_init` starting from the
+    /// root of the hierarchy, so for devices the `-global` options and
+    /// compat properties have already been applied.  Older versions start
+    /// from the type that is instantiated.
+    const INSTANCE_POST_INIT: Option<fn(obj: &Self)> = None;
+
+    /// If true, the `Conf` is not set to its `ConstDefault` value when
+    /// memory for the object is initialized; `INSTANCE_INIT` must be
+    /// present and fully initialize it instead.  This avoids writing
+    /// large configuration structs twice.
+    const INIT_CONF_IN_INSTANCE_INIT: bool = false;
+
+    /// Read-only properties that are registered on the class rather than
+    /// on each instance, usually built with `class_property!`.  They are
+    /// listed by introspection even if no instance exists, for example
+    /// to expose a capability of the type.
+    const CLASS_PROPERTIES: &'static [ClassProperty<Self>] = &[];
+
+    /// If not `None`, a function that returns internal counters of the
+    /// object, for example statistics that are useful when debugging.
+    /// When the object is created, each counter becomes a read-only
+    /// `uint64` property with the same name, which can be read with
+    /// `qom-get`.  Reading the property calls the function again and
+    /// picks the current value of the counter.
+    const INSTANCE_COUNTERS: Option<fn(obj: &Self) -> Vec<(CString, u64)>> = None;
+
+    /// Whether objects of this type can be created with `-object` or
+    /// `object-add`, for example because they are backends for devices.
+    /// If true, the type implements the `user-creatable` interface; the
+    /// object is then placed in `/objects`, under the name given by its
+    /// `id` option.
+    const USER_CREATABLE: bool = false;
+
+    /// If not `None`, the function that frees the memory of the object
+    /// after it is finalized, instead of leaving it to whoever allocated
+    /// it.  It is stored in `Object::free` when the object is initialized.
+    /// `object_new()` replaces it with the function that matches its own
+    /// allocation, so this is only useful for objects that are initialized
+    /// in place (see `ObjectClassMethods::initialize`), for example as part
+    /// of a larger allocation that the function releases as a whole.
+    ///
+    /// The function is called exactly once, after the last reference is
+    /// dropped and after `conf` and `state` are dropped; finalization
+    /// itself never frees the memory.
+    const FREE: Option<extern "C" fn(obj: *mut c_void)> = None;
+
+    /// Return the bytes allocated after the end of the struct because of
+    /// `EXTRA_INSTANCE_BYTES`.  They are zeroed when the object is created.
+    fn extra_instance_bytes(&self) -> &[Cell<u8>] {
+        // SAFETY: QOM allocated and zeroed EXTRA_INSTANCE_BYTES bytes after
+        // the struct, and Cell<u8> has the same layout as u8
+        unsafe {
+            let p: *const Self = self;
+            let extra = p.add(1).cast::<Cell<u8>>();
+            slice::from_raw_parts(extra, Self::EXTRA_INSTANCE_BYTES)
+        }
+    }
 }
 
 impl ObjectClass {
     /// Initialize an `ObjectClass` from an `ObjectImpl`.
     pub fn class_init<T: ObjectImpl>(&mut self) {
+        self.init_callbacks::<T>();
+
+        for prop in T::CLASS_PROPERTIES {
+            // SAFETY: the name and type are static; the getter expects
+            // an instance of T, which is guaranteed by ClassProperty<T>
+            unsafe {
+                object_class_property_add(
+                    self,
+                    prop.name.as_ptr(),
+                    prop.type_name.as_ptr(),
+                    Some(prop.get),
+                    None,
+                    None,
+                    ptr::null_mut(),
+                );
+            }
+        }
+    }
+
+    /// Fill in the function pointers of the class from the `ObjectImpl`.
+    pub(crate) fn init_callbacks<T: ObjectImpl>(&mut self) {
         unsafe extern "C" fn rust_unparent<T: ObjectImpl>(obj: *mut Object) {
-            let f = T::UNPARENT.unwrap();
-            f((&*obj).unsafe_cast::<T>())
+            if let Some(f) = T::PRE_UNPARENT {
+                callback::call(obj, f);
+            }
+
+            // Chain to the superclass, whose method was overwritten
+            let klass = object_class_by_name(T::TYPE.as_ptr());
+            let parent_class = object_class_get_parent(klass);
+            if let Some(parent_unparent) = (*parent_class).unparent {
+                parent_unparent(obj);
+            }
+
+            if let Some(f) = T::UNPARENT {
+                callback::call(obj, f);
+            }
+        }
+        if T::PRE_UNPARENT.is_some() || T::UNPARENT.is_some() {
+            self.unparent = Some(rust_unparent::<T>);
+        }
+    }
+}
+
+/// Implemented by marker types that `class_property!` and `qdev_prop!`
+/// generate for read-only properties, whose value is computed by a Rust
+/// function.  Only public because it is used by macros.
+pub trait PropertyGetter {
+    type Device: IsA<Object>;
+    type Value;
+
+    const GET: fn(&Self::Device) -> Self::Value;
+}
+
+pub struct PropertyRo;
+impl PropertyRo {
+    /// `get` callback for read-only properties
+    pub unsafe extern "C" fn get<G: PropertyGetter>(
+        obj: *mut Object,
+        v: *mut Visitor,
+        name: *const c_char,
+        _opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) where
+        G::Value: VisitOutput,
+    {
+        let value = callback::call(obj, G::GET);
+        let mut v = OutputVisitor::from_raw(v);
+        let result = value.visit_output(&mut v, Some(CStr::from_ptr(name)));
+        Error::ok_or_propagate(result, errp);
+    }
+}
+
+/// A read-only property of a class, see `ObjectImpl::CLASS_PROPERTIES`.
+pub struct ClassProperty<T> {
+    name: &'static CStr,
+    type_name: &'static CStr,
+    get: ObjectPropertyAccessor,
+    _phantom: PhantomData<fn(&T)>,
+}
+
+impl<T> ClassProperty<T> {
+    pub const fn new<G: PropertyGetter<Device = T>>(name: &'static CStr) -> Self
+    where
+        G::Value: VisitOutput,
+    {
+        ClassProperty {
+            name,
+            type_name: <G::Value as VisitOutput>::TYPE,
+            get: PropertyRo::get::<G>,
+            _phantom: PhantomData,
         }
-        self.unparent = T::UNPARENT.map(|_| rust_unparent::<T> as _);
     }
 }
 
+/// Build a read-only class property called `name`, whose value is
+/// computed by `getter`, a `fn(&Type) -> ValueType`:
+///
+/// ```ignore
+/// const CLASS_PROPERTIES: &'static [ClassProperty<MyDevice>] = &[
+///     class_property!(cstr!("has-dma"), MyDevice, bool, MyDevice::has_dma),
+/// ];
+/// ```
+#[macro_export]
+macro_rules! class_property {
+    ($name:expr, $type:ty, $value:ty, $getter:expr) => {{
+        struct Getter;
+        impl $crate::qom::object_impl::PropertyGetter for Getter {
+            type Device = $type;
+            type Value = $value;
+
+            const GET: fn(&$type) -> $value = $getter;
+        }
+        $crate::qom::object_impl::ClassProperty::new::<Getter>($name)
+    }};
+}
+
 impl Object {
     pub unsafe extern "C" fn rust_class_init<T: ObjectImpl>(
         klass: *mut c_void,
@@ -51,75 +307,289 @@ impl Object {
     }
 }
 
+/// What a Rust-implemented subclass `T` needs from its superclass.  It
+/// is implemented by the classes that Rust code can extend, including
+/// those defined by `qom_define_type!`.  Only public because it is used
+/// by macros.
+pub unsafe trait SuperclassImpl<T> {
+    /// The `class_init` function of `T`.
+    const CLASS_INIT: unsafe extern "C" fn(klass: *mut c_void, data: *mut c_void);
+
+    /// Initialize the fields that Rust superclasses have in `T`.  QOM
+    /// only calls the `instance_mem_init` of the type that is created.
+    unsafe fn mem_init(_obj: *mut T) {}
+}
+
+unsafe impl<T: ObjectImpl> SuperclassImpl<T> for Object {
+    const CLASS_INIT: unsafe extern "C" fn(klass: *mut c_void, data: *mut c_void) =
+        Object::rust_class_init::<T>;
+}
+
 /// Internal information on a Rust-implemented subclass of Object.
 /// Only public because it is used by macros.
 pub unsafe trait TypeImpl: ObjectType + ObjectImpl {
-    type Super: ObjectType;
+    type Super: ObjectType + SuperclassImpl<Self>;
     type Conf: ConstDefault;
     type State: Default;
 
-    const CLASS_INIT: unsafe extern "C" fn(klass: *mut c_void, data: *mut c_void);
-
     fn uninit_conf(obj: &mut MaybeUninit<Self>) -> &mut MaybeUninit<Self::Conf>;
     fn uninit_state(obj: &mut MaybeUninit<Self>) -> &mut MaybeUninit<Self::State>;
 }
 
-unsafe fn rust_type_register<T: TypeImpl + ObjectImpl>() {
+/// Add a read-only property for the counter `name` of `INSTANCE_COUNTERS`.
+/// The name is owned by the property and freed when the object is
+/// finalized.
+unsafe fn add_counter_property<T: ObjectImpl>(obj: *mut Object, name: CString) {
+    unsafe extern "C" fn get<T: ObjectImpl>(
+        obj: *mut Object,
+        v: *mut Visitor,
+        _name: *const c_char,
+        opaque: *mut c_void,
+        errp: *mut *mut bindings::Error,
+    ) {
+        let name = &*opaque.cast::<CString>();
+        let counters = callback::call(obj, T::INSTANCE_COUNTERS.unwrap());
+        let result = match counters.into_iter().find(|(n, _)| n == name) {
+            Some((_, value)) => OutputVisitor::from_raw(v).visit_u64(Some(name), value),
+            None => Err(Error::from(
+                format!("counter {} not found", name.to_string_lossy()).as_str(),
+            )),
+        };
+        Error::ok_or_propagate(result, errp);
+    }
+
+    unsafe extern "C" fn release(_obj: *mut Object, _name: *const c_char, opaque: *mut c_void) {
+        drop(Box::from_raw(opaque.cast::<CString>()));
+    }
+
+    let name = Box::new(name);
+    let name_ptr = name.as_ptr();
+    object_property_add(
+        obj,
+        name_ptr,
+        cstr!("uint64").as_ptr(),
+        Some(get::<T>),
+        None,
+        Some(release),
+        Box::into_raw(name).cast(),
+    );
+}
+
+/// Checks on the constants of a `TypeImpl` that are evaluated at compile
+/// time by `register_type`.
+struct TypeChecks<T>(PhantomData<T>);
+
+impl<T: TypeImpl> TypeChecks<T> {
+    const OK: () = {
+        assert!(
+            !T::INIT_CONF_IN_INSTANCE_INIT || T::INSTANCE_INIT.is_some(),
+            "INIT_CONF_IN_INSTANCE_INIT requires INSTANCE_INIT"
+        );
+        assert!(
+            T::INSTANCE_ALIGN == 0 || T::INSTANCE_ALIGN.is_power_of_two(),
+            "INSTANCE_ALIGN must be a power of two"
+        );
+    };
+}
+
+/// Initialize the `conf` and `state` of `T`, which can be the prefix of
+/// an object of a subclass.  Only public because it is used by macros.
+pub unsafe fn mem_init_fields<T: TypeImpl>(obj: *mut T) {
+    let obj: &mut MaybeUninit<T> = &mut *(obj.cast());
+
+    if !T::INIT_CONF_IN_INSTANCE_INIT {
+        T::uninit_conf(obj).write(ConstDefault::DEFAULT);
+    }
+    T::uninit_state(obj).write(Default::default());
+}
+
+/// Register the QOM type `T`.  The macros that define types do not
+/// register them, so this function must be called before the first
+/// instance of `T` is created, for example from the module's init
+/// function; it can also register a type only under some conditions,
+/// for example depending on the command line.
+///
+/// Fails if a type with the same name already exists.
+pub fn register_type<T: TypeImpl>() -> Result<()> {
+    register_type_with_init::<T>(None)
+}
+
+/// Run `INSTANCE_INIT` and add the properties for `INSTANCE_COUNTERS`.
+pub(crate) unsafe fn instance_init<T: TypeImpl>(obj: *mut c_void) {
+    if let Some(f) = T::INSTANCE_INIT {
+        f(&mut *(obj.cast()));
+    }
+    if let Some(f) = T::INSTANCE_COUNTERS {
+        for (name, _) in f(&*obj.cast::<T>()) {
+            add_counter_property::<T>(obj.cast(), name);
+        }
+    }
+}
+
+/// Register the QOM type `T` like [`register_type`].  If `instance_init`
+/// is not `None`, it replaces the `instance_init` function of the type;
+/// it must call [`instance_init`] for `T`.
+pub(crate) fn register_type_with_init<T: TypeImpl>(
+    instance_init: Option<unsafe extern "C" fn(*mut c_void)>,
+) -> Result<()> {
     unsafe extern "C" fn rust_instance_mem_init<T: TypeImpl>(obj: *mut c_void) {
         let obj: &mut std::mem::MaybeUninit<T> = &mut *(obj.cast());
 
-        T::uninit_conf(obj).write(ConstDefault::DEFAULT);
-        T::uninit_state(obj).write(Default::default());
+        <T::Super as SuperclassImpl<T>>::mem_init(obj.as_mut_ptr());
+        mem_init_fields::<T>(obj.as_mut_ptr());
+        if let Some(free) = T::FREE {
+            (*obj.as_mut_ptr().cast::<Object>()).free = free;
+        }
     }
 
+    unsafe extern "C" fn rust_instance_init<T: TypeImpl>(obj: *mut c_void) {
+        self::instance_init::<T>(obj);
+    }
+
+    unsafe extern "C" fn rust_instance_post_init<T: TypeImpl>(obj: *mut c_void) {
+        callback::call(obj.cast::<Object>(), T::INSTANCE_POST_INIT.unwrap());
+    }
+
+    // QOM calls instance_finalize starting from the most derived class,
+    // so this drops `conf` and `state` while `base` is still fully valid;
+    // only afterwards the superclasses' instance_finalize functions tear
+    // down `base`.  Because `base` is a ManuallyDrop, drop_in_place does
+    // not touch it.
     unsafe extern "C" fn rust_instance_finalize<T: TypeImpl>(obj: *mut c_void) {
         let obj: *mut T = obj.cast();
         drop_in_place(obj);
     }
 
+    // Evaluating the constant turns a misconfigured TypeImpl into a
+    // compile-time error for the instantiation of register_type::<T>.
+    let () = TypeChecks::<T>::OK;
+
+    // SAFETY: T::TYPE is a valid C string
+    if unsafe { !object_class_by_name(T::TYPE.as_ptr()).is_null() } {
+        return Err(Error::from(
+            format!("type {} already registered", T::TYPE.to_string_lossy()).as_str(),
+        ));
+    }
+
+    // QOM copies the names of the interfaces
+    let user_creatable = [
+        InterfaceInfo {
+            type_: cstr!("user-creatable").as_ptr(),
+        },
+        InterfaceInfo { type_: ptr::null() },
+    ];
+
     let ti = TypeInfo {
         name: T::TYPE.as_ptr(),
         parent: T::Super::TYPE.as_ptr(),
-        instance_size: mem::size_of::<T>(),
+        instance_size: mem::size_of::<T>() + T::EXTRA_INSTANCE_BYTES,
+        instance_align: T::INSTANCE_ALIGN.max(mem::align_of::<T>()),
         instance_mem_init: Some(rust_instance_mem_init::<T>),
+        instance_init: if instance_init.is_some() {
+            instance_init
+        } else if T::INSTANCE_INIT.is_some() || T::INSTANCE_COUNTERS.is_some() {
+            Some(rust_instance_init::<T>)
+        } else {
+            None
+        },
+        instance_post_init: T::INSTANCE_POST_INIT.map(|_| rust_instance_post_init::<T> as _),
         instance_finalize: Some(rust_instance_finalize::<T>),
-        class_init: Some(T::CLASS_INIT),
+        class_init: Some(<T::Super as SuperclassImpl<T>>::CLASS_INIT),
+        interfaces: if T::USER_CREATABLE {
+            user_creatable.as_ptr()
+        } else {
+            ptr::null()
+        },
 
         // SAFETY: TypeInfo is defined in C and all fields are okay to be zeroed
         ..Zeroed::zeroed()
     };
 
-    type_register(&ti)
+    // SAFETY: TypeImpl guarantees that the TypeInfo matches the layout of T
+    unsafe {
+        type_register(&ti);
+    }
+    Ok(())
 }
 
+/// Define a QOM type implemented in Rust.  The struct has three fields,
+/// `base`, `conf` and `state`.  When the object is finalized, `conf` and
+/// then `state` are dropped before the superclass finalizes `base`, so
+/// their `Drop` implementations can still use the superclass's resources.
+/// The type still has to be registered with [`register_type`].
+///
+/// Only the direct parent needs to be listed after `@extends`: the
+/// struct is `IsA<P>` for every `P` that its parent is `IsA<P>`, so
+/// upcasts work all the way to `Object`.  Further ancestors can be
+/// listed after the parent, separated by commas; they are checked
+/// at compile time but are otherwise redundant.
+///
+/// The parent can itself be defined with `qom_define_type!`.  Its
+/// `conf` and `state` are then initialized before those of the subclass
+/// and dropped after them.
 #[macro_export]
 macro_rules! qom_define_type {
     ($name:expr, $struct:ident, $conf_ty:ty, $state_ty:ty; @extends $super:ty $(,$supers:ty)*) => {
         $crate::with_offsets! {
             #[repr(C)]
             struct $struct {
-                // self.base dropped by call to superclass instance_finalize
+                // self.base dropped by call to superclass instance_finalize,
+                // after self.conf and self.state
                 base: std::mem::ManuallyDrop<$super>,
                 conf: $conf_ty,
                 state: $state_ty,
             }
         }
 
-        // Define IsA markers for the struct itself and all the superclasses
-        $crate::qom_isa!($struct, $super $(,$supers)*);
+        // Define IsA markers for all the superclasses; the struct itself
+        // is covered by the blanket implementation of IsA.
+        // SAFETY: base is the first field, and it is a $super
+        unsafe impl<P: $crate::ObjectType> $crate::qom::refs::IsA<P> for $struct
+        where
+            $super: $crate::qom::refs::IsA<P>,
+        {
+        }
+
+        impl<P: $crate::ObjectType> AsRef<P> for $struct
+        where
+            $super: $crate::qom::refs::IsA<P>,
+        {
+            fn as_ref(&self) -> &P {
+                use $crate::ObjectCast;
+                self.upcast::<P>()
+            }
+        }
+
+        // The other ancestors, if any, must be ancestors of $super
+        const _: fn() = || {
+            fn is_ancestor<T: $crate::qom::refs::IsA<P>, P: $crate::ObjectType>() {}
+            $(is_ancestor::<$super, $supers>();)*
+        };
 
         unsafe impl $crate::qom::object::ObjectType for $struct {
             const TYPE: &'static std::ffi::CStr = $name;
         }
 
+        // Rust subclasses of $struct are initialized like those of $super,
+        // plus the fields of $struct
+        unsafe impl<T> $crate::qom::object_impl::SuperclassImpl<T> for $struct
+        where
+            $super: $crate::qom::object_impl::SuperclassImpl<T>,
+        {
+            const CLASS_INIT: unsafe extern "C" fn(klass: *mut std::ffi::c_void, data: *mut std::ffi::c_void)
+                = <$super as $crate::qom::object_impl::SuperclassImpl<T>>::CLASS_INIT;
+
+            unsafe fn mem_init(obj: *mut T) {
+                <$super as $crate::qom::object_impl::SuperclassImpl<T>>::mem_init(obj);
+                $crate::qom::object_impl::mem_init_fields::<$struct>(obj.cast());
+            }
+        }
+
         unsafe impl $crate::qom::object_impl::TypeImpl for $struct {
             type Super = $super;
             type Conf = $conf_ty;
             type State = $state_ty;
 
-            const CLASS_INIT: unsafe extern "C" fn(klass: *mut std::ffi::c_void, data: *mut std::ffi::c_void)
-                = <$super>::rust_class_init::<Self>;
-
             fn uninit_conf(obj: &mut std::mem::MaybeUninit::<Self>) -> &mut std::mem::MaybeUninit<$conf_ty> {
                 use std::ptr::addr_of_mut;
 
@@ -137,7 +607,7 @@ macro_rules! qom_define_type {
             }
         }
 
-        // TODO: call rust_type_register
+        // The type is registered separately, with register_type
     };
 }
 
@@ -147,3 +617,10 @@ macro_rules! conf_type {
         <$type as $crate::qom::object_impl::TypeImpl>::Conf
     };
 }
+
+#[macro_export]
+macro_rules! state_type {
+    ($type:ty) => {
+        <$type as $crate::qom::object_impl::TypeImpl>::State
+    };
+}
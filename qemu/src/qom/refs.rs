@@ -2,18 +2,29 @@
 //!
 //! @author Paolo Bonzini
 
+#[cfg(all(debug_assertions, feature = "check-bql"))]
+use crate::bindings::bql_locked;
+use crate::bindings::object_class_by_name;
+use crate::bindings::object_class_is_abstract;
 use crate::bindings::object_dynamic_cast;
+use crate::bindings::object_new;
+use crate::bindings::object_resolve_path_type;
 use crate::bindings::Object;
 use crate::bindings::{object_ref, object_unref};
 
 use crate::qom::object::ObjectMethods;
 use crate::qom::object::ObjectType;
+use crate::qom::object_impl::TypeImpl;
 
 use std::borrow::Borrow;
+#[cfg(debug_assertions)]
+use std::cell::Cell;
+use std::ffi::{c_void, CStr, CString};
 use std::fmt::{self, Debug};
 use std::mem::ManuallyDrop;
+use std::mem::MaybeUninit;
 use std::ops::Deref;
-use std::ptr::NonNull;
+use std::ptr::{self, NonNull};
 
 /// Marker trait: `Self` can be statically upcasted to `P` (i.e. `P` is a direct
 /// or indirect parent of `Self`).
@@ -131,15 +142,129 @@ where
     {
         &*(self.as_ptr().cast::<U>())
     }
+
+    /// Unconditional cast to an arbitrary QOM type, like `unsafe_cast`,
+    /// that is verified at runtime in debug builds.  In release builds
+    /// it is exactly the same as `unsafe_cast`.
+    ///
+    /// # Safety
+    ///
+    /// Same as `unsafe_cast`.  Debug builds panic if the dynamic type of
+    /// `self` is not `U` or a subclass of it.
+    unsafe fn checked_cast<'a, U: ObjectType>(self) -> &'a U
+    where
+        Self: 'a,
+    {
+        #[cfg(debug_assertions)]
+        {
+            if self.dynamic_cast::<U>().is_none() {
+                panic!(
+                    "invalid cast of object of type {} to {}",
+                    self.unsafe_cast::<Object>().typename(),
+                    U::TYPE.to_string_lossy()
+                );
+            }
+        }
+        self.unsafe_cast::<U>()
+    }
 }
 
 impl<T: ObjectType> ObjectCast for &T {}
 
+/// A borrowed reference to a QOM object, that does not touch the
+/// reference count.
+///
+/// `ObjectRef<'a, T>` is the same as `&'a T`, but states explicitly in
+/// struct fields and function signatures that the object is borrowed
+/// rather than owned, and is more readable than `&'a T` next to
+/// [`Owned<T>`].  The object's methods are available through `Deref`;
+/// like those of `Owned`, the functions of `ObjectRef` itself are
+/// associated functions.
+///
+/// ```ignore
+/// struct BusWalker<'a> {
+///     bus: ObjectRef<'a, BusState>,
+/// }
+/// ```
+#[repr(transparent)]
+pub struct ObjectRef<'a, T: ObjectType>(&'a T);
+
+impl<'a, T: ObjectType> ObjectRef<'a, T> {
+    pub fn new(obj: &'a T) -> Self {
+        ObjectRef(obj)
+    }
+
+    /// Return the reference with its full lifetime, unlike `Deref`
+    /// whose result is tied to the `ObjectRef` itself.
+    pub fn get(this: Self) -> &'a T {
+        this.0
+    }
+
+    /// Perform a cast to a superclass
+    pub fn upcast<U: ObjectType>(this: Self) -> ObjectRef<'a, U>
+    where
+        T: IsA<U>,
+    {
+        ObjectRef(this.0.upcast::<U>())
+    }
+
+    /// Perform a cast between QOM types, checked at runtime.
+    pub fn dynamic_cast<U: ObjectType>(this: Self) -> Option<ObjectRef<'a, U>> {
+        this.0.dynamic_cast::<U>().map(ObjectRef)
+    }
+}
+
+impl<T: ObjectType> Clone for ObjectRef<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ObjectType> Copy for ObjectRef<'_, T> {}
+
+impl<'a, T: ObjectType> From<&'a T> for ObjectRef<'a, T> {
+    fn from(obj: &'a T) -> Self {
+        ObjectRef(obj)
+    }
+}
+
+impl<'a, T: ObjectType> From<&'a Owned<T>> for ObjectRef<'a, T> {
+    fn from(obj: &'a Owned<T>) -> Self {
+        ObjectRef(obj.deref())
+    }
+}
+
+impl<T: ObjectType> Deref for ObjectRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0
+    }
+}
+
+impl<T: IsA<Object>> Debug for ObjectRef<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.debug_fmt(f)
+    }
+}
+
 /// An owned reference to a QOM object.
 ///
 /// Like [`std::sync::Arc`], references are added with [`Clone::clone`] and removed
 /// by dropping the `Owned`.
+///
+/// `Owned<T>` has the same layout as a C `T *`, and `Option<Owned<T>>`
+/// can hold a NULL pointer as well.
+///
+/// `Owned<T>` deliberately does not store a separate pointer to the
+/// `Object`.  Every QOM struct starts with its parent, so the `Object`
+/// is at offset zero and casting to it (or to any other ancestor) is a
+/// pointer cast, with no work left for a cached pointer to save; this
+/// follows from the layout and was not benchmarked.  A second pointer
+/// would also break the layout guarantee above, on which link
+/// properties rely.
 #[derive(PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct Owned<T: ObjectType>(NonNull<T>);
 
 // QOM knows how to handle reference counting across threads, but sending
@@ -151,6 +276,103 @@ pub struct Owned<T: ObjectType>(NonNull<T>);
 //unsafe impl<T: Sync + ObjectType> Send for Owned<T> {}
 //unsafe impl<T: ObjectType> Sync for Owned<T> {}
 
+// Number of live `Owned` references on this thread.  `Owned` is not
+// `Send`, so a thread-local counter is enough and keeps tests that run
+// in parallel independent of each other.
+#[cfg(debug_assertions)]
+thread_local! {
+    static LIVE_REFS: Cell<usize> = Cell::new(0);
+}
+
+fn live_refs() -> usize {
+    #[cfg(debug_assertions)]
+    {
+        LIVE_REFS.with(Cell::get)
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        0
+    }
+}
+
+#[allow(unused_variables)]
+fn update_live_refs(f: fn(usize) -> usize) {
+    #[cfg(debug_assertions)]
+    LIVE_REFS.with(|count| count.set(f(count.get())));
+}
+
+/// With the `check-bql` feature, check in debug builds that the current
+/// thread can access objects of type `T`.  Accessing an object from the
+/// wrong thread is a data race, which is otherwise hard to debug.
+#[inline]
+fn check_bql<T: ObjectType>() {
+    if !T::THREAD_SAFE {
+        assert_bql_locked(T::TYPE, "accessed");
+    }
+}
+
+/// With the `check-bql` feature, panic in debug builds if the current
+/// thread does not hold the BQL.  `type_name` and `action` describe
+/// the operation that needs the lock.
+#[inline]
+pub(crate) fn assert_bql_locked(type_name: &CStr, action: &str) {
+    #[cfg(all(debug_assertions, feature = "check-bql"))]
+    {
+        // SAFETY: bql_locked only reads a thread-local variable
+        let locked = unsafe { bql_locked() };
+        assert!(
+            locked,
+            "object of type {} {} by a thread that does not hold the BQL",
+            type_name.to_string_lossy(),
+            action
+        );
+    }
+    #[cfg(not(all(debug_assertions, feature = "check-bql")))]
+    let _ = (type_name, action);
+}
+
+/// Test helper that checks that no `Owned` reference is leaked by the
+/// code that runs while the guard is alive.  When the guard is dropped,
+/// it panics if the number of `Owned` references that are alive on the
+/// current thread has grown since the guard was created.
+///
+/// The check is only done in debug builds.
+///
+/// ```ignore
+/// let _guard = LeakGuard::new();
+/// let obj = TestObject::new();
+/// std::mem::forget(obj); // panics when _guard goes out of scope
+/// ```
+#[derive(Debug)]
+pub struct LeakGuard {
+    baseline: usize,
+}
+
+impl LeakGuard {
+    pub fn new() -> Self {
+        LeakGuard {
+            baseline: live_refs(),
+        }
+    }
+}
+
+impl Default for LeakGuard {
+    fn default() -> Self {
+        LeakGuard::new()
+    }
+}
+
+impl Drop for LeakGuard {
+    fn drop(&mut self) {
+        // Do not cause a double panic if the test already failed
+        if std::thread::panicking() {
+            return;
+        }
+        let leaked = live_refs().saturating_sub(self.baseline);
+        assert!(leaked == 0, "{} QOM object reference(s) leaked", leaked);
+    }
+}
+
 impl<T: ObjectType> Owned<T> {
     /// Obtain a reference from a raw C pointer
     ///
@@ -159,6 +381,8 @@ impl<T: ObjectType> Owned<T> {
     /// Typically this function will only be used by low level bindings
     /// to C APIs.
     pub unsafe fn from_raw(ptr: *const T) -> Self {
+        update_live_refs(|n| n + 1);
+
         // SAFETY NOTE: while NonNull requires a mutable pointer,
         // only Deref is implemented so the pointer passed to from_raw
         // remains const
@@ -173,6 +397,7 @@ impl<T: ObjectType> Owned<T> {
     /// obtain an `Owned` safely, use `ObjectType::new()`.
     pub unsafe fn from(obj: &T) -> Self {
         object_ref(obj.unsafe_cast::<Object>().as_mut_ptr());
+        update_live_refs(|n| n + 1);
 
         // SAFETY NOTE: while NonNull requires a mutable pointer,
         // only Deref is implemented so the pointer passed to from_raw
@@ -180,6 +405,70 @@ impl<T: ObjectType> Owned<T> {
         Owned(NonNull::new_unchecked(obj.as_mut_ptr()))
     }
 
+    /// Release the reference now.  This is the same as dropping `src`,
+    /// but makes the intent explicit.
+    ///
+    /// Like the other methods of `Owned`, this is an associated function
+    /// (`Owned::release(obj)`) so that it cannot be confused with a method
+    /// of the pointed-to object.
+    pub fn release(src: Owned<T>) {
+        drop(src);
+    }
+
+    /// Consume the `Owned` without releasing the reference, and return a
+    /// reference to the object that is valid for the rest of the program.
+    /// Useful for singletons that are never destroyed.  The leaked reference
+    /// is not reported by [`LeakGuard`].
+    pub fn leak(src: Owned<T>) -> &'static T {
+        let src = ManuallyDrop::new(src);
+        update_live_refs(|n| n - 1);
+
+        // SAFETY: the reference that src held is never dropped,
+        // so the object remains alive forever
+        unsafe { &*src.0.as_ptr() }
+    }
+
+    /// Consume the `Owned` without releasing the reference, and return
+    /// a pointer that can be stored as the opaque of a C callback.  The
+    /// reference is held until [`Owned::from_opaque`] recovers it; until
+    /// then it is still reported by [`LeakGuard`].
+    pub fn into_opaque(src: Owned<T>) -> *mut c_void {
+        let src = ManuallyDrop::new(src);
+        src.0.as_ptr().cast()
+    }
+
+    /// Recover the reference that was stored in a C opaque pointer by
+    /// [`Owned::into_opaque`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Owned::<T>::into_opaque`, and each call to
+    /// `into_opaque` must be matched by at most one call to `from_opaque`.
+    /// To access the object without taking back the reference, for example
+    /// in a callback that can run multiple times, cast `ptr` to `&T`.
+    pub unsafe fn from_opaque(ptr: *mut c_void) -> Owned<T> {
+        Owned(NonNull::new_unchecked(ptr.cast()))
+    }
+
+    /// Find the object at `path` in the QOM tree, for example
+    /// `/objects/rng0` for an object created with `-object ...,id=rng0`.
+    /// Partial paths are accepted if they match a single object.  Return
+    /// `None` if no object of type `T` is found, or if a partial path
+    /// is ambiguous.
+    ///
+    /// Note that the object can be embedded in its parent; the reference
+    /// must not outlive the parent in that case.
+    pub fn resolve_path(path: &str) -> Option<Owned<T>> {
+        let path = CString::new(path).ok()?;
+        // SAFETY: both strings are valid; the result is NULL or an object
+        // of type T, which is kept alive by the QOM tree until from()
+        // takes a reference
+        unsafe {
+            let obj = object_resolve_path_type(path.as_ptr(), T::TYPE.as_ptr(), ptr::null_mut());
+            obj.cast::<T>().as_ref().map(|obj| Owned::from(obj))
+        }
+    }
+
     /// Perform a cast to a superclass
     pub fn upcast<U: ObjectType>(src: Owned<T>) -> Owned<U>
     where
@@ -198,6 +487,19 @@ impl<T: ObjectType> Owned<T> {
 
     /// Perform a cast between QOM types.  The check that U is indeed
     /// the dynamic type of `self` happens at runtime.
+    ///
+    /// Neither success nor failure touch the reference count: on failure
+    /// the original reference is given back, so that several types can
+    /// be tried in turn cheaply:
+    ///
+    /// ```ignore
+    /// let obj = match Owned::dynamic_cast::<PL011State>(obj) {
+    ///     Ok(pl011) => return handle_pl011(pl011),
+    ///     Err(obj) => obj,
+    /// };
+    /// let obj = match Owned::dynamic_cast::<HPETState>(obj) {
+    ///     ...
+    /// ```
     pub fn dynamic_cast<U: ObjectType>(src: Owned<T>) -> Result<Owned<U>, Owned<T>> {
         // override automatic drop to skip the unref/ref
         let src = ManuallyDrop::new(src);
@@ -205,12 +507,26 @@ impl<T: ObjectType> Owned<T> {
             // get the ownership back from the ManuallyDrop<>
             None => Err(ManuallyDrop::into_inner(src)),
 
-            // SAFETY: the ref is moved (thanks to ManuallyDrop) from
+            // the ref is moved (thanks to ManuallyDrop) from
             // self to casted_ref
-            Some(casted_ref) => Ok(unsafe { Owned::<U>::from_raw(casted_ref) }),
+            Some(casted_ref) => Ok(Owned(NonNull::from(casted_ref))),
         }
     }
 
+    /// Cast the object to `U`, checking at runtime, and call `f` on the
+    /// result.  Unlike [`Owned::dynamic_cast`], `this` is only borrowed
+    /// and no new reference is created, so this is cheaper for a quick
+    /// look at the object:
+    ///
+    /// ```ignore
+    /// let irqs = Owned::with_downcast(&obj, |dev: &PL011State| dev.num_irqs());
+    /// ```
+    ///
+    /// Return `None`, without calling `f`, if the object is not a `U`.
+    pub fn with_downcast<U: ObjectType, R, F: FnOnce(&U) -> R>(this: &Owned<T>, f: F) -> Option<R> {
+        this.dynamic_cast::<U>().map(f)
+    }
+
     /// Unconditional cast to an arbitrary QOM type.
     ///
     /// # Safety
@@ -221,7 +537,120 @@ impl<T: ObjectType> Owned<T> {
         // override automatic drop to skip the unref/ref
         let src = ManuallyDrop::new(src);
         let casted_ref = src.unsafe_cast::<U>();
-        Owned::<U>::from_raw(casted_ref)
+        Owned(NonNull::from(casted_ref))
+    }
+}
+
+impl Owned<Object> {
+    /// Create an object whose type is only known at runtime, for example
+    /// because it comes from the command line.  The result can be
+    /// converted to the concrete type with [`Owned::dynamic_cast`].
+    ///
+    /// Unlike `object_new()`, this fails instead of aborting if the type
+    /// does not exist or is abstract.
+    pub fn new_dynamic(name: &CStr) -> crate::Result<Owned<Object>> {
+        // SAFETY: name is a valid C string, and classes are never freed
+        let klass = unsafe { object_class_by_name(name.as_ptr()) };
+        if klass.is_null() {
+            return Err(crate::Error::from(
+                format!("unknown type {}", name.to_string_lossy()).as_str(),
+            ));
+        }
+        // SAFETY: klass is a valid class
+        if unsafe { object_class_is_abstract(klass) } {
+            return Err(crate::Error::from(
+                format!("type {} is abstract", name.to_string_lossy()).as_str(),
+            ));
+        }
+        // SAFETY: the object created by object_new is allocated on
+        // the heap and has a reference count of 1
+        unsafe { Ok(Owned::from_raw(object_new(name.as_ptr()))) }
+    }
+}
+
+/// A reference to an object of any QOM type, for storing objects of
+/// different types in the same collection.  The concrete type is
+/// recovered with a runtime check:
+///
+/// ```ignore
+/// let devs: Vec<AnyObject> = vec![pl011.into(), hpet.into()];
+/// for dev in &devs {
+///     if let Some(pl011) = dev.downcast_ref::<PL011State>() {
+///         ...
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct AnyObject(Owned<Object>);
+
+impl AnyObject {
+    pub fn new<T: IsA<Object>>(obj: Owned<T>) -> Self {
+        AnyObject(Owned::upcast::<Object>(obj))
+    }
+
+    /// Return whether the object is an instance of `T`, or of a
+    /// subclass of `T`.
+    pub fn is<T: ObjectType>(&self) -> bool {
+        self.downcast_ref::<T>().is_some()
+    }
+
+    /// Borrow the object as a `T`, if it is an instance of `T`.
+    pub fn downcast_ref<T: ObjectType>(&self) -> Option<&T> {
+        self.0.deref().dynamic_cast::<T>()
+    }
+
+    /// Convert the reference to an `Owned<T>`, or give it back if the
+    /// object is not an instance of `T`.  The reference count is not
+    /// touched.
+    pub fn downcast<T: ObjectType>(self) -> Result<Owned<T>, AnyObject> {
+        Owned::dynamic_cast::<T>(self.0).map_err(AnyObject)
+    }
+
+    /// Return the reference as an `Owned<Object>`.
+    pub fn into_inner(self) -> Owned<Object> {
+        self.0
+    }
+}
+
+impl<T: IsA<Object>> From<Owned<T>> for AnyObject {
+    fn from(obj: Owned<T>) -> Self {
+        AnyObject::new(obj)
+    }
+}
+
+impl Deref for AnyObject {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        self.0.deref()
+    }
+}
+
+impl Debug for AnyObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: TypeImpl> Owned<T> {
+    /// Return a mutable reference to the state of the object, if `this`
+    /// is the only reference to it; otherwise return `None`.  Similar to
+    /// [`std::sync::Arc::get_mut`], this can be used to set up a newly
+    /// created object before sharing it, without going through a `RefCell`
+    /// or similar.
+    pub fn get_mut(this: &mut Owned<T>) -> Option<&mut T::State> {
+        // SAFETY: upcasting to Object is always valid
+        let obj = unsafe { this.deref().unsafe_cast::<Object>() };
+        if obj.r#ref != 1 {
+            return None;
+        }
+
+        // SAFETY: there are no other references to the object, and
+        // the state was initialized by instance_mem_init
+        unsafe {
+            let uninit = &mut *this.0.as_ptr().cast::<MaybeUninit<T>>();
+            Some(T::uninit_state(uninit).assume_init_mut())
+        }
     }
 }
 
@@ -249,6 +678,7 @@ impl<T: ObjectType> Deref for Owned<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
+        check_bql::<T>();
         // SAFETY: creation method is unsafe, and whoever calls it
         // has responsibility that the pointer has a static lifetime.
         // Once that is guaranteed, reference counting ensures that
@@ -259,11 +689,13 @@ impl<T: ObjectType> Deref for Owned<T> {
 
 impl<T: ObjectType> Drop for Owned<T> {
     fn drop(&mut self) {
+        check_bql::<T>();
         // SAFETY: creation method is unsafe, and whoever calls it
         // has responsibility that the pointer is valid
         unsafe {
             object_unref(self.unsafe_cast::<Object>().as_mut_ptr());
         }
+        update_live_refs(|n| n - 1);
     }
 }
 
@@ -272,3 +704,95 @@ impl<T: IsA<Object>> Debug for Owned<T> {
         self.deref().debug_fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindings::stubs::{stub_object, StubClass};
+    use crate::bindings::DeviceState;
+    use cstr::cstr;
+
+    static OBJECT_CLASS: StubClass = StubClass(&[cstr!("object")]);
+
+    #[test]
+    fn test_owned_layout() {
+        assert_eq!(
+            std::mem::size_of::<Owned<Object>>(),
+            std::mem::size_of::<*mut Object>()
+        );
+        assert_eq!(
+            std::mem::size_of::<Option<Owned<DeviceState>>>(),
+            std::mem::size_of::<*mut DeviceState>()
+        );
+    }
+
+    #[test]
+    fn test_checked_cast() {
+        let obj = stub_object(&OBJECT_CLASS);
+        let obj: &Object = unsafe { (&obj).checked_cast::<Object>() };
+        assert!(obj.dynamic_cast::<DeviceState>().is_none());
+    }
+
+    #[test]
+    #[cfg(all(debug_assertions, feature = "check-bql"))]
+    fn test_check_bql() {
+        use crate::bindings::stubs::set_bql_locked;
+
+        let obj: &'static Object = Box::leak(Box::new(stub_object(&OBJECT_CLASS)));
+        set_bql_locked(true);
+        let owned = unsafe { Owned::from(obj) };
+        assert_eq!(owned.r#ref, 2);
+
+        // The spawned thread does not hold the BQL
+        let addr = obj as *const Object as usize;
+        let result = std::thread::spawn(move || {
+            // ManuallyDrop avoids a second panic in Drop while unwinding
+            let remote = ManuallyDrop::new(unsafe { Owned::from_raw(addr as *const Object) });
+            remote.r#ref
+        })
+        .join();
+        let msg = *result.unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(
+            msg,
+            "object of type object accessed by a thread that does not hold the BQL"
+        );
+        drop(owned);
+        assert_eq!(obj.r#ref, 1);
+    }
+
+    #[test]
+    fn test_leak_guard() {
+        use crate::bindings::stubs::set_bql_locked;
+
+        let obj: &'static Object = Box::leak(Box::new(stub_object(&OBJECT_CLASS)));
+        set_bql_locked(true);
+        let _guard = LeakGuard::new();
+        let owned = unsafe { Owned::from(obj) };
+        let clone = owned.clone();
+        drop(owned);
+        drop(clone);
+        assert_eq!(obj.r#ref, 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "1 QOM object reference(s) leaked")]
+    fn test_leak_guard_forget() {
+        use crate::bindings::stubs::set_bql_locked;
+
+        let obj: &'static Object = Box::leak(Box::new(stub_object(&OBJECT_CLASS)));
+        set_bql_locked(true);
+        let _guard = LeakGuard::new();
+        std::mem::forget(unsafe { Owned::from(obj) });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "invalid cast of object of type object to device")]
+    fn test_checked_cast_invalid() {
+        let obj = stub_object(&OBJECT_CLASS);
+        unsafe {
+            (&obj).checked_cast::<DeviceState>();
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Bit field manipulation, equivalent to `include/qemu/bitops.h`
+
+use crate::Error;
+use crate::Result;
+
+macro_rules! bitops {
+    ($bits:literal, $type:ty, $extract:ident, $deposit:ident, $try_deposit:ident) => {
+        /// Return the `length`-bit field of `value` that starts at bit `start`.
+        ///
+        /// Panics if the field does not lie within the value.
+        pub fn $extract(value: $type, start: u32, length: u32) -> $type {
+            assert!(
+                start < $bits && length > 0 && length <= $bits - start,
+                "invalid bit field"
+            );
+            (value >> start) & (<$type>::MAX >> ($bits - length))
+        }
+
+        /// Return `value` with the `length`-bit field that starts at bit
+        /// `start` replaced by `fieldval`.  Like in C, the bits of
+        /// `fieldval` that do not fit in the field are discarded; use
+        #[doc = concat!("[`", stringify!($try_deposit), "`] if that would be a bug.")]
+        ///
+        /// Panics if the field does not lie within the value.
+        pub fn $deposit(value: $type, start: u32, length: u32, fieldval: $type) -> $type {
+            assert!(
+                start < $bits && length > 0 && length <= $bits - start,
+                "invalid bit field"
+            );
+            let mask = (<$type>::MAX >> ($bits - length)) << start;
+            (value & !mask) | ((fieldval << start) & mask)
+        }
+
+        #[doc = concat!("Same as [`", stringify!($deposit), "`], but fail if `fieldval` does not")]
+        /// fit in `length` bits instead of truncating it, for example
+        /// because it comes from the guest or the user and must be
+        /// validated.
+        pub fn $try_deposit(
+            value: $type,
+            start: u32,
+            length: u32,
+            fieldval: $type,
+        ) -> Result<$type> {
+            if length < $bits && fieldval >> length != 0 {
+                return Err(Error::from(
+                    format!("value {:#x} does not fit in {} bits", fieldval, length).as_str(),
+                ));
+            }
+            Ok($deposit(value, start, length, fieldval))
+        }
+    };
+}
+
+bitops!(32, u32, extract32, deposit32, try_deposit32);
+bitops!(64, u64, extract64, deposit64, try_deposit64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        assert_eq!(extract32(0x1234_5678, 4, 8), 0x67);
+        assert_eq!(extract32(0x8000_0000, 31, 1), 1);
+        assert_eq!(extract32(0xdead_beef, 0, 32), 0xdead_beef);
+        assert_eq!(extract64(0x1234_5678_9abc_def0, 60, 4), 0x1);
+    }
+
+    #[test]
+    fn test_deposit() {
+        assert_eq!(deposit32(0xffff_ffff, 4, 8, 0), 0xffff_f00f);
+        // Bits that do not fit are discarded
+        assert_eq!(deposit32(0, 28, 4, 0x1f), 0xf000_0000);
+        assert_eq!(deposit32(0, 0, 32, 0x1234_5678), 0x1234_5678);
+        assert_eq!(deposit64(0, 63, 1, 1), 1 << 63);
+    }
+
+    #[test]
+    fn test_try_deposit() {
+        assert_eq!(try_deposit32(0, 4, 4, 0xf).unwrap(), 0xf0);
+        assert!(try_deposit32(0, 4, 4, 0x10).is_err());
+        assert_eq!(try_deposit32(0, 0, 32, u32::MAX).unwrap(), u32::MAX);
+        assert_eq!(try_deposit64(0, 62, 2, 3).unwrap(), 3 << 62);
+        assert!(try_deposit64(0, 62, 2, 4).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid bit field")]
+    fn test_invalid_field() {
+        deposit32(0, 30, 4, 0);
+    }
+}
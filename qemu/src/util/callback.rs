@@ -0,0 +1,43 @@
+//! Helpers for C callbacks implemented in Rust
+
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+
+use crate::qom::object::ObjectType;
+use crate::qom::refs::IsA;
+
+/// Call `f` with the object that C passed to a callback as `obj`.  This
+/// is the body of most `extern "C"` trampolines:
+///
+/// ```ignore
+/// unsafe extern "C" fn rust_cold_reset<T: DeviceImpl>(obj: *mut DeviceState) {
+///     callback::call(obj, T::COLD_RESET.unwrap())
+/// }
+/// ```
+///
+/// `obj` is usually declared as a superclass of `T`, and `T: IsA<U>`
+/// rejects casts to unrelated types at compile time.
+///
+/// Unwinding out of an `extern "C"` function is undefined behavior, so
+/// QEMU aborts if `f` panics.
+///
+/// # Safety
+///
+/// `obj` must point to a valid `T`.
+pub unsafe fn call<T: IsA<U>, U: ObjectType, R, F: FnOnce(&T) -> R>(obj: *mut U, f: F) -> R {
+    call_opaque(obj.cast(), f)
+}
+
+/// Call `f` with the opaque pointer that C passed to a callback, for
+/// callbacks whose argument is not statically typed.
+///
+/// As with [`call`], QEMU aborts if `f` panics.
+///
+/// # Safety
+///
+/// `opaque` must point to a valid `T`.
+pub unsafe fn call_opaque<T, R, F: FnOnce(&T) -> R>(opaque: *mut c_void, f: F) -> R {
+    let obj = &*opaque.cast::<T>();
+    panic::catch_unwind(AssertUnwindSafe(|| f(obj))).unwrap_or_else(|_| process::abort())
+}
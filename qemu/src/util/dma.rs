@@ -0,0 +1,108 @@
+//! Host memory for devices that perform DMA
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::slice;
+
+use crate::bindings::qemu_memalign;
+use crate::bindings::qemu_vfree;
+
+/// A zeroed, page-aligned buffer in host memory, allocated with
+/// `qemu_memalign()` and freed with `qemu_vfree()` when dropped.
+///
+/// It is scratch memory for the device, for example to hold descriptors
+/// that are copied out of guest memory before processing them, so that
+/// the guest cannot modify them in the meanwhile.  Unlike a
+/// [`MemoryRegion`](crate::MemoryRegion), it is not visible to the guest.
+///
+/// ```ignore
+/// let mut desc = DmaBuf::new(4096);
+/// ram.read(desc_addr, &mut desc[..DESC_SIZE]);
+/// ```
+pub struct DmaBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl DmaBuf {
+    /// Allocate a buffer of `size` bytes.  Aborts if the memory cannot
+    /// be allocated, like `qemu_memalign()`.  Zero-sized buffers do not
+    /// allocate any memory.
+    pub fn new(size: usize) -> Self {
+        if size == 0 {
+            return DmaBuf {
+                ptr: NonNull::dangling(),
+                len: 0,
+            };
+        }
+
+        // SAFETY: qemu_memalign aborts instead of returning NULL; the
+        // memory is then zeroed, so that it can be exposed as a slice
+        unsafe {
+            let ptr = qemu_memalign(page_size(), size).cast::<u8>();
+            ptr.write_bytes(0, size);
+            DmaBuf {
+                ptr: NonNull::new_unchecked(ptr),
+                len: size,
+            }
+        }
+    }
+
+    /// Return a pointer to the buffer, for C functions that fill it.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+}
+
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(size).expect("invalid page size")
+}
+
+impl Deref for DmaBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the buffer is initialized and owned by self
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for DmaBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the buffer is initialized and owned by self
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuf {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: the buffer was allocated by qemu_memalign
+            unsafe {
+                qemu_vfree(self.ptr.as_ptr().cast());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DmaBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DmaBuf")
+            .field("ptr", &self.ptr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_size() {
+        assert!(page_size().is_power_of_two());
+    }
+}
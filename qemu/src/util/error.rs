@@ -4,23 +4,47 @@
 
 use crate::bindings;
 use crate::bindings::error_free;
+use crate::bindings::error_get_class;
 use crate::bindings::error_get_pretty;
-use crate::bindings::error_setg_internal;
+use crate::bindings::error_set_internal;
+
+use crate::foreign_enum;
 
 use cstr::cstr;
 
+use libc::c_int;
+
 use std::ffi::CStr;
 use std::fmt::{self, Display};
 use std::ptr;
 
 use crate::util::foreign::{CloneToForeign, FromForeign, OwnedPointer};
 
+foreign_enum! {
+    #[repr(i32)]
+    /// The QAPI `ErrorClass` of an error, which QMP clients can use to
+    /// tell errors apart.  Almost all errors are `GenericError`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum ErrorClass {
+        #[default]
+        GenericError = 0,
+        CommandNotFound = 1,
+        DeviceNotActive = 2,
+        DeviceNotFound = 3,
+        KVMMissingCap = 4,
+    }
+    fallback GenericError;
+}
+
 #[derive(Debug, Default)]
 pub struct Error {
     msg: Option<String>,
     /// Appends the print string of the error to the msg if not None
     cause: Option<Box<dyn std::error::Error>>,
     location: Option<(String, u32)>,
+    error_class: ErrorClass,
+    errno: Option<c_int>,
 }
 
 impl std::error::Error for Error {
@@ -37,13 +61,8 @@ impl std::error::Error for Error {
     }
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut prefix = "";
-        if let Some((ref file, line)) = self.location {
-            write!(f, "{}:{}", file, line)?;
-            prefix = ": ";
-        }
+impl Error {
+    fn fmt_message(&self, f: &mut fmt::Formatter, mut prefix: &str) -> fmt::Result {
         if let Some(ref msg) = self.msg {
             write!(f, "{}{}", prefix, msg)?;
             prefix = ": ";
@@ -57,12 +76,86 @@ impl Display for Error {
     }
 }
 
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut prefix = "";
+        if let Some((ref file, line)) = self.location {
+            write!(f, "{}:{}", file, line)?;
+            prefix = ": ";
+        }
+        self.fmt_message(f, prefix)
+    }
+}
+
+/// The message of an error, without the location
+#[cfg(feature = "serde")]
+struct Message<'a>(&'a Error);
+
+#[cfg(feature = "serde")]
+impl Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_message(f, "")
+    }
+}
+
+/// Serialized form of an `Error`.  The cause is not serializable, so it
+/// only survives as part of the message.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedError {
+    message: String,
+    location: Option<SerializedLocation>,
+    #[serde(default)]
+    error_class: ErrorClass,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedLocation {
+    file: String,
+    line: u32,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedError {
+            message: Message(self).to_string(),
+            location: self
+                .location
+                .as_ref()
+                .map(|(file, line)| SerializedLocation {
+                    file: file.clone(),
+                    line: *line,
+                }),
+            error_class: self.error_class,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Error {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let err = SerializedError::deserialize(deserializer)?;
+        Ok(Error {
+            msg: Some(err.message),
+            cause: None,
+            location: err.location.map(|loc| (loc.file, loc.line)),
+            error_class: err.error_class,
+            errno: None,
+        })
+    }
+}
+
 impl From<&str> for Error {
     fn from(msg: &str) -> Self {
         Error {
             msg: Some(String::from(msg)),
             cause: None,
             location: None,
+            error_class: ErrorClass::GenericError,
+            errno: None,
         }
     }
 }
@@ -71,13 +164,70 @@ impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
         Error {
             msg: None,
+            errno: error.raw_os_error(),
             cause: Some(Box::new(error)),
             location: None,
+            error_class: ErrorClass::GenericError,
+        }
+    }
+}
+
+/// The boxed error becomes the cause, without wrapping it again.
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(error: Box<dyn std::error::Error>) -> Self {
+        Error {
+            msg: None,
+            cause: Some(error),
+            location: None,
+            error_class: ErrorClass::GenericError,
+            errno: None,
+        }
+    }
+}
+
+/// Wrapper that lets an `anyhow::Error` be stored as the cause of
+/// an `Error`, while printing the whole chain of contexts.
+#[cfg(feature = "anyhow")]
+#[derive(Debug)]
+struct AnyhowCause(anyhow::Error);
+
+#[cfg(feature = "anyhow")]
+impl Display for AnyhowCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl std::error::Error for AnyhowCause {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        Error {
+            msg: None,
+            cause: Some(Box::new(AnyhowCause(error))),
+            location: None,
+            error_class: ErrorClass::GenericError,
+            errno: None,
         }
     }
 }
 
 impl Error {
+    /// Convert `self` to an `anyhow::Error`.  `Error` is not `Send` and
+    /// `Sync`, so neither the blanket `From` implementation provided by
+    /// `anyhow` nor a separate one can be used; instead, the message
+    /// of the resulting error includes the description of the cause.
+    #[cfg(feature = "anyhow")]
+    pub fn into_anyhow(self) -> anyhow::Error {
+        anyhow::Error::msg(self.to_string())
+    }
+
     /// Create a new error, prepending `msg` to the
     /// description of `cause`
     pub fn with_error<E: std::error::Error + 'static>(msg: &str, cause: E) -> Self {
@@ -85,6 +235,8 @@ impl Error {
             msg: Some(String::from(msg)),
             cause: Some(Box::new(cause)),
             location: None,
+            error_class: ErrorClass::GenericError,
+            errno: None,
         }
     }
 
@@ -100,6 +252,8 @@ impl Error {
             msg: Some(String::from(msg)),
             cause: Some(Box::new(cause)),
             location: Some((String::from(file), line)),
+            error_class: ErrorClass::GenericError,
+            errno: None,
         }
     }
 
@@ -109,6 +263,75 @@ impl Error {
             msg: Some(String::from(msg)),
             cause: None,
             location: Some((String::from(file), line)),
+            error_class: ErrorClass::GenericError,
+            errno: None,
+        }
+    }
+
+    /// Create a new error with the given QAPI error class
+    pub fn with_class(error_class: ErrorClass, msg: &str) -> Self {
+        Error {
+            msg: Some(String::from(msg)),
+            cause: None,
+            location: None,
+            error_class,
+            errno: None,
+        }
+    }
+
+    /// Return the QAPI error class of the error
+    pub fn error_class(&self) -> ErrorClass {
+        self.error_class
+    }
+
+    /// Create a new error for a failure that C callers see as the
+    /// (positive) `errno` value `errno`.
+    pub fn with_errno(errno: c_int, msg: &str) -> Self {
+        Error {
+            msg: Some(String::from(msg)),
+            cause: None,
+            location: None,
+            error_class: ErrorClass::GenericError,
+            errno: Some(errno),
+        }
+    }
+
+    /// Return the `errno` value of the error, if any.  If the error
+    /// was not created with one, look for it in its cause, for example
+    /// an `std::io::Error` that the error wraps.
+    pub fn errno(&self) -> Option<c_int> {
+        self.errno.or_else(|| {
+            let cause = self.cause.as_deref()?;
+            if let Some(err) = cause.downcast_ref::<Error>() {
+                err.errno()
+            } else {
+                cause
+                    .downcast_ref::<std::io::Error>()
+                    .and_then(std::io::Error::raw_os_error)
+            }
+        })
+    }
+
+    /// Consume a result and convert it for C callbacks that return
+    /// zero on success and a negative `errno` value on failure.  Errors
+    /// without an `errno` value become `-EINVAL`.  Only the `errno`
+    /// value is returned to C, so report the error first if the
+    /// message is needed.
+    pub fn into_c_int(result: Result<(), Self>) -> c_int {
+        match result {
+            Ok(()) => 0,
+            Err(err) => -err.errno().unwrap_or(libc::EINVAL),
+        }
+    }
+
+    /// Prepend `msg` to the description of the error, keeping its
+    /// QAPI error class.
+    #[must_use]
+    pub fn prepend(self, msg: &str) -> Self {
+        let error_class = self.error_class;
+        Error {
+            error_class,
+            ..Error::with_error(msg, self)
         }
     }
 
@@ -206,17 +429,45 @@ impl Error {
     }
 }
 
+/// Add context to the error of a `Result`, similar to `anyhow::Context`:
+///
+/// ```ignore
+/// self.setup_dma().qemu_context("while setting up DMA")?;
+/// ```
+///
+/// The methods have different names from `anyhow::Context`, so that both
+/// traits can be in scope at the same time.
+pub trait ResultExt<T> {
+    /// Prepend `msg` to the description of the error.
+    fn qemu_context(self, msg: &str) -> Result<T, Error>;
+
+    /// Prepend the result of `f` to the description of the error; `f`
+    /// is only called if there is an error.
+    fn with_qemu_context<S: AsRef<str>, F: FnOnce() -> S>(self, f: F) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn qemu_context(self, msg: &str) -> Result<T, Error> {
+        self.map_err(|err| err.into().prepend(msg))
+    }
+
+    fn with_qemu_context<S: AsRef<str>, F: FnOnce() -> S>(self, f: F) -> Result<T, Error> {
+        self.map_err(|err| err.into().prepend(f().as_ref()))
+    }
+}
+
 impl CloneToForeign for Error {
     type Foreign = bindings::Error;
 
     fn clone_to_foreign(&self) -> OwnedPointer<Self> {
         let mut x: *mut bindings::Error = ptr::null_mut();
         unsafe {
-            error_setg_internal(
+            error_set_internal(
                 &mut x,
                 ptr::null_mut(), // FIXME
                 0,
                 ptr::null_mut(), // FIXME
+                i32::from(&self.error_class),
                 cstr!("%s").as_ptr(),
                 format!("{}", self),
             );
@@ -234,10 +485,122 @@ impl CloneToForeign for Error {
 impl FromForeign for Error {
     unsafe fn cloned_from_foreign(c_error: *const bindings::Error) -> Self {
         let c_str = unsafe { CStr::from_ptr(error_get_pretty(c_error)) };
+        // Classes that are unknown to Rust are treated as generic errors
+        let error_class = unsafe { error_get_class(c_error) };
         Error {
             msg: Some(c_str.to_string_lossy().into_owned()),
             cause: None,
             location: None,
+            error_class: ErrorClass::try_from(error_class).unwrap_or_default(),
+            errno: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_class() {
+        let generic = Error::from("msg");
+        assert_eq!(generic.error_class(), ErrorClass::GenericError);
+
+        let err = Error::with_class(ErrorClass::DeviceNotFound, "Device 'foo' not found");
+        assert_eq!(err.error_class(), ErrorClass::DeviceNotFound);
+        assert_eq!(err.to_string(), "Device 'foo' not found");
+        assert_eq!(i32::from(&err.error_class()), 3);
+    }
+
+    #[test]
+    fn test_context() {
+        fn setup(queue: u32) -> crate::Result<()> {
+            Err(Error::with_class(ErrorClass::DeviceNotFound, "no backend"))
+                .with_qemu_context(|| format!("queue {}", queue))
+        }
+
+        let err = setup(3).qemu_context("while setting up DMA").unwrap_err();
+        assert_eq!(err.to_string(), "while setting up DMA: queue 3: no backend");
+        assert_eq!(err.error_class(), ErrorClass::DeviceNotFound);
+
+        let io: std::result::Result<(), std::io::Error> = Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "no such file",
+        ));
+        let io_err = io.qemu_context("cannot open image").unwrap_err();
+        assert_eq!(io_err.to_string(), "cannot open image: no such file");
+        assert!(Ok::<_, Error>(1).qemu_context("unused").is_ok());
+    }
+
+    #[test]
+    fn test_boxed() {
+        fn parse(s: &str) -> std::result::Result<u32, Box<dyn std::error::Error>> {
+            Ok(s.parse::<u32>()?)
+        }
+        fn realize(s: &str) -> crate::Result<u32> {
+            Ok(parse(s)?)
         }
+
+        assert_eq!(realize("42").unwrap(), 42);
+        let err = realize("x").unwrap_err();
+        assert_eq!(err.to_string(), "invalid digit found in string");
+        assert!(std::error::Error::source(&err).is_some());
+        let err = err.prepend("bad queue count");
+        assert_eq!(
+            err.to_string(),
+            "bad queue count: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn test_into_c_int() {
+        assert_eq!(Error::into_c_int(Ok(())), 0);
+
+        let err = Error::with_errno(libc::ENOSPC, "ring full");
+        assert_eq!(err.errno(), Some(libc::ENOSPC));
+        assert_eq!(Error::into_c_int(Err(err)), -libc::ENOSPC);
+
+        let io = Error::from(std::io::Error::from_raw_os_error(libc::EBADF));
+        assert_eq!(
+            Error::into_c_int(Err(io.prepend("cannot read"))),
+            -libc::EBADF
+        );
+
+        assert_eq!(Error::into_c_int(Err(Error::from("msg"))), -libc::EINVAL);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde() {
+        let cause = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let err = Error::with_error_file_line("realize failed", cause, "hw/foo.rs", 42);
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["message"], "realize failed: no such file");
+        assert_eq!(json["location"]["file"], "hw/foo.rs");
+        assert_eq!(json["location"]["line"], 42);
+        assert_eq!(json["error_class"], "GenericError");
+
+        let copy: Error = serde_json::from_value(json).unwrap();
+        assert_eq!(copy.to_string(), err.to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "anyhow")]
+    fn test_anyhow() {
+        use anyhow::Context;
+
+        fn inner() -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("inner")).context("outer")
+        }
+        fn realize() -> crate::Result<()> {
+            inner()?;
+            Ok(())
+        }
+
+        let err = realize().unwrap_err();
+        assert_eq!(err.to_string(), "outer: inner");
+
+        let err = Error::with_error("msg", err).into_anyhow();
+        assert_eq!(err.to_string(), "msg: outer: inner");
     }
 }
@@ -10,7 +10,12 @@ use std::fmt;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::mem;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::time::Duration;
 
 /// A type for which there is a canonical representation as a C datum.
 pub trait CloneToForeign {
@@ -360,6 +365,26 @@ impl<'a, P, T: 'a> BorrowedPointer<'a, P, T> {
     }
 }
 
+impl<'a, P, T> BorrowedPointer<'a, P, &'a [T]> {
+    /// Return the number of elements that the pointer points to, for
+    /// C functions that take a pointer and a count.
+    ///
+    /// ```
+    /// # use qemu::ForeignBorrow;
+    /// let v = [1u32, 2, 3];
+    /// let borrowed = v[..].borrow_foreign();
+    /// let copy = unsafe { std::slice::from_raw_parts(borrowed.as_ptr(), borrowed.len()) };
+    /// # assert_eq!(copy, v);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+}
+
 impl<'a, P, T: 'a> Debug for BorrowedPointer<'a, P, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let ptr_name = std::any::type_name::<*mut P>();
@@ -415,6 +440,27 @@ impl<'a, P, T: 'a> BorrowedMutPointer<'a, P, T> {
     }
 }
 
+impl<'a, P, T> BorrowedMutPointer<'a, P, &'a mut [T]> {
+    /// Return the number of elements that the pointer points to, for
+    /// C functions that take a pointer and a count.  This makes it
+    /// possible to let C fill a Rust buffer without any copy:
+    ///
+    /// ```
+    /// # use qemu::ForeignBorrowMut;
+    /// let mut buf = [0u8; 16];
+    /// let mut borrowed = buf[..].borrow_foreign_mut();
+    /// unsafe { libc::memset(borrowed.as_mut_ptr().cast(), 0xff, borrowed.len()) };
+    /// # assert_eq!(buf, [0xff; 16]);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+}
+
 impl<'a, P, T: 'a> Debug for BorrowedMutPointer<'a, P, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let name = std::any::type_name::<*mut P>();
@@ -574,6 +620,229 @@ impl ForeignBorrow<'_> for String {
     }
 }
 
+/// Return the bytes that are passed to C for `path`.  On Unix these are
+/// the bytes of the path as stored by the operating system, even if they
+/// are not valid UTF-8.  On other platforms, for example Windows, QEMU
+/// expects UTF-8 paths and the function panics if `path` is not valid
+/// Unicode.
+fn path_to_bytes(path: &Path) -> &[u8] {
+    #[cfg(unix)]
+    {
+        path.as_os_str().as_bytes()
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_str().expect("path is not valid Unicode").as_bytes()
+    }
+}
+
+impl CloneToForeign for Path {
+    type Foreign = c_char;
+
+    unsafe fn free_foreign(ptr: *mut c_char) {
+        libc::free(ptr as *mut c_void);
+    }
+
+    fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+        let bytes = path_to_bytes(self);
+        // SAFETY: bytes.as_ptr() is guaranteed to point to bytes.len() bytes;
+        // the destination is freshly allocated
+        unsafe {
+            let p = libc::malloc(bytes.len() + 1) as *mut c_char;
+            ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, p, bytes.len());
+            *p.add(bytes.len()) = 0;
+            OwnedPointer::new(p)
+        }
+    }
+}
+
+impl CloneToForeign for PathBuf {
+    type Foreign = c_char;
+
+    unsafe fn free_foreign(ptr: *mut c_char) {
+        libc::free(ptr as *mut c_void);
+    }
+
+    fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+        self.as_path().clone_to_foreign().into()
+    }
+}
+
+impl FromForeign for PathBuf {
+    unsafe fn cloned_from_foreign(p: *const c_char) -> Self {
+        let cstr = CStr::from_ptr(p);
+        #[cfg(unix)]
+        {
+            std::ffi::OsString::from_vec(cstr.to_bytes().to_vec()).into()
+        }
+        #[cfg(not(unix))]
+        {
+            String::from_utf8_lossy(cstr.to_bytes()).into_owned().into()
+        }
+    }
+}
+
+/// Paths are borrowed as NUL-terminated strings; like for `String`,
+/// `borrow_foreign` panics if the path includes a NUL character.
+impl ForeignBorrow<'_> for Path {
+    type Storage = CString;
+
+    fn borrow_foreign(&self) -> BorrowedPointer<c_char, CString> {
+        let tmp = CString::new(path_to_bytes(self)).unwrap();
+        BorrowedPointer::new(tmp.as_ptr(), tmp)
+    }
+}
+
+impl ForeignBorrow<'_> for PathBuf {
+    type Storage = CString;
+
+    fn borrow_foreign(&self) -> BorrowedPointer<c_char, CString> {
+        self.as_path().borrow_foreign()
+    }
+}
+
+/// Network addresses are passed to C as strings in their canonical form,
+/// for example `10.0.2.2` or `[::1]:4444`; as required by most C parsers,
+/// IPv6 addresses are bracketed when followed by a port.
+macro_rules! impl_foreign_net_addr {
+    ($($type:ty),*) => {
+        $(
+            impl CloneToForeign for $type {
+                type Foreign = c_char;
+
+                unsafe fn free_foreign(ptr: *mut c_char) {
+                    libc::free(ptr as *mut c_void);
+                }
+
+                fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+                    self.to_string().clone_to_foreign().into()
+                }
+            }
+
+            impl ForeignBorrow<'_> for $type {
+                type Storage = CString;
+
+                fn borrow_foreign(&self) -> BorrowedPointer<c_char, CString> {
+                    let tmp = CString::new(self.to_string()).unwrap();
+                    BorrowedPointer::new(tmp.as_ptr(), tmp)
+                }
+            }
+        )*
+    };
+}
+
+impl_foreign_net_addr!(IpAddr, SocketAddr);
+
+/// Durations are represented in C as an `int64_t` number of nanoseconds,
+/// as used by QEMU's timer functions.  Conversions saturate: durations
+/// above `i64::MAX` nanoseconds (about 292 years) become `i64::MAX`, and
+/// negative values coming from C become `Duration::ZERO`.
+impl CloneToForeign for Duration {
+    type Foreign = i64;
+
+    unsafe fn free_foreign(ptr: *mut i64) {
+        libc::free(ptr as *mut c_void);
+    }
+
+    fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+        let ns = i64::try_from(self.as_nanos()).unwrap_or(i64::MAX);
+        ns.clone_to_foreign().into()
+    }
+}
+
+impl FromForeign for Duration {
+    unsafe fn cloned_from_foreign(p: *const i64) -> Self {
+        u64::try_from(*p).map_or(Duration::ZERO, Duration::from_nanos)
+    }
+}
+
+macro_rules! foreign_duration_struct {
+    ($(#[$meta:meta])* $name:ident, $foreign_type:ty, $subsec:ident, $subsec_per_sec:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+        pub struct $name(pub Duration);
+
+        impl TryFrom<&$foreign_type> for $name {
+            type Error = crate::Error;
+
+            fn try_from(value: &$foreign_type) -> Result<Self, crate::Error> {
+                let secs = u64::try_from(value.tv_sec);
+                let subsec = u32::try_from(value.$subsec)
+                    .ok()
+                    .filter(|&subsec| subsec < $subsec_per_sec);
+                match (secs, subsec) {
+                    (Ok(secs), Some(subsec)) => Ok($name(
+                        Duration::from_secs(secs)
+                            + Duration::from_nanos(u64::from(subsec) * (1_000_000_000 / $subsec_per_sec)),
+                    )),
+                    _ => Err(crate::Error::from(
+                        format!(
+                            "invalid {} {{ {}, {} }}",
+                            stringify!($foreign_type),
+                            value.tv_sec,
+                            value.$subsec
+                        )
+                        .as_str(),
+                    )),
+                }
+            }
+        }
+
+        impl CloneToForeign for $name {
+            type Foreign = $foreign_type;
+
+            unsafe fn free_foreign(ptr: *mut Self::Foreign) {
+                libc::free(ptr as *mut c_void);
+            }
+
+            fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+                let tv_sec = self.0.as_secs().try_into().unwrap_or_else(|_| {
+                    panic!("{:?} does not fit in {}", self.0, stringify!($foreign_type))
+                });
+                let subsec = self.0.subsec_nanos() / (1_000_000_000 / $subsec_per_sec);
+                // SAFETY: we are copying into a freshly-allocated block
+                unsafe {
+                    let p = libc::malloc(mem::size_of::<Self::Foreign>()) as *mut Self::Foreign;
+                    p.write(mem::zeroed());
+                    (*p).tv_sec = tv_sec;
+                    // Always less than one billion, which fits in a C long
+                    (*p).$subsec = subsec.try_into().unwrap();
+                    OwnedPointer::new(p)
+                }
+            }
+        }
+
+        impl FromForeign for $name {
+            unsafe fn cloned_from_foreign(p: *const Self::Foreign) -> Self {
+                $name::try_from(&*p).unwrap_or_else(|e| panic!("{}", e))
+            }
+        }
+    };
+}
+
+foreign_duration_struct!(
+    /// A `Duration` that is represented in C as a `struct timespec`.
+    ///
+    /// Durations whose seconds do not fit in `time_t` cannot be converted
+    /// to C, and `clone_to_foreign` panics.  In the other direction,
+    /// negative values and nanoseconds that are out of range are rejected
+    /// by `TryFrom`, and make `FromForeign` panic.
+    Timespec,
+    libc::timespec,
+    tv_nsec,
+    1_000_000_000
+);
+
+foreign_duration_struct!(
+    /// A `Duration` that is represented in C as a `struct timeval`.
+    /// Conversion to C truncates the duration to microseconds; otherwise
+    /// it is the same as [`Timespec`].
+    Timeval,
+    libc::timeval,
+    tv_usec,
+    1_000_000
+);
+
 macro_rules! foreign_copy_type {
     ($rust_type:ty, $foreign_type:ty) => {
         impl CloneToForeign for $rust_type {
@@ -664,6 +933,100 @@ foreign_copy_type!(usize, libc::size_t);
 foreign_copy_type!(f32, f32);
 foreign_copy_type!(f64, f64);
 
+/// Declare a fieldless enum whose discriminants match a C enum, and
+/// implement the conversions from and to its integer representation.
+///
+/// ```ignore
+/// foreign_enum! {
+///     #[repr(u32)]
+///     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+///     pub enum OnOffAuto {
+///         Auto = 0,
+///         On = 1,
+///         Off = 2,
+///     }
+///     fallback Auto;
+/// }
+/// ```
+///
+/// Every variant needs an explicit discriminant.  Values coming from C
+/// are checked: `TryFrom` returns the raw value as an error if it does
+/// not correspond to any variant.  `FromForeign` is only implemented
+/// if the optional `fallback` line names a variant, which replaces any
+/// value that `TryFrom` rejects.
+///
+/// This is a `macro_rules!` macro rather than a derive because the
+/// crate does not have a procedural macro companion.
+#[macro_export]
+macro_rules! foreign_enum {
+    (
+        #[repr($repr:ty)]
+        $(#[$enum_meta:meta])*
+        $enum_vis:vis
+        enum $EnumName:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident = $value:expr
+            ),+
+            $(,)?
+        }
+        $(fallback $Fallback:ident;)?
+    ) => {
+        #[repr($repr)]
+        $(#[$enum_meta])*
+        $enum_vis
+        enum $EnumName {
+            $(
+                $(#[$variant_meta])*
+                $variant = $value,
+            )+
+        }
+
+        impl std::convert::TryFrom<$repr> for $EnumName {
+            type Error = $repr;
+
+            fn try_from(value: $repr) -> std::result::Result<Self, $repr> {
+                $(
+                    if value == $EnumName::$variant as $repr {
+                        return Ok($EnumName::$variant);
+                    }
+                )+
+                Err(value)
+            }
+        }
+
+        impl From<&$EnumName> for $repr {
+            fn from(value: &$EnumName) -> $repr {
+                match value {
+                    $($EnumName::$variant => $EnumName::$variant as $repr,)+
+                }
+            }
+        }
+
+        impl $crate::CloneToForeign for $EnumName {
+            type Foreign = $repr;
+
+            unsafe fn free_foreign(p: *mut $repr) {
+                <$repr as $crate::CloneToForeign>::free_foreign(p)
+            }
+
+            fn clone_to_foreign(&self) -> $crate::OwnedPointer<Self> {
+                let value = <$repr>::from(self);
+                $crate::OwnedPointer::from(<$repr as $crate::CloneToForeign>::clone_to_foreign(&value))
+            }
+        }
+
+        $(
+            impl $crate::FromForeign for $EnumName {
+                unsafe fn cloned_from_foreign(p: *const $repr) -> Self {
+                    <Self as std::convert::TryFrom<$repr>>::try_from(*p)
+                        .unwrap_or($EnumName::$Fallback)
+                }
+            }
+        )?
+    };
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::shadow_unrelated)]
@@ -712,6 +1075,108 @@ mod tests {
         assert_eq!(i, 45i8);
     }
 
+    // Round-trip two values of each type generated by foreign_copy_type!,
+    // alone and in a slice
+    macro_rules! test_foreign_copy_type {
+        ($($name:ident: $type:ty = $a:expr, $b:expr;)*) => {
+            $(
+                mod $name {
+                    use super::*;
+
+                    #[test]
+                    fn convert() {
+                        let a: $type = $a;
+                        assert_eq!(a.clone_to_foreign().into_native(), a);
+                        let p = a.clone_to_foreign();
+                        unsafe {
+                            assert_eq!(<$type>::cloned_from_foreign(p.as_ptr()), a);
+                            assert_eq!(<$type>::from_foreign(p.into_inner()), a);
+                        }
+                    }
+
+                    #[test]
+                    fn borrow() {
+                        let (a, b): ($type, $type) = ($a, $b);
+                        unsafe {
+                            assert_eq!(*a.borrow_foreign().as_ptr(), a);
+                        }
+                        let mut c = a;
+                        let mut borrowed = c.borrow_foreign_mut();
+                        unsafe {
+                            assert_eq!(*borrowed.as_ptr(), a);
+                            *borrowed.as_mut_ptr() = b;
+                        }
+                        assert_eq!(c, b);
+                    }
+
+                    #[test]
+                    fn slice() {
+                        let (a, b): ($type, $type) = ($a, $b);
+                        let v = [a, b, a];
+                        let p = v[..].clone_to_foreign();
+                        let copy = unsafe { std::slice::from_raw_parts(p.as_ptr(), 3) };
+                        assert_eq!(copy, v);
+
+                        let borrowed = v[..].borrow_foreign();
+                        assert_eq!(borrowed.len(), 3);
+                        assert_eq!(borrowed.as_ptr(), v.as_ptr());
+
+                        let mut w = v;
+                        let mut borrowed = w[..].borrow_foreign_mut();
+                        unsafe {
+                            *borrowed.as_mut_ptr().add(2) = b;
+                        }
+                        assert_eq!(w, [a, b, b]);
+                        drop([a; 0][..].clone_to_foreign());
+                    }
+                }
+            )*
+        };
+    }
+
+    test_foreign_copy_type! {
+        foreign_i8: i8 = i8::MIN, i8::MAX;
+        foreign_u8: u8 = 0, u8::MAX;
+        foreign_i16: i16 = i16::MIN, i16::MAX;
+        foreign_u16: u16 = 0x1234, u16::MAX;
+        foreign_i32: i32 = i32::MIN, i32::MAX;
+        foreign_u32: u32 = 0x1234_5678, u32::MAX;
+        foreign_i64: i64 = i64::MIN, i64::MAX;
+        foreign_u64: u64 = 0x1234_5678_9abc_def0, u64::MAX;
+        foreign_isize: isize = isize::MIN, isize::MAX;
+        foreign_usize: usize = 1, usize::MAX;
+        foreign_f32: f32 = -0.25, f32::MAX;
+        foreign_f64: f64 = 1.5, f64::MIN_POSITIVE;
+    }
+
+    #[test]
+    fn test_borrow_foreign_slice_len() {
+        let v = [1u32, 2, 3];
+        let borrowed = v[..].borrow_foreign();
+        assert_eq!(borrowed.len(), 3);
+        assert_eq!(borrowed.as_ptr(), v.as_ptr());
+
+        let mut w = [0u16; 0];
+        let borrowed = w[..].borrow_foreign_mut();
+        assert!(borrowed.is_empty());
+    }
+
+    #[test]
+    fn test_borrow_foreign_mut_slice_fill() {
+        extern "C" fn fill(buf: *mut u8, len: usize) {
+            for i in 0..len {
+                unsafe { buf.add(i).write(i as u8) }
+            }
+        }
+
+        let mut buf = [0u8; 16];
+        let mut borrowed = buf[..].borrow_foreign_mut();
+        assert_eq!(borrowed.len(), 16);
+        fill(borrowed.as_mut_ptr(), borrowed.len());
+        assert_eq!(buf[0], 0);
+        assert_eq!(buf[15], 15);
+    }
+
     #[test]
     fn test_borrow_foreign_string() {
         let s = "Hello, world!".to_string();
@@ -860,6 +1325,144 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_borrow_foreign_path() {
+        let p = PathBuf::from("/dev/null");
+        let borrowed = p.borrow_foreign();
+        unsafe {
+            assert_eq!(CStr::from_ptr(borrowed.as_ptr()).to_bytes(), b"/dev/null");
+        }
+    }
+
+    #[test]
+    fn test_clone_to_foreign_path() {
+        let p = Path::new("/dev/null");
+        let cloned = p.clone_to_foreign();
+        let copy: PathBuf = OwnedPointer::<PathBuf>::from(cloned).into_native();
+        assert_eq!(p, copy);
+    }
+
+    #[test]
+    fn test_clone_to_foreign_ip_addr() {
+        let addr: IpAddr = "10.0.2.2".parse().unwrap();
+        let cloned = addr.clone_to_foreign();
+        unsafe {
+            assert_eq!(CStr::from_ptr(cloned.as_ptr()).to_bytes(), b"10.0.2.2");
+        }
+        let addr: IpAddr = "::1".parse().unwrap();
+        let borrowed = addr.borrow_foreign();
+        unsafe {
+            assert_eq!(CStr::from_ptr(borrowed.as_ptr()).to_bytes(), b"::1");
+        }
+    }
+
+    #[test]
+    fn test_clone_to_foreign_socket_addr() {
+        let addr: SocketAddr = "127.0.0.1:4444".parse().unwrap();
+        let borrowed = addr.borrow_foreign();
+        unsafe {
+            assert_eq!(
+                CStr::from_ptr(borrowed.as_ptr()).to_bytes(),
+                b"127.0.0.1:4444"
+            );
+        }
+        let addr = SocketAddr::new("::1".parse().unwrap(), 4444);
+        let cloned = addr.clone_to_foreign();
+        unsafe {
+            assert_eq!(CStr::from_ptr(cloned.as_ptr()).to_bytes(), b"[::1]:4444");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_non_utf8() {
+        use std::ffi::OsStr;
+
+        let p = Path::new(OsStr::from_bytes(b"/tmp/\xff\xfe"));
+        let copy = unsafe { PathBuf::cloned_from_foreign(p.borrow_foreign().as_ptr()) };
+        assert_eq!(p, copy);
+        assert_eq!(copy.as_os_str().as_bytes(), b"/tmp/\xff\xfe");
+    }
+
+    foreign_enum! {
+        #[repr(i32)]
+        #[derive(Debug, PartialEq, Eq)]
+        enum TestEnum {
+            A = 0,
+            B = 1,
+            C = -1,
+        }
+        fallback A;
+    }
+
+    #[test]
+    fn test_foreign_enum() {
+        for e in [TestEnum::A, TestEnum::B, TestEnum::C] {
+            let p = e.clone_to_foreign();
+            unsafe {
+                assert_eq!(TestEnum::cloned_from_foreign(p.as_ptr()), e);
+            }
+            assert_eq!(p.into_native(), e);
+        }
+
+        assert_eq!(TestEnum::try_from(-1), Ok(TestEnum::C));
+        assert_eq!(TestEnum::try_from(2), Err(2));
+    }
+
+    #[test]
+    fn test_foreign_enum_invalid() {
+        let p = 2i32.clone_to_foreign();
+        unsafe {
+            assert_eq!(TestEnum::cloned_from_foreign(p.as_ptr()), TestEnum::A);
+        }
+    }
+
+    #[test]
+    fn test_duration() {
+        let d = Duration::from_millis(10);
+        let p = d.clone_to_foreign();
+        unsafe {
+            assert_eq!(*p.as_ptr(), 10_000_000);
+        }
+        assert_eq!(p.into_native(), d);
+
+        // Out of range values saturate
+        let p = Duration::MAX.clone_to_foreign();
+        unsafe {
+            assert_eq!(*p.as_ptr(), i64::MAX);
+        }
+        let p = (-1i64).clone_to_foreign();
+        unsafe {
+            assert_eq!(Duration::cloned_from_foreign(p.as_ptr()), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_timespec() {
+        let d = Timespec(Duration::new(3, 250_000_000));
+        let p = d.clone_to_foreign();
+        unsafe {
+            assert_eq!((*p.as_ptr()).tv_sec, 3);
+            assert_eq!((*p.as_ptr()).tv_nsec, 250_000_000);
+        }
+        assert_eq!(p.into_native(), d);
+
+        let t = Timeval(Duration::new(3, 250_000_999)).clone_to_foreign();
+        unsafe {
+            assert_eq!((*t.as_ptr()).tv_usec, 250_000);
+        }
+        assert_eq!(t.into_native(), Timeval(Duration::new(3, 250_000_000)));
+
+        let ts = Timespec::default().clone_to_foreign();
+        unsafe {
+            (*ts.as_mut_ptr()).tv_sec = -1;
+            assert!(Timespec::try_from(&*ts.as_ptr()).is_err());
+            (*ts.as_mut_ptr()).tv_sec = 0;
+            (*ts.as_mut_ptr()).tv_nsec = 1_000_000_000;
+            assert!(Timespec::try_from(&*ts.as_ptr()).is_err());
+        }
+    }
+
     #[test]
     fn test_option() {
         // An Option can be used to produce or convert NULL pointers
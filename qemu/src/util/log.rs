@@ -0,0 +1,85 @@
+//! Forwarding of the `log` crate's macros to QEMU's logging
+//!
+//! After [`init`] is called, messages from `log::error!` go to
+//! `error_report()`, while the others go to the QEMU log (`-D logfile`)
+//! if the corresponding mask is enabled with `-d`:
+//!
+//! | Level   | Mask              | `-d` option   |
+//! |---------|-------------------|---------------|
+//! | `Warn`  | `LOG_GUEST_ERROR` | `guest_errors`|
+//! | `Info`  | `LOG_TRACE`       | `trace:...`   |
+//! | `Debug` | `LOG_TRACE`       | `trace:...`   |
+//! | `Trace` | `LOG_TRACE`       | `trace:...`   |
+//!
+//! No level maps to `LOG_UNIMP`, because `-d unimp` is reserved for
+//! reports of features that the device does not implement.
+
+use ::log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use cstr::cstr;
+
+use libc::c_int;
+
+use std::ffi::CString;
+
+use crate::bindings::error_report;
+use crate::bindings::qemu_log;
+use crate::bindings::qemu_loglevel;
+
+pub const LOG_UNIMP: c_int = 1 << 10;
+pub const LOG_GUEST_ERROR: c_int = 1 << 11;
+pub const LOG_TRACE: c_int = 1 << 15;
+
+/// Return the QEMU log mask that enables messages at `level`, or `None`
+/// if they are always printed with `error_report()`.
+fn level_to_mask(level: Level) -> Option<c_int> {
+    match level {
+        Level::Error => None,
+        Level::Warn => Some(LOG_GUEST_ERROR),
+        Level::Info | Level::Debug | Level::Trace => Some(LOG_TRACE),
+    }
+}
+
+struct QemuLogger;
+
+impl Log for QemuLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match level_to_mask(metadata.level()) {
+            None => true,
+            // SAFETY: qemu_loglevel is only written while processing
+            // the command line or monitor commands, under the BQL
+            Some(mask) => unsafe { qemu_loglevel & mask != 0 },
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        // Check before formatting the message, so that disabled
+        // messages cost as little as possible
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let msg = format!("{}: {}", record.target(), record.args());
+        let msg = CString::new(msg.replace('\0', "\\0")).unwrap();
+        // SAFETY: the format string consumes exactly one C string
+        unsafe {
+            if level_to_mask(record.level()).is_none() {
+                error_report(cstr!("%s").as_ptr(), msg.as_ptr());
+            } else {
+                qemu_log(cstr!("%s\n").as_ptr(), msg.as_ptr());
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: QemuLogger = QemuLogger;
+
+/// Install a logger that forwards messages from the `log` crate to QEMU.
+/// Fails if another logger was installed already.
+pub fn init() -> Result<(), SetLoggerError> {
+    ::log::set_logger(&LOGGER)?;
+    ::log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
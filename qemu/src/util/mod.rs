@@ -1,4 +1,12 @@
+pub mod bitops;
+pub mod callback;
+pub mod dma;
 pub mod error;
 pub mod foreign;
+#[cfg(feature = "log")]
+pub mod log;
 pub mod offset_of;
+pub mod runstate;
+pub mod uuid;
+pub mod version;
 pub mod zeroed;
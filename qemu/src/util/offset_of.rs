@@ -6,6 +6,30 @@ macro_rules! offset_of {
     };
 }
 
+/// Compute the offset of a field that is reached through a path such
+/// as `sub.value`, where `sub` is a field of type `Sub` and `value` is a
+/// field of `Sub`.  A path with a single element is the same as
+/// `offset_of!`; longer paths do not need `with_offsets!` on the inner
+/// structs.
+#[macro_export]
+macro_rules! offset_of_path {
+    ($Container:ty, $field:ident) => {
+        $crate::offset_of!($Container, $field)
+    };
+    ($Container:ty, $($field:ident).+) => {{
+        let uninit = std::mem::MaybeUninit::<$Container>::uninit();
+        let base = uninit.as_ptr();
+        // SAFETY: addr_of! computes the address of the field without
+        // reading the uninitialized memory, and the result is within
+        // the same allocation as base
+        #[allow(clippy::cast_sign_loss)]
+        unsafe {
+            let field = std::ptr::addr_of!((*base).$($field).+);
+            field.cast::<u8>().offset_from(base.cast::<u8>()) as usize
+        }
+    }};
+}
+
 /// A wrapper for struct declarations, that allows using `offset_of!` in
 /// versions of Rust prior to 1.77
 #[macro_export]
@@ -97,3 +121,31 @@ macro_rules! with_offsets {
         };
     );
 }
+
+#[cfg(test)]
+mod tests {
+    #[repr(C)]
+    struct Inner {
+        a: u8,
+        b: u32,
+    }
+
+    crate::with_offsets! {
+        #[repr(C)]
+        struct Outer {
+            x: u16,
+            inner: Inner,
+            y: u64,
+        }
+    }
+
+    #[test]
+    fn test_offset_of_path() {
+        const B: usize = offset_of_path!(Outer, inner.b);
+        assert_eq!(offset_of_path!(Outer, x), 0);
+        assert_eq!(offset_of_path!(Outer, inner), 4);
+        assert_eq!(offset_of_path!(Outer, inner.a), 4);
+        assert_eq!(B, 8);
+        assert_eq!(offset_of_path!(Outer, y), 16);
+    }
+}
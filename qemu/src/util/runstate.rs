@@ -0,0 +1,111 @@
+//! Notification of changes to the run state of the virtual machine
+
+use std::ffi::c_void;
+use std::marker::PhantomData;
+
+use crate::bindings;
+use crate::bindings::qemu_add_vm_change_state_handler;
+use crate::bindings::qemu_del_vm_change_state_handler;
+use crate::bindings::VMChangeStateEntry;
+use crate::foreign_enum;
+use crate::util::callback;
+
+foreign_enum! {
+    #[repr(u32)]
+    /// The run state of the virtual machine, as passed to the functions
+    /// registered with [`add_vm_change_state_handler`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum RunState {
+        Debug = bindings::RUN_STATE_DEBUG,
+        InMigrate = bindings::RUN_STATE_INMIGRATE,
+        InternalError = bindings::RUN_STATE_INTERNAL_ERROR,
+        IoError = bindings::RUN_STATE_IO_ERROR,
+        Paused = bindings::RUN_STATE_PAUSED,
+        PostMigrate = bindings::RUN_STATE_POSTMIGRATE,
+        Prelaunch = bindings::RUN_STATE_PRELAUNCH,
+        FinishMigrate = bindings::RUN_STATE_FINISH_MIGRATE,
+        RestoreVm = bindings::RUN_STATE_RESTORE_VM,
+        Running = bindings::RUN_STATE_RUNNING,
+        SaveVm = bindings::RUN_STATE_SAVE_VM,
+        Shutdown = bindings::RUN_STATE_SHUTDOWN,
+        Suspended = bindings::RUN_STATE_SUSPENDED,
+        Watchdog = bindings::RUN_STATE_WATCHDOG,
+        GuestPanicked = bindings::RUN_STATE_GUEST_PANICKED,
+        Colo = bindings::RUN_STATE_COLO,
+    }
+}
+
+struct Handler<T> {
+    obj: *const T,
+    cb: fn(&T, bool, RunState),
+}
+
+/// A function registered with [`add_vm_change_state_handler`].  It is
+/// unregistered when the `VmChangeStateHandler` is dropped.
+pub struct VmChangeStateHandler<T> {
+    entry: *mut VMChangeStateEntry,
+    _handler: Box<Handler<T>>,
+    _phantom: PhantomData<fn(&T)>,
+}
+
+/// Call `cb` with `obj` whenever the virtual machine starts or stops
+/// running; `running` tells whether the virtual machine is running
+/// after the change, and `state` is the new run state.  Changes to a
+/// run state that [`RunState`] does not know about, for example one that
+/// was added by a newer QEMU, are not reported.
+///
+/// Devices usually call it from `REALIZE` and keep the result in their
+/// state, so that the function is unregistered at the latest when the
+/// device is finalized:
+///
+/// ```ignore
+/// fn realize(&self) -> Result<()> {
+///     // SAFETY: the handler is dropped together with the state of self
+///     let handler = unsafe {
+///         add_vm_change_state_handler(self, MyDevice::vm_state_changed)
+///     };
+///     self.state.borrow_mut().vm_handler = Some(handler);
+///     Ok(())
+/// }
+/// ```
+///
+/// # Safety
+///
+/// The returned `VmChangeStateHandler` must be dropped before `obj`
+/// goes away.
+pub unsafe fn add_vm_change_state_handler<T>(
+    obj: &T,
+    cb: fn(&T, bool, RunState),
+) -> VmChangeStateHandler<T> {
+    unsafe extern "C" fn rust_vm_change_state<T>(
+        opaque: *mut c_void,
+        running: bool,
+        state: bindings::RunState,
+    ) {
+        callback::call_opaque(opaque, |handler: &Handler<T>| {
+            if let Ok(state) = RunState::try_from(state) {
+                (handler.cb)(&*handler.obj, running, state);
+            }
+        });
+    }
+
+    let handler = Box::new(Handler { obj, cb });
+    let opaque: *const Handler<T> = &*handler;
+    let entry =
+        qemu_add_vm_change_state_handler(Some(rust_vm_change_state::<T>), opaque.cast_mut().cast());
+    VmChangeStateHandler {
+        entry,
+        _handler: handler,
+        _phantom: PhantomData,
+    }
+}
+
+impl<T> Drop for VmChangeStateHandler<T> {
+    fn drop(&mut self) {
+        // SAFETY: the entry was returned by qemu_add_vm_change_state_handler
+        // and is only removed here
+        unsafe {
+            qemu_del_vm_change_state_handler(self.entry);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! UUIDs, compatible with `QemuUUID`
+
+use const_default::ConstDefault;
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use crate::bindings::QemuUUID;
+use crate::util::foreign::{CloneToForeign, FromForeign, OwnedPointer};
+use crate::Error;
+
+/// A UUID, stored as 16 bytes in big-endian order like `QemuUUID`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid(pub [u8; 16]);
+
+impl ConstDefault for Uuid {
+    const DEFAULT: Self = Uuid([0; 16]);
+}
+
+impl Uuid {
+    pub fn is_nil(&self) -> bool {
+        *self == Uuid::DEFAULT
+    }
+}
+
+impl FromStr for Uuid {
+    type Err = Error;
+
+    /// Parse a UUID in the canonical format,
+    /// `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.as_bytes();
+        if s.len() != 36 {
+            return Err(Error::from("invalid UUID"));
+        }
+
+        let mut uuid = [0u8; 16];
+        let mut digits = Vec::with_capacity(32);
+        for (i, &c) in s.iter().enumerate() {
+            if matches!(i, 8 | 13 | 18 | 23) {
+                if c != b'-' {
+                    return Err(Error::from("invalid UUID"));
+                }
+            } else {
+                let digit = (c as char)
+                    .to_digit(16)
+                    .ok_or_else(|| Error::from("invalid UUID"))?;
+                digits.push(digit as u8);
+            }
+        }
+        for (byte, pair) in uuid.iter_mut().zip(digits.chunks(2)) {
+            *byte = pair[0] << 4 | pair[1];
+        }
+        Ok(Uuid(uuid))
+    }
+}
+
+impl Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl CloneToForeign for Uuid {
+    type Foreign = QemuUUID;
+
+    unsafe fn free_foreign(p: *mut QemuUUID) {
+        libc::free(p.cast());
+    }
+
+    fn clone_to_foreign(&self) -> OwnedPointer<Self> {
+        // SAFETY: we are copying into a freshly-allocated block
+        unsafe {
+            let p: *mut QemuUUID = libc::malloc(std::mem::size_of::<QemuUUID>()).cast();
+            (*p).data = self.0;
+            OwnedPointer::new(p)
+        }
+    }
+}
+
+impl FromForeign for Uuid {
+    unsafe fn cloned_from_foreign(p: *const QemuUUID) -> Self {
+        Uuid((*p).data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid_parse() {
+        let s = "550e8400-e29b-41d4-a716-446655440000";
+        let uuid: Uuid = s.parse().unwrap();
+        assert_eq!(uuid.0[0], 0x55);
+        assert_eq!(uuid.0[15], 0x00);
+        assert_eq!(uuid.to_string(), s);
+
+        let p = uuid.clone_to_foreign();
+        assert_eq!(p.into_native(), uuid);
+
+        assert!("550e8400e29b41d4a716446655440000".parse::<Uuid>().is_err());
+        assert!("550e8400-e29b-41d4-a716-44665544000g"
+            .parse::<Uuid>()
+            .is_err());
+        assert!("550e8400-e29b-41d4-a716_446655440000"
+            .parse::<Uuid>()
+            .is_err());
+    }
+}
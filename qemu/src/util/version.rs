@@ -0,0 +1,46 @@
+//! Version of QEMU that the crate is linked with
+
+use std::ptr;
+
+use crate::bindings::qapi_free_VersionInfo;
+use crate::bindings::qmp_query_version;
+
+/// Return the version of the running QEMU as a `(major, minor, micro)`
+/// triple, as reported by the `query-version` QMP command.  Development
+/// snapshots have a micro version of 50 or more, for example `(9, 0, 50)`
+/// is a snapshot between 9.0 and 9.1.
+pub fn qemu_version() -> (u32, u32, u32) {
+    // SAFETY: query-version cannot fail, and the result is freed
+    // after the version is copied out of it
+    unsafe {
+        let info = qmp_query_version(ptr::null_mut());
+        let triple = &*(*info).qemu;
+        let version = (
+            triple.major as u32,
+            triple.minor as u32,
+            triple.micro as u32,
+        );
+        qapi_free_VersionInfo(info);
+        version
+    }
+}
+
+/// Return whether the version of QEMU is at least `major.minor.micro`.
+pub fn qemu_version_at_least(major: u32, minor: u32, micro: u32) -> bool {
+    qemu_version() >= (major, minor, micro)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{qemu_version, qemu_version_at_least};
+
+    #[test]
+    fn test_qemu_version() {
+        // The stubs report version 9.1.50
+        assert_eq!(qemu_version(), (9, 1, 50));
+        assert!(qemu_version_at_least(9, 1, 0));
+        assert!(qemu_version_at_least(9, 1, 50));
+        assert!(!qemu_version_at_least(9, 2, 0));
+        assert!(!qemu_version_at_least(10, 0, 0));
+    }
+}
@@ -18,4 +18,5 @@ pub unsafe trait Zeroed: Sized {
 }
 
 // Put here all the impls that you need for the bindgen-provided types.
+unsafe impl Zeroed for crate::bindings::DeviceClass {}
 unsafe impl Zeroed for crate::bindings::TypeInfo {}
@@ -1,28 +1,168 @@
 use const_default::ConstDefault;
 use cstr::cstr;
 
+use qemu::class_property;
 use qemu::qom_define_type;
+use qemu::AnyObject;
+use qemu::ClassProperty;
 use qemu::Object;
 use qemu::ObjectClassMethods;
 use qemu::ObjectImpl;
+use qemu::ObjectRef;
+use qemu::TypeImpl;
 
+use qemu::device_config;
+use qemu::device_impl;
 use qemu::qdev_define_type;
 use qemu::qdev_prop;
+use qemu::register_device_type;
+use qemu::Deferred;
+use qemu::DeviceClass;
 use qemu::DeviceImpl;
+use qemu::DeviceImplExt;
 use qemu::DeviceMethods;
 use qemu::DeviceState;
+use qemu::DeviceTypeImpl;
+use qemu::DmaBuf;
+use qemu::ObjectTree;
+use qemu::SysBusDevice;
+use qemu::VirtIODevice;
+use qemu::VirtioDeviceImpl;
+use qemu::VirtioDeviceMethods;
 
+use qemu::CloneToForeign;
+use qemu::Error;
+use qemu::ErrorClass;
+use qemu::IrqBank;
+use qemu::LeakGuard;
+use qemu::MemoryRegion;
+use qemu::MmioRegion;
+use qemu::RegisterFile;
+use qemu::RunState;
+use qemu::VmChangeStateHandler;
+
+use qemu::OutputVisitor;
+use qemu::Owned;
+use qemu::Property;
+use qemu::QObject;
+use qemu::ResetType;
 use qemu::Result;
+use qemu::ResultExt;
+use qemu::Tracked;
+use qemu::Uuid;
+use qemu::VMStateDescription;
+use qemu::VisitOutput;
+
+use qemu::util::bitops::{extract32, try_deposit32};
+use qemu::util::version::{qemu_version, qemu_version_at_least};
+
+use qemu::qapi::qmp;
 
+use qemu::mmio_region;
+use qemu::vmstate_description;
+use qemu::vmstate_field;
 use qemu::with_offsets;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::ffi::{c_void, CStr};
+use std::mem::MaybeUninit;
+use std::os::unix::io::RawFd;
+use std::panic::AssertUnwindSafe;
+use std::ptr;
+
+// Grouped in TestConf, but each field is a separate property
+#[repr(C)]
+struct TestRingConf {
+    packed: bool,
+    size: usize,
+}
+
+impl ConstDefault for TestRingConf {
+    const DEFAULT: Self = TestRingConf {
+        packed: false,
+        size: 256,
+    };
+}
+
+impl Default for TestRingConf {
+    fn default() -> Self {
+        TestRingConf::DEFAULT
+    }
+}
+
+// The DMA window starts 5 bytes before the buffer unless configured
+#[repr(C)]
+struct TestDmaConf {
+    offset: i32,
+}
+
+impl ConstDefault for TestDmaConf {
+    const DEFAULT: Self = TestDmaConf { offset: -5 };
+}
+
+impl Default for TestDmaConf {
+    fn default() -> Self {
+        TestDmaConf::DEFAULT
+    }
+}
 
 with_offsets! {
     #[repr(C)]
-    #[derive(Default, ConstDefault)]
     struct TestConf {
         foo: bool,
+        peer: Option<Owned<TestObject>>,
+        queues: Tracked<u32>,
+        ctrl: u32,
+        uuid: Uuid,
+        ports: u32,
+        buf_size: usize,
+        fd: Option<RawFd>,
+        ring: TestRingConf,
+        dma: TestDmaConf,
+    }
+}
+
+impl ConstDefault for TestConf {
+    const DEFAULT: Self = TestConf {
+        foo: true,
+        peer: None,
+        queues: ConstDefault::DEFAULT,
+        ctrl: 0,
+        uuid: ConstDefault::DEFAULT,
+        ports: 0,
+        buf_size: 0,
+        fd: None,
+        ring: TestRingConf::DEFAULT,
+        dma: TestDmaConf::DEFAULT,
+    };
+}
+
+impl Default for TestConf {
+    fn default() -> Self {
+        TestConf::DEFAULT
+    }
+}
+
+// Counts how many times a device was reset, so that tests can check
+// that resetting a composed device reached all of its parts
+#[derive(Debug, Default)]
+struct ResetCounter(Cell<u32>);
+
+impl ResetCounter {
+    fn reset(&self) {
+        self.0.set(self.0.get() + 1);
+    }
+
+    // Panic unless the device was reset exactly once, then clear the counter
+    fn assert_reset_once(&self) {
+        let count = self.0.replace(0);
+        assert!(
+            count == 1,
+            "device was reset {} times, expected once",
+            count
+        );
     }
 }
 
@@ -30,17 +170,165 @@ with_offsets! {
 struct TestState {
     #[allow(dead_code)]
     bar: i32,
+    resets: ResetCounter,
+    scratch: u32,
+    inputs: u32,
+    buffer: Deferred<Vec<u8>>,
+    vm_running: Option<bool>,
+    vm_handler: Option<VmChangeStateHandler<TestDevice>>,
+    children_before_unparent: Option<usize>,
+    realized_after_unparent: Option<bool>,
+    foo_changes: u32,
+    foo_locked: bool,
+}
+
+device_config! {
+    #[derive(Default)]
+    struct TestDeviceConfig {
+        foo: Option<bool>,
+        queues: Option<u32>,
+        buf_size: Option<usize>,
+        ring_size: Option<usize>,
+    }
+}
+
+struct TestVersion {
+    major: u64,
+    minor: u64,
+}
+
+impl VisitOutput for TestVersion {
+    const TYPE: &'static CStr = cstr!("TestVersion");
+
+    fn visit_output(&self, v: &mut OutputVisitor, name: Option<&CStr>) -> Result<()> {
+        v.visit_struct(name, |v| {
+            v.visit_u64(Some(cstr!("major")), self.major)?;
+            v.visit_u64(Some(cstr!("minor")), self.minor)
+        })
+    }
+}
+
+thread_local! {
+    static DROP_LOG: RefCell<Vec<&'static str>> = RefCell::new(Vec::new());
+}
+
+#[derive(Default)]
+struct TestObjectState {
+    size: Cell<u64>,
+    size_changes: Cell<u32>,
+    running: Cell<bool>,
+}
+
+impl Drop for TestObjectState {
+    fn drop(&mut self) {
+        DROP_LOG.with(|log| log.borrow_mut().push("test-object"));
+    }
 }
 
 qom_define_type!(
     cstr!("test-object"),
     TestObject,
     TestConf,
-    ();
+    TestObjectState;
     @extends Object
 );
 
-impl ObjectImpl for TestObject {}
+impl TestObject {
+    fn instance_init(obj: &mut MaybeUninit<TestObject>) {
+        TestObject::uninit_conf(obj).write(TestConf::default());
+        // SAFETY: the configuration was just initialized, and the state
+        // is initialized before INSTANCE_INIT runs
+        let obj = unsafe { obj.assume_init_ref() };
+        obj.state.size.set(4096);
+        qemu::ObjectMethods::add_uint_property(
+            &obj,
+            cstr!("size"),
+            TestObject::size,
+            Some(TestObject::set_size),
+            512..=65536,
+        );
+        qemu::ObjectMethods::add_inline_child_property::<TestObject>(&obj, cstr!("backend"));
+        qemu::ObjectMethods::on_property_change(&obj, cstr!("size"), TestObject::size_changed);
+        qemu::ObjectMethods::set_property_guard(&obj, cstr!("size"), TestObject::is_stopped);
+    }
+
+    fn size_changed(&self) {
+        self.state
+            .size_changes
+            .set(self.state.size_changes.get() + 1);
+    }
+
+    // The size cannot change while the backend is in use
+    fn is_stopped(&self) -> bool {
+        !self.state.running.get()
+    }
+
+    fn size(&self) -> u64 {
+        self.state.size.get()
+    }
+
+    fn set_size(&self, size: u64) -> Result<()> {
+        if !size.is_power_of_two() {
+            return Err(Error::from("size must be a power of two"));
+        }
+        self.state.size.set(size);
+        Ok(())
+    }
+
+    #[allow(clippy::unused_self)]
+    fn has_extra_bytes(&self) -> bool {
+        true
+    }
+}
+
+impl ObjectImpl for TestObject {
+    const CLASS_PROPERTIES: &'static [ClassProperty<TestObject>] = &[class_property!(
+        cstr!("has-extra-bytes"),
+        TestObject,
+        bool,
+        TestObject::has_extra_bytes
+    )];
+    const EXTRA_INSTANCE_BYTES: usize = 64;
+    const INSTANCE_ALIGN: usize = 64;
+    const INSTANCE_INIT: Option<fn(&mut MaybeUninit<TestObject>)> = Some(TestObject::instance_init);
+    const INIT_CONF_IN_INSTANCE_INIT: bool = true;
+    // -object test-object,id=obj0,size=8192
+    const USER_CREATABLE: bool = true;
+}
+
+// An object that lives inside a larger allocation and frees it
+#[derive(Default)]
+struct TestEmbeddedState;
+
+impl Drop for TestEmbeddedState {
+    fn drop(&mut self) {
+        DROP_LOG.with(|log| log.borrow_mut().push("test-embedded"));
+    }
+}
+
+qom_define_type!(
+    cstr!("test-embedded"),
+    TestEmbedded,
+    TestConf,
+    TestEmbeddedState;
+    @extends Object
+);
+
+#[repr(C)]
+struct TestContainer {
+    obj: MaybeUninit<TestEmbedded>,
+    trailer: [u8; 16],
+}
+
+extern "C" fn test_container_free(obj: *mut c_void) {
+    DROP_LOG.with(|log| log.borrow_mut().push("test-container"));
+    // SAFETY: the object is the first field of a boxed TestContainer
+    drop(unsafe { Box::from_raw(obj.cast::<TestContainer>()) });
+}
+
+impl ObjectImpl for TestEmbedded {
+    const FREE: Option<extern "C" fn(*mut c_void)> = Some(test_container_free);
+}
 
 qdev_define_type!(
     cstr!("test-device"),
@@ -48,41 +336,1236 @@ qdev_define_type!(
     TestConf,
     RefCell<TestState>;
     @extends DeviceState;
-    @properties [qdev_prop!(bool, cstr!("foo"), TestDevice, true, foo)]
+    @properties [
+        qdev_prop!(bool, cstr!("foo"), TestDevice, true, foo;
+                   @description cstr!("Enable foo"),
+                   @notify TestDevice::foo_changed,
+                   @settable TestDevice::foo_settable),
+        qdev_prop!(link, cstr!("peer"), TestDevice, TestObject, peer),
+        qdev_prop!(ro_uint64, cstr!("status"), TestDevice, TestDevice::status),
+        qdev_prop!(ro, cstr!("version"), TestDevice, TestVersion, TestDevice::version),
+        qdev_prop!(tracked, uint32, cstr!("queues"), TestDevice, u32, queues),
+        qdev_prop!(bool_bit, cstr!("enable"), TestDevice, ctrl, 0, true),
+        qdev_prop!(bool_bit, cstr!("irq"), TestDevice, ctrl, 1, false),
+        qdev_prop!(bool_bit, cstr!("dma"), TestDevice, ctrl, 3, true),
+        qdev_prop!(uuid, cstr!("uuid"), TestDevice, uuid),
+        qdev_prop!(usize, cstr!("buf-size"), TestDevice, buf_size; @frozen),
+        qdev_prop!(fd, cstr!("fd"), TestDevice, fd),
+        qdev_prop!(bool, cstr!("ring-packed"), TestDevice, ring.packed),
+        qdev_prop!(usize, cstr!("ring-size"), TestDevice, ring.size),
+        qdev_prop!(int32, cstr!("dma-offset"), TestDevice, dma.offset)
+    ]
+);
+
+// A device that cannot be realized without its DMA target
+with_offsets! {
+    #[repr(C)]
+    #[derive(Default, ConstDefault)]
+    struct TestDmaUserConf {
+        dma: Option<Owned<TestObject>>,
+    }
+}
+
+qdev_define_type!(
+    cstr!("test-dma-user"),
+    TestDmaUser,
+    TestDmaUserConf,
+    ();
+    @extends DeviceState, Object;
+    @properties [
+        qdev_prop!(link, cstr!("dma"), TestDmaUser, TestObject, dma; @required)
+    ]
+);
+
+impl ObjectImpl for TestDmaUser {}
+impl DeviceImpl for TestDmaUser {}
+
+// A device that records when each of its hooks runs
+thread_local! {
+    static HOOK_LOG: RefCell<Vec<&'static str>> = RefCell::new(Vec::new());
+}
+
+fn log_hook(hook: &'static str) {
+    HOOK_LOG.with(|log| log.borrow_mut().push(hook));
+}
+
+struct TestOrderConf;
+
+impl ConstDefault for TestOrderConf {
+    const DEFAULT: Self = TestOrderConf;
+}
+
+impl Drop for TestOrderConf {
+    fn drop(&mut self) {
+        log_hook("drop conf");
+    }
+}
+
+struct TestOrderState;
+
+impl Default for TestOrderState {
+    fn default() -> Self {
+        log_hook("mem_init");
+        TestOrderState
+    }
+}
+
+impl Drop for TestOrderState {
+    fn drop(&mut self) {
+        log_hook("drop state");
+    }
+}
+
+qdev_define_type!(
+    cstr!("test-order"),
+    TestOrder,
+    TestOrderConf,
+    TestOrderState;
+    @extends DeviceState;
+    @properties []
+);
+
+impl ObjectImpl for TestOrder {
+    const INSTANCE_INIT: Option<fn(&mut MaybeUninit<TestOrder>)> =
+        Some(|_| log_hook("instance_init"));
+    const INSTANCE_POST_INIT: Option<fn(&TestOrder)> = Some(|_| log_hook("instance_post_init"));
+    const PRE_UNPARENT: Option<fn(&TestOrder)> = Some(|_| log_hook("pre_unparent"));
+    const UNPARENT: Option<fn(&TestOrder)> = Some(|_| log_hook("unparent"));
+}
+
+impl DeviceImpl for TestOrder {
+    const APPLY_DEFAULT_PROPS: Option<fn(&TestOrder) -> Result<()>> = Some(|_| {
+        log_hook("apply_default_props");
+        Ok(())
+    });
+    const TRY_INIT: Option<fn(&TestOrder) -> Result<()>> = Some(|_| {
+        log_hook("try_init");
+        Ok(())
+    });
+    const REALIZE: Option<fn(&TestOrder) -> Result<()>> = Some(|_| {
+        log_hook("realize");
+        Ok(())
+    });
+    const UNREALIZE: Option<fn(&TestOrder)> = Some(|_| log_hook("unrealize"));
+}
+
+// A Rust subclass of a Rust class; the hooks of both log their name
+struct TestOrderParentState;
+
+impl Default for TestOrderParentState {
+    fn default() -> Self {
+        log_hook("parent mem_init");
+        TestOrderParentState
+    }
+}
+
+impl Drop for TestOrderParentState {
+    fn drop(&mut self) {
+        log_hook("drop parent state");
+    }
+}
+
+qom_define_type!(
+    cstr!("test-order-parent"),
+    TestOrderParent,
+    (),
+    TestOrderParentState;
+    @extends Object
 );
 
+impl ObjectImpl for TestOrderParent {
+    const INSTANCE_INIT: Option<fn(&mut MaybeUninit<TestOrderParent>)> =
+        Some(|_| log_hook("parent instance_init"));
+    const INSTANCE_POST_INIT: Option<fn(&TestOrderParent)> =
+        Some(|_| log_hook("parent instance_post_init"));
+}
+
+struct TestOrderChildState;
+
+impl Default for TestOrderChildState {
+    fn default() -> Self {
+        log_hook("child mem_init");
+        TestOrderChildState
+    }
+}
+
+impl Drop for TestOrderChildState {
+    fn drop(&mut self) {
+        log_hook("drop child state");
+    }
+}
+
+qom_define_type!(
+    cstr!("test-order-child"),
+    TestOrderChild,
+    (),
+    TestOrderChildState;
+    @extends TestOrderParent, Object
+);
+
+impl ObjectImpl for TestOrderChild {
+    const INSTANCE_INIT: Option<fn(&mut MaybeUninit<TestOrderChild>)> =
+        Some(|_| log_hook("child instance_init"));
+    const INSTANCE_POST_INIT: Option<fn(&TestOrderChild)> =
+        Some(|_| log_hook("child instance_post_init"));
+}
+
+const TEST_DEVICE_PORTS: u8 = 4;
+
 impl TestDevice {
-    #[allow(clippy::unused_self)]
+    fn pre_unparent(&self) {
+        let children = qemu::ObjectMethods::children_of_type::<TestDevice>(&self).count();
+        self.state.borrow_mut().children_before_unparent = Some(children);
+    }
+
+    fn foo_changed(&self) {
+        self.state.borrow_mut().foo_changes += 1;
+    }
+
+    fn foo_settable(&self) -> bool {
+        !self.state.borrow().foo_locked
+    }
+
+    // The superclass's unparent has unrealized the device by now
     fn unparent(&self) {
-        println!("unparent");
+        self.state.borrow_mut().realized_after_unparent = Some(self.is_realized());
+    }
+
+    #[allow(clippy::unused_self)]
+    fn status(&self) -> u64 {
+        42
     }
 
     #[allow(clippy::unused_self)]
+    fn version(&self) -> TestVersion {
+        TestVersion { major: 1, minor: 2 }
+    }
+
+    fn apply_default_props(&self) -> Result<()> {
+        // One queue per CPU, unless specified on the command line
+        self.conf.queues.set_default(4);
+        Ok(())
+    }
+
+    // One "portN" property for each bit of conf.ports
+    fn dynamic_properties() -> Vec<Property> {
+        (0..TEST_DEVICE_PORTS)
+            .map(|i| {
+                let name = CString::new(format!("port{}", i)).unwrap();
+                let name: &'static CStr = Box::leak(name.into_boxed_c_str());
+                Property {
+                    name: name.as_ptr(),
+                    bitnr: i,
+                    ..qdev_prop!(bool_bit, cstr!("port"), TestDevice, ports, 0, false)
+                }
+            })
+            .collect()
+    }
+
+    // The buffer size is only known after properties are set, and the
+    // allocation can fail, so it cannot be done in instance_init
+    fn try_init(&self) -> Result<()> {
+        let size = self.conf.buf_size;
+        self.state.borrow().buffer.get_or_try_init(|| {
+            let mut buf = Vec::new();
+            buf.try_reserve_exact(size)
+                .map_err(|e| Error::with_error("cannot allocate buffer", e))?;
+            buf.resize(size, 0);
+            Ok(buf)
+        })?;
+        Ok(())
+    }
+
     fn realize(&self) -> Result<()> {
         println!("realize");
+        if let Some(machine) = TestDevice::current_machine_type() {
+            println!("running on {}", machine);
+        }
+        if let Some(fd) = self.conf.fd {
+            println!("using file descriptor {}", fd);
+        }
+        // SAFETY: the handler is dropped together with the state of self
+        let handler = unsafe {
+            qemu::util::runstate::add_vm_change_state_handler(self, TestDevice::vm_state_changed)
+        };
+        self.state.borrow_mut().vm_handler = Some(handler);
         Ok(())
     }
 
-    #[allow(clippy::unused_self)]
+    fn vm_state_changed(&self, running: bool, state: RunState) {
+        assert_eq!(running, state == RunState::Running);
+        self.state.borrow_mut().vm_running = Some(running);
+    }
+
     fn unrealize(&self) {
         println!("unrealize");
+        self.state.borrow_mut().vm_handler = None;
+    }
+
+    fn cold_reset(&self) {
+        self.state.borrow().resets.reset();
+    }
+
+    fn instance_init(obj: &mut MaybeUninit<TestDevice>) {
+        // SAFETY: the state is initialized before INSTANCE_INIT runs
+        let obj = unsafe { obj.assume_init_ref() };
+        obj.init_gpio_in(2);
+    }
+
+    fn gpio_in(&self, line: u32, level: bool) {
+        let mut state = self.state.borrow_mut();
+        if level {
+            state.inputs |= 1 << line;
+        } else {
+            state.inputs &= !(1 << line);
+        }
+    }
+
+    fn reset_hold(&self, reset_type: ResetType) {
+        // The scratch register is restored by the snapshot
+        if reset_type != ResetType::SnapshotLoad {
+            self.state.borrow_mut().scratch = 0;
+        }
     }
 }
 
 impl ObjectImpl for TestDevice {
+    const INSTANCE_INIT: Option<fn(&mut MaybeUninit<TestDevice>)> = Some(TestDevice::instance_init);
+    const PRE_UNPARENT: Option<fn(&TestDevice)> = Some(TestDevice::pre_unparent);
     const UNPARENT: Option<fn(&TestDevice)> = Some(TestDevice::unparent);
 }
 
-impl DeviceImpl for TestDevice {
-    const REALIZE: Option<fn(&TestDevice) -> Result<()>> = Some(TestDevice::realize);
-    const UNREALIZE: Option<fn(&TestDevice)> = Some(TestDevice::unrealize);
+device_impl!(
+    TestDevice: apply_default_props,
+    dynamic_properties,
+    try_init,
+    realize,
+    unrealize,
+    cold_reset,
+    reset_hold,
+    gpio_in
+);
+
+qdev_define_type!(
+    cstr!("test-virtio"),
+    TestVirtio,
+    TestConf,
+    ();
+    @extends VirtIODevice;
+    @properties [
+        qdev_prop!(virtio_features, cstr!("features"), TestVirtio)
+    ]
+);
+
+const TEST_VIRTIO_F_FOO: u32 = 0;
+
+impl TestVirtio {
+    #[allow(clippy::unused_self)]
+    fn get_config(&self, config: &mut [u8]) {
+        config[0] = 1;
+    }
+
+    fn set_features(&self, _features: u64) {
+        println!(
+            "foo negotiated: {}",
+            self.has_guest_feature(TEST_VIRTIO_F_FOO)
+        );
+    }
+}
+
+impl ObjectImpl for TestVirtio {}
+
+// Only available with -device
+impl DeviceImpl for TestVirtio {
+    const HOTPLUGGABLE: bool = false;
+}
+
+impl VirtioDeviceImpl for TestVirtio {
+    const DEVICE_ID: u16 = 0x1234;
+    const CONFIG_SIZE: usize = 1;
+    const HOST_FEATURES: u64 = 1 << TEST_VIRTIO_F_FOO;
+
+    const SET_FEATURES: Option<fn(&TestVirtio, u64)> = Some(TestVirtio::set_features);
+    const GET_CONFIG: Option<fn(&TestVirtio, &mut [u8])> = Some(TestVirtio::get_config);
+}
+
+// A simple interrupt controller with eight GPIO inputs, each of which
+// is forwarded to the GPIO output with the same number
+const TEST_INTC_LINES: usize = 8;
+const TEST_INTC_PULSE: u64 = 0xc;
+
+with_offsets! {
+    #[repr(C)]
+    struct TestIntcState {
+        regs: RegisterFile<3>,
+        irqs: IrqBank,
+        reads: Cell<u64>,
+        writes: Cell<u64>,
+        pulses: Cell<u64>,
+    }
+}
+
+impl Default for TestIntcState {
+    fn default() -> Self {
+        TestIntcState {
+            regs: RegisterFile::new(),
+            irqs: IrqBank::new(TEST_INTC_LINES),
+            reads: Cell::new(0),
+            writes: Cell::new(0),
+            pulses: Cell::new(0),
+        }
+    }
+}
+
+qdev_define_type!(
+    cstr!("test-sysbus"),
+    TestSysBus,
+    TestConf,
+    TestIntcState;
+    @extends SysBusDevice;
+    @properties []
+);
+
+impl TestSysBus {
+    fn instance_init(obj: &mut MaybeUninit<TestSysBus>) {
+        // SAFETY: the state is initialized before INSTANCE_INIT runs
+        let obj = unsafe { obj.assume_init_ref() };
+        obj.state.irqs.init_gpio_out(obj);
+        obj.init_gpio_in(TEST_INTC_LINES);
+    }
+
+    // Each line has a 4-bit priority in register 1
+    fn set_priority(&self, line: u32, priority: u32) -> Result<()> {
+        let regs = &self.state.regs;
+        let value = try_deposit32(regs.read(1), line * 4, 4, priority)
+            .qemu_context("invalid interrupt priority")?;
+        regs.write(1, value);
+        Ok(())
+    }
+
+    fn set_input(&self, line: u32, level: bool) {
+        self.state.irqs.set(line as usize, level);
+    }
+
+    fn cold_reset(&self) {
+        self.state.irqs.reset();
+    }
+
+    vmstate_description!(
+        fn vmstate, cstr!("test-sysbus"), TestSysBus, 2, 1;
+        @fields [
+            vmstate_field!(uint64, TestSysBus, reads),
+            vmstate_field!(uint64, TestSysBus, writes),
+        ];
+        @subsections [TestSysBus::vmstate_pulses]
+    );
+
+    // Pulses were added later, and only need to be migrated once used
+    vmstate_description!(
+        fn vmstate_pulses, cstr!("test-sysbus/pulses"), TestSysBus, 1, 1;
+        @needed TestSysBus::pulses_needed;
+        @fields [vmstate_field!(uint64, TestSysBus, pulses)]
+    );
+
+    fn pulses_needed(&self) -> bool {
+        self.state.pulses.get() != 0
+    }
+
+    fn counters(&self) -> Vec<(CString, u64)> {
+        vec![
+            (CString::new("reads").unwrap(), self.state.reads.get()),
+            (CString::new("writes").unwrap(), self.state.writes.get()),
+            (CString::new("pulses").unwrap(), self.state.pulses.get()),
+        ]
+    }
+
+    fn read(&self, addr: u64, _size: u32) -> u64 {
+        self.state.reads.set(self.state.reads.get() + 1);
+        self.state.regs.read(addr as usize / 4).into()
+    }
+
+    fn write(&self, addr: u64, data: u64, _size: u32) {
+        self.state.writes.set(self.state.writes.get() + 1);
+        if addr == TEST_INTC_PULSE {
+            self.state.pulses.set(self.state.pulses.get() + 1);
+            self.state.irqs.pulse(data as usize);
+        } else {
+            self.state.regs.write(addr as usize / 4, data as u32);
+        }
+    }
+}
+
+impl ObjectImpl for TestSysBus {
+    const INSTANCE_COUNTERS: Option<fn(&TestSysBus) -> Vec<(CString, u64)>> =
+        Some(TestSysBus::counters);
+    const INSTANCE_INIT: Option<fn(&mut MaybeUninit<TestSysBus>)> = Some(TestSysBus::instance_init);
+}
+
+impl DeviceImpl for TestSysBus {
+    const COLD_RESET: Option<fn(&TestSysBus)> = Some(TestSysBus::cold_reset);
+    const GPIO_IN: Option<fn(&TestSysBus, u32, bool)> = Some(TestSysBus::set_input);
+    const VMSTATE: Option<fn() -> *const VMStateDescription> = Some(TestSysBus::vmstate);
+    const MMIO_REGIONS: &'static [MmioRegion<TestSysBus>] = &[mmio_region!(
+        cstr!("regs"),
+        16,
+        TestSysBus,
+        TestSysBus::read,
+        TestSysBus::write
+    )];
+}
+
+// Works for every device type that uses TestConf
+fn buffer_size<T: DeviceTypeImpl<Conf = TestConf>>(dev: &T) -> usize {
+    dev.conf().buf_size
+}
+
+// Short-lived helper that looks at a device without taking a reference
+struct DeviceSummary<'a> {
+    dev: ObjectRef<'a, DeviceState>,
+    gpio_out: usize,
+}
+
+impl<'a> DeviceSummary<'a> {
+    fn new(dev: ObjectRef<'a, DeviceState>) -> Self {
+        DeviceSummary {
+            dev,
+            gpio_out: dev.gpio_line_count(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} ({} GPIO outputs, realized: {})",
+            qemu::ObjectMethods::typename(&self.dev),
+            self.gpio_out,
+            self.dev.is_realized()
+        )
+    }
+}
+
+// Reply to "query-test-device", which takes an optional "verbose" argument
+fn query_test_device(args: QObject) -> Result<QObject> {
+    let mut reply = BTreeMap::new();
+    reply.insert("status".to_string(), QObject::Uint(42));
+    match args {
+        QObject::Dict(args) => match args.get("verbose") {
+            None | Some(QObject::Bool(false)) => {}
+            Some(QObject::Bool(true)) => {
+                reply.insert("version".to_string(), QObject::Str("1.2".to_string()));
+            }
+            Some(_) => return Err(Error::from("'verbose' must be a boolean")),
+        },
+        _ => return Err(Error::from("arguments must be a dictionary")),
+    }
+    Ok(QObject::Dict(reply))
+}
+
+/// Find a property in the static property table of `TestDevice`
+fn test_device_property(name: &CStr) -> &'static Property {
+    let mut prop = TestDevice::properties();
+    // SAFETY: the table is terminated by END_OF_LIST and lives forever
+    unsafe {
+        while !(*prop).name.is_null() {
+            if CStr::from_ptr((*prop).name) == name {
+                return &*prop;
+            }
+            prop = prop.add(1);
+        }
+    }
+    panic!("TestDevice has no property {:?}", name);
 }
 
 fn main() {
-    drop(TestObject::new());
+    let _guard = LeakGuard::new();
+
+    let (major, minor, micro) = qemu_version();
+    assert!(major >= 9);
+    assert!(qemu_version_at_least(major, minor, micro));
+    assert!(!qemu_version_at_least(major, minor, micro + 1));
+
+    register_device_type::<TestVirtio>().unwrap();
+    assert!(register_device_type::<TestVirtio>().is_err());
+
+    assert!(TestObject::object_class().has_property(cstr!("has-extra-bytes")));
+
+    // The callbacks can be checked without registering the type
+    let dc = DeviceClass::test_class_init::<TestDevice>();
+    assert!(dc.realize.is_some());
+    assert!(dc.unrealize.is_some());
+    assert!(dc.cold_reset.is_some());
+    assert!(dc.oc.unparent.is_some());
+    assert!(dc.hotpluggable);
+    let virtio_dc = DeviceClass::test_class_init::<TestVirtio>();
+    assert!(virtio_dc.realize.is_none());
+    assert!(virtio_dc.cold_reset.is_none());
+    assert!(virtio_dc.oc.unparent.is_none());
+    assert!(!virtio_dc.hotpluggable);
+
+    let o = TestObject::new();
+    let addr: *const TestObject = &*o;
+    assert_eq!(addr as usize % 64, 0);
+    o.extra_instance_bytes()[63].set(42);
+    assert_eq!(o.extra_instance_bytes()[63].get(), 42);
+    Owned::release(o);
+    // The state is dropped by instance_finalize, before Object's own
+    // finalization
+    DROP_LOG.with(|log| assert_eq!(*log.borrow(), ["test-object"]));
+
+    // The custom free function runs once, after finalization
+    DROP_LOG.with(|log| log.borrow_mut().clear());
+    let container = Box::into_raw(Box::new(TestContainer {
+        obj: MaybeUninit::uninit(),
+        trailer: [0; 16],
+    }));
+    let embedded = unsafe { TestEmbedded::initialize(ptr::addr_of_mut!((*container).obj)) };
+    Owned::release(embedded);
+    DROP_LOG.with(|log| assert_eq!(*log.borrow(), ["test-embedded", "test-container"]));
+
+    // A failed cast gives back the original reference, without
+    // touching the reference count
+    let obj = Owned::upcast::<Object>(TestObject::new());
+    let refcnt = obj.r#ref;
+    let obj = Owned::dynamic_cast::<DeviceState>(obj).unwrap_err();
+    assert_eq!(obj.r#ref, refcnt);
+    let obj = Owned::dynamic_cast::<TestObject>(obj).unwrap();
+    assert_eq!(obj.base.r#ref, refcnt);
+    drop(obj);
+
+    // Create an object from its type name, then recover the type
+    let untyped = Owned::<Object>::new_dynamic(cstr!("test-device")).unwrap();
+    let queues = Owned::with_downcast(&untyped, |dev: &TestDevice| dev.conf.queues.get());
+    assert_eq!(queues, Some(0));
+    assert!(Owned::with_downcast(&untyped, |_: &TestObject| ()).is_none());
+    let typed = Owned::dynamic_cast::<TestDevice>(untyped).unwrap();
+    assert_eq!(qemu::ObjectMethods::typename(&typed), "test-device");
+    drop(typed);
+    assert!(Owned::<Object>::new_dynamic(cstr!("no-such-type")).is_err());
+    assert!(Owned::<Object>::new_dynamic(cstr!("device")).is_err());
+
+    // Objects of different types in the same collection
+    let objs: Vec<AnyObject> = vec![TestObject::new().into(), TestDevice::new().into()];
+    assert!(objs[0].is::<TestObject>());
+    assert!(objs[1].is::<DeviceState>());
+    assert!(objs[0].downcast_ref::<TestDevice>().is_none());
+    let mut objs = objs.into_iter();
+    let first = objs.next().unwrap().downcast::<TestObject>().unwrap();
+    assert_eq!(qemu::ObjectMethods::typename(&first), "test-object");
+    let second = objs.next().unwrap().downcast::<TestObject>().unwrap_err();
+    let second = second.downcast::<TestDevice>().unwrap();
+    assert_eq!(qemu::ObjectMethods::typename(&second), "test-device");
+    drop((first, second));
+
+    // Objects created with -object are found under /objects
+    if let Some(backend) = Owned::<TestObject>::resolve_path("/objects/obj0") {
+        assert_eq!(qemu::ObjectMethods::typename(&backend), "test-object");
+    }
+    assert!(Owned::<TestObject>::resolve_path("/objects/no-such-object").is_none());
+
+    // Bounds are checked before the setter is called, and the notifier
+    // only runs after the setter succeeds
+    let sized = TestObject::new();
+    qemu::ObjectMethods::set_property_qobject(&sized, cstr!("size"), QObject::Uint(1024)).unwrap();
+    assert_eq!(sized.size(), 1024);
+    assert_eq!(sized.state.size_changes.get(), 1);
+    for bad in [256, 3000, 1 << 20] {
+        assert!(qemu::ObjectMethods::set_property_qobject(
+            &sized,
+            cstr!("size"),
+            QObject::Uint(bad)
+        )
+        .is_err());
+    }
+    sized.state.running.set(true);
+    assert_eq!(
+        qemu::ObjectMethods::set_property_qobject(&sized, cstr!("size"), QObject::Uint(2048))
+            .unwrap_err()
+            .to_string(),
+        "Property size cannot be set in the current state"
+    );
+    sized.state.running.set(false);
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&sized, cstr!("size")).unwrap(),
+        QObject::Uint(1024)
+    );
+    assert_eq!(sized.state.size_changes.get(), 1);
+    drop(sized);
+
+    // Create a child from an inline definition, as in backend.size=2048
+    let outer = TestObject::new();
+    let backend = BTreeMap::from([("size".to_string(), QObject::Str("2048".to_string()))]);
+    qemu::ObjectMethods::set_property_qobject(&outer, cstr!("backend"), QObject::Dict(backend))
+        .unwrap();
+    let inner = qemu::ObjectMethods::children_of_type::<TestObject>(&outer)
+        .next()
+        .unwrap();
+    assert_eq!(inner.size(), 2048);
+    assert!(qemu::ObjectMethods::set_property_qobject(
+        &outer,
+        cstr!("backend"),
+        QObject::Dict(BTreeMap::new())
+    )
+    .is_err());
+    drop(inner);
+
+    // Stash a reference in a C opaque and take it back
+    let opaque = Owned::into_opaque(TestObject::new());
+    let recovered: Owned<TestObject> = unsafe { Owned::from_opaque(opaque) };
+    assert_eq!(qemu::ObjectMethods::typename(&recovered), "test-object");
+    drop(recovered);
 
-    let d = TestDevice::new();
+    let singleton: &'static TestObject = Owned::leak(TestObject::new());
+    assert_eq!(singleton.extra_instance_bytes()[0].get(), 0);
+
+    // Each kind of property in the static table uses the C PropertyInfo
+    for (name, info) in [
+        (cstr!("foo"), unsafe { &qemu::bindings::qdev_prop_bool }),
+        (cstr!("peer"), unsafe { &qemu::bindings::qdev_prop_link }),
+        (cstr!("enable"), unsafe { &qemu::bindings::qdev_prop_bit }),
+        (cstr!("uuid"), unsafe { &qemu::bindings::qdev_prop_uuid }),
+        (cstr!("buf-size"), unsafe {
+            &qemu::bindings::qdev_prop_usize
+        }),
+        (cstr!("dma-offset"), unsafe {
+            &qemu::bindings::qdev_prop_int32
+        }),
+    ] {
+        assert!(ptr::eq(test_device_property(name).info, info));
+    }
+
+    // Defaults come from TestConf::DEFAULT, which the literal default of
+    // "foo" must match
+    let conf = TestConf::DEFAULT;
+    assert_eq!(test_device_property(cstr!("foo")).default, 1);
+    assert_eq!(
+        test_device_property(cstr!("buf-size")).default,
+        conf.buf_size as u64
+    );
+    assert_eq!(
+        test_device_property(cstr!("dma-offset")).default as i64,
+        conf.dma.offset.into()
+    );
+
+    // set_default_value records the default in the class property,
+    // and QOM applies it to new instances
+    let klass: *const qemu::bindings::ObjectClass = TestDevice::object_class();
+    let defval = unsafe {
+        let op = qemu::bindings::object_class_property_find(
+            klass.cast_mut(),
+            cstr!("buf-size").as_ptr(),
+        );
+        <QObject as qemu::FromForeign>::cloned_from_foreign((*op).defval)
+    };
+    assert_eq!(defval, QObject::Uint(conf.buf_size as u64));
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&TestDevice::new(), cstr!("buf-size")).unwrap(),
+        defval
+    );
+
+    let mut d = TestDevice::new();
+    Owned::get_mut(&mut d).unwrap().get_mut().bar = 1;
+    d.state.borrow_mut().scratch = 0x1234;
+    d.reset(ResetType::SnapshotLoad);
+    assert_eq!(d.state.borrow().scratch, 0x1234);
+    d.reset(ResetType::Cold);
+    assert_eq!(d.state.borrow().scratch, 0);
+    assert!(d.device_class().realize.is_some());
+    assert!(d.device_class().cold_reset.is_some());
+    assert!(d.device_class().hotpluggable);
+    // SAFETY: foo is a class property of TestDevice
+    unsafe {
+        let foo =
+            qemu::bindings::object_class_property_find(klass.cast_mut(), cstr!("foo").as_ptr());
+        assert_eq!(CStr::from_ptr((*foo).description), cstr!("Enable foo"));
+    }
+    let child = TestDevice::new();
+    qemu::ObjectMethods::set_property_qobject(&child, cstr!("queues"), QObject::Uint(8)).unwrap();
+    assert!(qemu::ObjectMethods::set_property_qobject(
+        &child,
+        cstr!("foo"),
+        QObject::Str("yes".to_string())
+    )
+    .is_err());
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&child, cstr!("foo")).unwrap(),
+        QObject::Bool(true)
+    );
+    // A failed set does not call the notifier
+    assert_eq!(child.state.borrow().foo_changes, 0);
+    qemu::ObjectMethods::set_property_qobject(&child, cstr!("foo"), QObject::Bool(false)).unwrap();
+    assert_eq!(child.state.borrow().foo_changes, 1);
+    child.state.borrow_mut().foo_locked = true;
+    assert_eq!(
+        qemu::ObjectMethods::set_property_qobject(&child, cstr!("foo"), QObject::Bool(true))
+            .unwrap_err()
+            .to_string(),
+        "Property foo cannot be set in the current state"
+    );
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&child, cstr!("foo")).unwrap(),
+        QObject::Bool(false)
+    );
+    assert_eq!(child.state.borrow().foo_changes, 1);
+    child.state.borrow_mut().foo_locked = false;
+    // "foo" belongs to the class, which is shared with other types; use
+    // @notify instead
+    assert!(std::panic::catch_unwind(AssertUnwindSafe(|| {
+        qemu::ObjectMethods::on_property_change(&child, cstr!("foo"), |_| {});
+    }))
+    .is_err());
+    assert!(qemu::ObjectMethods::get_property_qobject(&child, cstr!("nonexistent")).is_err());
+    qemu::ObjectMethods::set_property_qobject(
+        &child,
+        cstr!("buf-size"),
+        QObject::Str("64k".to_string()),
+    )
+    .unwrap();
+    assert_eq!(child.conf.buf_size, 65536);
+    qemu::ObjectMethods::add_child(&d, cstr!("child"), &*child);
+    let d2 = d.clone();
+    assert!(Owned::get_mut(&mut d).is_none());
+    drop(d2);
+    d.realize_children().unwrap();
+    assert_eq!(
+        qemu::ObjectMethods::children_of_type::<TestDevice>(&d).count(),
+        1
+    );
+    let devices: Vec<(String, Owned<DeviceState>)> = qemu::ObjectMethods::child_refs(&d)
+        .filter_map(|(name, c)| Some((name, Owned::dynamic_cast(c).ok()?)))
+        .collect();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].0, "child");
+    drop(devices);
+    // Devices that are not plugged into a bus have no parent bus
+    assert!(d.parent_bus().is_none());
     d.realize().unwrap();
+    assert!(d.state.borrow().vm_handler.is_some());
+    assert_eq!(d.state.borrow().vm_running, None);
+    assert_eq!(d.conf.queues.get(), 4);
+    assert_eq!(child.conf.queues.get(), 8);
+    // buf-size is frozen once the device is realized
+    assert!(child.is_realized());
+    assert!(qemu::ObjectMethods::set_property_qobject(
+        &child,
+        cstr!("buf-size"),
+        QObject::Uint(4096)
+    )
+    .is_err());
+    assert_eq!(child.conf.buf_size, 65536);
+    assert_eq!(child.state.borrow().buffer.len(), 65536);
+    assert_eq!(d.conf.ctrl, 0b1001);
+    assert!(d.conf.uuid.is_nil());
+    // The dynamic properties were registered with QOM
+    for i in 0..TEST_DEVICE_PORTS {
+        let name = CString::new(format!("port{}", i)).unwrap();
+        assert!(TestDevice::object_class().has_property(&name));
+    }
+    let port_name = CString::new(format!("port{}", TEST_DEVICE_PORTS)).unwrap();
+    assert!(!TestDevice::object_class().has_property(&port_name));
+    let ports = TestDevice::new();
+    qemu::ObjectMethods::set_property_qobject(&ports, cstr!("port0"), QObject::Bool(true)).unwrap();
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&ports, cstr!("port0")).unwrap(),
+        QObject::Bool(true)
+    );
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&ports, cstr!("port1")).unwrap(),
+        QObject::Bool(false)
+    );
+    assert_eq!(ports.conf.ports & 1, 1);
+
+    // Properties can refer to fields of nested structs, and take their
+    // default from the nested struct's ConstDefault
+    assert_eq!(test_device_property(cstr!("ring-size")).default, 256);
+    assert_eq!(child.conf.ring.size, 256);
+    let errors = qemu::ObjectMethods::set_properties(
+        &child,
+        &[
+            (cstr!("queues"), QObject::Str("many".to_string())),
+            (cstr!("ring-size"), QObject::Uint(512)),
+            (cstr!("nonexistent"), QObject::Bool(true)),
+        ],
+    )
+    .unwrap_err();
+    let failed: Vec<&str> = errors.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(failed, ["queues", "nonexistent"]);
+    assert_eq!(child.conf.ring.size, 512);
+    assert_eq!(child.conf.dma.offset, -5);
+    qemu::ObjectMethods::set_property_qobject(&child, cstr!("dma-offset"), QObject::Int(-3))
+        .unwrap();
+    assert_eq!(child.conf.dma.offset, -3);
+    qemu::ObjectMethods::set_property_qobject(&child, cstr!("ring-size"), QObject::Uint(1024))
+        .unwrap();
+    qemu::ObjectMethods::set_property_qobject(&child, cstr!("ring-packed"), QObject::Bool(true))
+        .unwrap();
+    assert_eq!(child.conf.ring.size, 1024);
+    assert!(child.conf.ring.packed);
+
+    // Unknown file descriptor names are rejected
+    assert!(qemu::ObjectMethods::set_property_qobject(
+        &child,
+        cstr!("fd"),
+        QObject::Str("nosuchfd".to_string())
+    )
+    .is_err());
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&child, cstr!("fd")).unwrap(),
+        QObject::Int(-1)
+    );
+
+    // Set several properties from a typed configuration
+    let configured = TestDevice::new();
+    let config = TestDeviceConfig {
+        foo: Some(false),
+        queues: Some(2),
+        ring_size: Some(512),
+        ..Default::default()
+    };
+    config.apply_to(&*configured).unwrap();
+    assert!(!configured.conf.foo);
+    assert_eq!(configured.conf.queues.get(), 2);
+    assert_eq!(configured.conf.ring.size, 512);
+    assert_eq!(configured.conf.buf_size, 0);
+    drop(configured);
+
+    // A failed allocation is reported by realize
+    let huge = TestDevice::new();
+    qemu::ObjectMethods::set_property_qobject(&huge, cstr!("buf-size"), QObject::Uint(u64::MAX))
+        .unwrap();
+    assert!(huge.realize().is_err());
+    assert!(!huge.is_realized());
+    assert!(huge.state.borrow().buffer.get().is_none());
+
+    // Build a two-level tree, then one that fails to realize
+    let board = TestObject::new();
+    let mut tree = ObjectTree::new();
+    let intc = tree.add(&*board, cstr!("intc"), TestSysBus::new());
+    let leaf = tree.add(&*intc, cstr!("leaf"), TestDevice::new());
+    let nested = tree.add(&*leaf, cstr!("nested"), TestDevice::new());
+    tree.realize().unwrap();
+    assert!(intc.is_realized() && leaf.is_realized());
+    let names: Vec<String> = qemu::ObjectMethods::child_refs(&intc)
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(names, ["leaf"]);
+    assert!(qemu::ObjectMethods::canonical_path(&leaf)
+        .map_or(true, |path| path.ends_with("/intc/leaf")));
+
+    // Every device of the tree is reset once
+    qemu::ObjectMethods::reset_subtree(&board);
+    leaf.state.borrow().resets.assert_reset_once();
+    nested.state.borrow().resets.assert_reset_once();
+    drop(nested);
+    drop(leaf);
+
+    let mut broken = ObjectTree::new();
+    let ok = broken.add(&*board, cstr!("ok"), TestDevice::new());
+    let bad = broken.add(&*ok, cstr!("bad"), TestDevice::new());
+    qemu::ObjectMethods::set_property_qobject(&bad, cstr!("buf-size"), QObject::Uint(u64::MAX))
+        .unwrap();
+    assert!(broken.realize().is_err());
+    assert_eq!(qemu::ObjectMethods::child_refs(&ok).count(), 0);
+    assert_eq!(
+        qemu::ObjectMethods::children_of_type::<TestDevice>(&board).count(),
+        0
+    );
+
+    // A required link that was not set makes realize fail cleanly
+    let dma_user = TestDmaUser::new();
+    let missing_link = dma_user.realize().unwrap_err();
+    assert_eq!(missing_link.to_string(), "property 'dma' is required");
+    assert!(!dma_user.is_realized());
+
+    // The hooks run in the order documented in ObjectImpl
+    let mut lifecycle = ObjectTree::new();
+    let order = lifecycle.add(&*board, cstr!("order"), TestOrder::new());
+    lifecycle.realize().unwrap();
+    qemu::ObjectMethods::unparent(&order);
+    drop(order);
+    HOOK_LOG.with(|log| {
+        assert_eq!(
+            *log.borrow(),
+            [
+                "mem_init",
+                "instance_init",
+                "instance_post_init",
+                "apply_default_props",
+                "try_init",
+                "realize",
+                "pre_unparent",
+                "unrealize",
+                "unparent",
+                "drop conf",
+                "drop state",
+            ]
+        );
+    });
+
+    // Initialization runs from the superclass down, finalization from
+    // the subclass up
+    HOOK_LOG.with(|log| log.borrow_mut().clear());
+    let order_child = TestOrderChild::new();
+    let order_parent = qemu::ObjectCast::upcast::<TestOrderParent>(&*order_child);
+    assert_eq!(
+        qemu::ObjectMethods::typename(&order_parent),
+        "test-order-child"
+    );
+    drop(order_child);
+    HOOK_LOG.with(|log| {
+        assert_eq!(
+            *log.borrow(),
+            [
+                "parent mem_init",
+                "child mem_init",
+                "parent instance_init",
+                "child instance_init",
+                "parent instance_post_init",
+                "child instance_post_init",
+                "drop child state",
+                "drop parent state",
+            ]
+        );
+    });
+
+    let ram = MemoryRegion::ram(&*d, cstr!("test-ram"), 4096).unwrap();
+    // One reference for the child property of d, one for the caller
+    assert_eq!(qemu::ObjectCast::upcast::<Object>(&*ram).r#ref, 2);
+    ram.write(4092, &[1, 2, 3, 4]);
+    let mut buf = [0u8; 4];
+    ram.read(4092, &mut buf);
+    assert_eq!(buf, [1, 2, 3, 4]);
+
+    // Copy a descriptor out of guest memory into host scratch memory
+    let mut desc = DmaBuf::new(4096);
+    assert_eq!(desc.as_mut_ptr() as usize % 4096, 0);
+    ram.read(4092, &mut desc[..4]);
+    assert_eq!(desc[..4], [1, 2, 3, 4]);
+    drop(desc);
+    let mut empty = DmaBuf::new(0);
+    assert!(empty.is_empty());
+    assert_eq!(&mut *empty, &mut [] as &mut [u8]);
+
+    // A window whose second half exposes the last 2 KiB of test-ram
+    let window = MemoryRegion::container(&*d, cstr!("test-window"), 8192);
+    let low = MemoryRegion::ram(&*d, cstr!("test-window-low"), 4096).unwrap();
+    let high = MemoryRegion::alias(&*d, cstr!("test-window-high"), &ram, 2048, 2048);
+    assert_eq!(high.size(), 2048);
+    window.add_subregion(0, &low);
+    window.add_subregion(4096, &high);
+
+    // Map the window in system memory, and check that accesses are
+    // dispatched to the subregion that covers them
+    const TEST_WINDOW_BASE: u64 = 0xfee0_0000_0000;
+    // SAFETY: system memory lives as long as QEMU
+    let system: &MemoryRegion = unsafe { &*qemu::bindings::get_system_memory() };
+    system.add_subregion(TEST_WINDOW_BASE, &window);
+    let window_rw = |offset: u64, data: &mut [u8], is_write: bool| {
+        // SAFETY: data is valid for data.len() bytes
+        let result = unsafe {
+            qemu::bindings::address_space_rw(
+                ptr::addr_of_mut!(qemu::bindings::address_space_memory),
+                TEST_WINDOW_BASE + offset,
+                qemu::bindings::MEMTXATTRS_UNSPECIFIED,
+                data.as_mut_ptr().cast(),
+                data.len() as u64,
+                is_write,
+            )
+        };
+        assert_eq!(result, qemu::bindings::MEMTX_OK);
+    };
+    window_rw(0, &mut [9, 10, 11, 12], true);
+    low.read(0, &mut buf);
+    assert_eq!(buf, [9, 10, 11, 12]);
+    window_rw(4096 + 2044, &mut buf, false);
+    assert_eq!(buf, [1, 2, 3, 4]);
+    window_rw(4096, &mut [5, 6, 7, 8], true);
+    ram.read(2048, &mut buf);
+    assert_eq!(buf, [5, 6, 7, 8]);
+    system.del_subregion(&window);
+    window.del_subregion(&low);
+    drop(ram);
     d.cold_reset();
+    d.state.borrow().resets.assert_reset_once();
     d.unparent();
+    assert_eq!(d.state.borrow().children_before_unparent, Some(1));
+    assert_eq!(d.state.borrow().realized_after_unparent, Some(false));
+
+    let v = TestVirtio::new();
+    v.realize().unwrap();
+    assert!(!v.device_class().hotpluggable);
+    assert_eq!(v.host_features(), 1 << TEST_VIRTIO_F_FOO);
+    assert!(!v.has_guest_feature(64));
+    assert_eq!(qemu::ObjectMethods::typename(&v), "test-virtio");
+    let err = Error::with_class(ErrorClass::DeviceNotFound, "Device 'foo' not found");
+    let err: Error = err.clone_to_foreign().into_native();
+    assert_eq!(err.error_class(), ErrorClass::DeviceNotFound);
+
+    let dev = unsafe { qemu::ObjectCast::checked_cast::<DeviceState>(&*v) };
+    dev.cold_reset();
+    // SAFETY: v is alive across the call
+    unsafe {
+        qemu::bindings::device_cold_reset(v.as_device_ptr());
+    }
+    let handed_over = Owned::into_device_ptr(TestDevice::new());
+    // SAFETY: into_device_ptr passed the only reference to C
+    unsafe {
+        qemu::bindings::object_unref(handed_over.cast());
+    }
+
+    // The subsection is not sent until the first pulse, so that older
+    // versions can load the state of a device that does not use pulses
+    let s = TestSysBus::new();
+    // The MMIO region is created once, by instance_init
+    let regs = qemu::ObjectMethods::child_refs(&s)
+        .filter(|(name, _)| name.starts_with("regs"))
+        .count();
+    assert_eq!(regs, 1);
+    let vmsd = TestSysBus::vmstate();
+    assert!(ptr::eq(s.device_class().vmsd, vmsd));
+    // SAFETY: the description is static, and s is alive across the calls
+    unsafe {
+        assert_eq!(CStr::from_ptr((*vmsd).name), cstr!("test-sysbus"));
+        assert_eq!(((*vmsd).version_id, (*vmsd).minimum_version_id), (2, 1));
+        let fields = (*vmsd).fields;
+        assert_eq!(CStr::from_ptr((*fields).name), cstr!("reads"));
+        assert_eq!((*fields.add(1)).size, 8);
+        assert!((*fields.add(2)).name.is_null());
+        let pulses = *(*vmsd).subsections;
+        assert!(ptr::eq(pulses, TestSysBus::vmstate_pulses()));
+        assert!((*(*vmsd).subsections.add(1)).is_null());
+
+        assert!(!qemu::bindings::vmstate_section_needed(
+            pulses,
+            ptr::addr_of!(*s).cast_mut().cast()
+        ));
+    }
+    assert!(s.device_class().realize.is_some());
+    assert_eq!(buffer_size(&*s), buffer_size(&*TestDevice::new()));
+    s.cold_reset();
+    s.realize().unwrap();
+    assert_eq!(s.state.irqs.len(), TEST_INTC_LINES);
+    s.write(TEST_INTC_PULSE, 3, 4);
+    assert_eq!(s.gpio_line_count(), TEST_INTC_LINES);
+    assert_eq!(
+        qemu::ObjectMethods::get_property_qobject(&s, cstr!("pulses")).unwrap(),
+        QObject::Uint(1)
+    );
+    // SAFETY: the description is static, and s is alive across the calls
+    unsafe {
+        let pulses = TestSysBus::vmstate_pulses();
+        let sysbus_ptr: *mut c_void = ptr::addr_of!(*s).cast_mut().cast();
+        assert!(qemu::bindings::vmstate_section_needed(pulses, sysbus_ptr));
+
+        // The field points at the Cell in the state
+        let offset = (*(*pulses).fields).offset;
+        assert!(ptr::eq(
+            sysbus_ptr.cast::<u8>().add(offset),
+            s.state.pulses.as_ptr().cast::<u8>()
+        ));
+    }
+    s.state.irqs.set(5, true);
+    s.cold_reset();
+    assert!((0..TEST_INTC_LINES).all(|i| !s.state.irqs.level(i)));
+
+    // Borrow the device without changing its reference count
+    let sysbus_refcnt = qemu::ObjectCast::upcast::<Object>(&*s).r#ref;
+    let summary = DeviceSummary::new(ObjectRef::upcast(ObjectRef::from(&s)));
+    assert_eq!(
+        summary.describe(),
+        format!(
+            "test-sysbus ({} GPIO outputs, realized: true)",
+            TEST_INTC_LINES
+        )
+    );
+    assert_eq!(qemu::ObjectCast::upcast::<Object>(&*s).r#ref, sysbus_refcnt);
+
+    s.set_priority(2, 9).unwrap();
+    assert!(s.set_priority(3, 16).is_err());
+    assert_eq!(extract32(s.state.regs.read(1), 8, 4), 9);
+    assert_eq!(extract32(s.state.regs.read(1), 12, 4), 0);
+
+    // Wire a timer's output to input 0 of the interrupt controller
+    let timer = TestSysBus::new();
+    timer.connect_gpio_out(0, &DeviceMethods::gpio_in(&s, 0));
+    timer.state.irqs.set(0, true);
+    assert!(s.state.irqs.level(0));
+    timer.state.irqs.set(0, false);
+    assert!(!s.state.irqs.level(0));
+    drop(timer);
+
+    // Wire an output of the interrupt controller to a device's input
+    let sink = TestDevice::new();
+    s.connect_gpio_out(2, &DeviceMethods::gpio_in(&sink, 1));
+    s.state.irqs.set(2, true);
+    assert_eq!(sink.state.borrow().inputs, 2);
+    s.state.irqs.set(2, false);
+    assert_eq!(sink.state.borrow().inputs, 0);
+
+    // Round trip QMP-style arguments through the C representation
+    let args = QObject::Dict(BTreeMap::from([
+        ("id".to_string(), QObject::Str("dev0".to_string())),
+        ("offset".to_string(), QObject::Int(-4096)),
+        ("size".to_string(), QObject::Uint(u64::MAX)),
+        ("ratio".to_string(), QObject::Double(0.5)),
+        ("verbose".to_string(), QObject::Bool(true)),
+        (
+            "queues".to_string(),
+            QObject::List(vec![QObject::Uint(1), QObject::Null]),
+        ),
+    ]));
+    match args.clone_to_foreign().into_native() {
+        QObject::Dict(parsed) => {
+            assert!(matches!(parsed.get("id"), Some(QObject::Str(id)) if id == "dev0"));
+            assert!(matches!(parsed.get("offset"), Some(QObject::Int(-4096))));
+            assert!(matches!(parsed.get("size"), Some(QObject::Uint(u64::MAX))));
+            assert!(matches!(parsed.get("ratio"), Some(QObject::Double(r)) if *r == 0.5));
+            assert!(matches!(parsed.get("verbose"), Some(QObject::Bool(true))));
+            match parsed.get("queues") {
+                Some(QObject::List(list)) => {
+                    assert_eq!(list, &[QObject::Uint(1), QObject::Null]);
+                }
+                other => panic!("unexpected queues {:?}", other),
+            }
+        }
+        other => panic!("unexpected arguments {:?}", other),
+    }
+
+    // Go through the dispatcher, which converts the QDict of arguments
+    // and the reply
+    qmp::register_command(cstr!("query-test-device"), query_test_device);
+    let cmd = unsafe {
+        qemu::bindings::qmp_find_command(
+            ptr::addr_of!(qemu::bindings::qmp_commands),
+            cstr!("query-test-device").as_ptr(),
+        )
+    };
+    assert!(!cmd.is_null());
+    let dispatch = |cmd_args: BTreeMap<String, QObject>| -> Result<QObject> {
+        let c_args = QObject::Dict(cmd_args).clone_to_foreign();
+        let mut reply = ptr::null_mut();
+        let mut c_err = ptr::null_mut();
+        // SAFETY: the command was found above and the arguments are a QDict
+        unsafe {
+            ((*cmd).fn_)(c_args.as_ptr().cast_mut().cast(), &mut reply, &mut c_err);
+            Error::err_or_else(c_err, || {
+                <QObject as qemu::FromForeign>::from_foreign(reply)
+            })
+        }
+    };
+    assert_eq!(
+        dispatch(BTreeMap::new()).unwrap(),
+        QObject::Dict(BTreeMap::from([("status".to_string(), QObject::Uint(42))]))
+    );
+    let verbose = BTreeMap::from([("verbose".to_string(), QObject::Bool(true))]);
+    assert_eq!(
+        dispatch(verbose).unwrap(),
+        QObject::Dict(BTreeMap::from([
+            ("status".to_string(), QObject::Uint(42)),
+            ("version".to_string(), QObject::Str("1.2".to_string())),
+        ]))
+    );
+    let bad_args = BTreeMap::from([("verbose".to_string(), QObject::Int(1))]);
+    assert_eq!(
+        dispatch(bad_args).unwrap_err().to_string(),
+        "'verbose' must be a boolean"
+    );
 }